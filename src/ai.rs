@@ -4,6 +4,7 @@ use tracing::{error, info};
 use thiserror::Error;
 
 use crate::{Sessions, models::*};
+use crate::redact::{self, Redaction, Redactor};
 
 #[derive(Error, Debug)]
 pub enum AIError {
@@ -15,16 +16,30 @@ pub enum AIError {
     UnsupportedProvider(String),
 }
 
+/// 提示词和前端附带的终端上下文 (在 system_prompt 中) 离开本机前先遮盖密钥
+pub fn redact_request(request: &mut AIRequest, redactor: &Redactor) -> Vec<Redaction> {
+    let mut report = Default::default();
+    request.message = redactor.redact(&request.message, &mut report);
+    request.ai_config.system_prompt = request.ai_config.system_prompt
+        .take()
+        .map(|prompt| redactor.redact(&prompt, &mut report));
+    let redactions = redact::summarize(report);
+    if !redactions.is_empty() {
+        info!("已遮盖发送给AI的敏感内容: {:?}", redactions);
+    }
+    redactions
+}
+
+/// 请求需已经过 `redact_request`
 pub async fn process_ai_request(
     request: AIRequest,
     sessions: Sessions,
 ) -> Result<AIResponse, AIError> {
-    let provider = request.ai_config.provider.as_deref().unwrap_or("openai");
-    
-    match provider {
+    let provider = request.ai_config.provider.clone().unwrap_or_else(|| "openai".to_string());
+    match provider.as_str() {
         "openai" => process_openai_request(request, sessions).await,
         "claude" => process_claude_request(request, sessions).await,
-        _ => Err(AIError::UnsupportedProvider(provider.to_string())),
+        _ => Err(AIError::UnsupportedProvider(provider)),
     }
 }
 
@@ -97,6 +112,10 @@ async fn process_openai_request(
     Ok(AIResponse {
         response: ai_response,
         command,
+        auto_execute: false,
+        policy: None,
+        risk: None,
+        redactions: Vec::new(),
     })
 }
 
@@ -164,6 +183,10 @@ async fn process_claude_request(
     Ok(AIResponse {
         response: ai_response,
         command,
+        auto_execute: false,
+        policy: None,
+        risk: None,
+        redactions: Vec::new(),
     })
 }
 
@@ -0,0 +1,301 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use parking_lot::Mutex;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use thiserror::Error;
+use tracing::error;
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::Reply;
+
+use crate::AppState;
+use crate::audit::{self, AuditEvent};
+use crate::auth::Identity;
+use crate::config::{ApiTokenConfig, UserRole};
+
+/// 令牌明文的前缀，便于在日志和密钥扫描中识别
+const TOKEN_PREFIX: &str = "sat_";
+/// last_used 最多每隔这么久写一次文件
+const LAST_USED_FLUSH: i64 = 60;
+
+/// 令牌可访问的接口
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TokenScope {
+    /// 通过 `/ws` 打开终端并输入
+    Exec,
+    /// `/api/ai/chat`
+    Ai,
+    /// `/api/sessions`
+    ReadSessions,
+    /// `/metrics`，仅 admin 的令牌有效
+    Metrics,
+}
+
+#[derive(Error, Debug)]
+pub enum TokenError {
+    #[error("未启用登录，无需API令牌")]
+    LoginDisabled,
+    #[error("API令牌不存在")]
+    NotFound,
+    #[error("至少需要一个权限范围")]
+    NoScopes,
+    #[error("有效期不能超过 {0} 秒")]
+    TtlTooLong(u64),
+    #[error("保存API令牌失败: {0}")]
+    Store(#[from] std::io::Error),
+}
+
+/// 令牌信息，不含明文和哈希
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub id: Uuid,
+    pub user: String,
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    #[serde(flatten)]
+    info: TokenInfo,
+    /// 创建时的角色和组，单点登录账户使用令牌时以此为准
+    role: UserRole,
+    groups: Vec<String>,
+}
+
+/// 用户自行签发的 API 令牌，只保存 SHA-256，写入 `auth.api_tokens.store`
+pub struct ApiTokenStore {
+    path: PathBuf,
+    max_ttl: u64,
+    /// 以令牌哈希为键
+    records: Mutex<HashMap<String, Record>>,
+}
+
+impl ApiTokenStore {
+    pub fn open(config: &ApiTokenConfig) -> anyhow::Result<Self> {
+        let path = PathBuf::from(&config.store);
+        let records = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("无法解析API令牌 {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(anyhow::anyhow!("无法读取API令牌 {}: {}", path.display(), e)),
+        };
+        Ok(ApiTokenStore {
+            path,
+            max_ttl: config.max_ttl,
+            records: Mutex::new(records),
+        })
+    }
+
+    /// 签发新令牌，返回只显示一次的明文
+    pub fn create(
+        &self,
+        identity: &Identity,
+        name: &str,
+        scopes: Vec<TokenScope>,
+        ttl: Option<u64>,
+    ) -> Result<(String, TokenInfo), TokenError> {
+        if scopes.is_empty() {
+            return Err(TokenError::NoScopes);
+        }
+        let ttl = match (ttl, self.max_ttl) {
+            (Some(ttl), max) if max > 0 && ttl > max => return Err(TokenError::TtlTooLong(max)),
+            (None, max) if max > 0 => Some(max),
+            (ttl, _) => ttl,
+        };
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = format!("{}{}", TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(bytes));
+        let now = Utc::now();
+        let info = TokenInfo {
+            id: Uuid::new_v4(),
+            user: identity.user.clone(),
+            name: name.to_string(),
+            scopes,
+            created_at: now,
+            expires_at: ttl.map(|ttl| now + chrono::Duration::seconds(ttl.min(i64::MAX as u64) as i64)),
+            last_used: None,
+        };
+
+        let mut records = self.records.lock();
+        records.insert(hash_token(&token), Record {
+            info: info.clone(),
+            role: identity.role,
+            groups: identity.groups.clone(),
+        });
+        self.save(&records)?;
+        audit::record(Some(&identity.user), None, AuditEvent::Account {
+            action: "api_token_created".to_string(),
+            detail: Some(format!("token {} scopes {:?}", info.id, info.scopes)),
+        });
+        Ok((token, info))
+    }
+
+    /// 校验令牌并记录使用时间，返回令牌信息和创建时的角色；过期的令牌一并清除
+    pub fn authenticate(&self, token: &str) -> Option<(TokenInfo, UserRole, Vec<String>)> {
+        if !token.starts_with(TOKEN_PREFIX) {
+            return None;
+        }
+        let hash = hash_token(token);
+        let now = Utc::now();
+        let mut records = self.records.lock();
+        let record = records.get_mut(&hash)?;
+        if record.info.expires_at.is_some_and(|expires| expires <= now) {
+            records.remove(&hash);
+            let _ = self.save(&records);
+            return None;
+        }
+        let flush = record.info.last_used.is_none_or(|used| (now - used).num_seconds() >= LAST_USED_FLUSH);
+        record.info.last_used = Some(now);
+        let result = (record.info.clone(), record.role, record.groups.clone());
+        if flush {
+            if let Err(e) = self.save(&records) {
+                error!("保存API令牌失败: {}", e);
+            }
+        }
+        Some(result)
+    }
+
+    pub fn list(&self, user: &str) -> Vec<TokenInfo> {
+        let mut tokens: Vec<_> = self
+            .records
+            .lock()
+            .values()
+            .filter(|r| r.info.user == user)
+            .map(|r| r.info.clone())
+            .collect();
+        tokens.sort_by_key(|t| t.created_at);
+        tokens
+    }
+
+    /// 吊销令牌，admin 可以吊销任何人的令牌
+    pub fn revoke(&self, identity: &Identity, id: Uuid) -> Result<(), TokenError> {
+        let mut records = self.records.lock();
+        let hash = records
+            .iter()
+            .find(|(_, r)| r.info.id == id && (r.info.user == identity.user || identity.role == UserRole::Admin))
+            .map(|(hash, _)| hash.clone())
+            .ok_or(TokenError::NotFound)?;
+        records.remove(&hash);
+        self.save(&records)?;
+        audit::record(Some(&identity.user), None, AuditEvent::Account {
+            action: "api_token_revoked".to_string(),
+            detail: Some(format!("token {}", id)),
+        });
+        Ok(())
+    }
+
+    fn save(&self, records: &HashMap<String, Record>) -> std::io::Result<()> {
+        let content = serde_json::to_vec_pretty(records)?;
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, &self.path)
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(token.as_bytes()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    name: String,
+    scopes: Vec<TokenScope>,
+    /// 有效期 (秒)，不设置时使用 `auth.api_tokens.max_ttl`
+    expires_in: Option<u64>,
+}
+
+pub async fn handle_create(
+    identity: Option<Identity>,
+    request: CreateTokenRequest,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(identity) = identity else {
+        return Ok(error_reply(&TokenError::LoginDisabled));
+    };
+    Ok(match state.auth.tokens().create(&identity, &request.name, request.scopes, request.expires_in) {
+        Ok((token, info)) => {
+            warp::reply::json(&serde_json::json!({ "token": token, "info": info })).into_response()
+        }
+        Err(e) => error_reply(&e),
+    })
+}
+
+pub async fn handle_list(identity: Option<Identity>, state: AppState) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(identity) = identity else {
+        return Ok(error_reply(&TokenError::LoginDisabled));
+    };
+    Ok(warp::reply::json(&state.auth.tokens().list(&identity.user)).into_response())
+}
+
+pub async fn handle_revoke(
+    id: Uuid,
+    identity: Option<Identity>,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(identity) = identity else {
+        return Ok(error_reply(&TokenError::LoginDisabled));
+    };
+    Ok(match state.auth.tokens().revoke(&identity, id) {
+        Ok(()) => warp::reply::json(&serde_json::json!({ "ok": true })).into_response(),
+        Err(e) => error_reply(&e),
+    })
+}
+
+fn error_reply(error: &TokenError) -> warp::reply::Response {
+    let status = match error {
+        TokenError::LoginDisabled | TokenError::NotFound => StatusCode::NOT_FOUND,
+        TokenError::NoScopes | TokenError::TtlTooLong(_) => StatusCode::BAD_REQUEST,
+        TokenError::Store(e) => {
+            error!("保存API令牌失败: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    let body = warp::reply::json(&serde_json::json!({ "error": error.to_string() }));
+    warp::reply::with_status(body, status).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scoped_expiring_revocable() {
+        let path = std::env::temp_dir().join(format!("tokens-{}.json", Uuid::new_v4()));
+        let store = ApiTokenStore::open(&ApiTokenConfig {
+            store: path.to_string_lossy().into_owned(),
+            max_ttl: 3600,
+        })
+        .unwrap();
+        let alice = Identity { user: "alice".to_string(), role: UserRole::Operator, groups: vec!["ops".to_string()] };
+
+        assert!(matches!(store.create(&alice, "ci", vec![TokenScope::Exec], Some(7200)), Err(TokenError::TtlTooLong(3600))));
+        let (token, info) = store.create(&alice, "ci", vec![TokenScope::Exec], None).unwrap();
+        assert!(info.expires_at.is_some());
+        assert!(!std::fs::read_to_string(&path).unwrap().contains(&token));
+
+        let (used, role, groups) = store.authenticate(&token).unwrap();
+        assert!(used.last_used.is_some());
+        assert_eq!((role, groups), (UserRole::Operator, vec!["ops".to_string()]));
+        assert!(store.authenticate("sat_unknown").is_none());
+
+        let bob = Identity { user: "bob".to_string(), role: UserRole::Operator, groups: Vec::new() };
+        assert!(matches!(store.revoke(&bob, info.id), Err(TokenError::NotFound)));
+        store.revoke(&alice, info.id).unwrap();
+        assert!(store.authenticate(&token).is_none());
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,429 @@
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::audit::{self, AuditEvent};
+use crate::auth::Identity;
+use crate::config::{ApprovalConfig, AuthConfig, PolicyAction};
+use crate::models::WebSocketResponse;
+use crate::policy::Verdict;
+use crate::session::SharedSession;
+use crate::{rbac, risk, Sessions};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalOutcome {
+    Approved,
+    Rejected,
+    Expired,
+    /// 申请人撤回，或批准时会话已经结束、申请人已不能输入
+    Cancelled,
+}
+
+#[derive(Error, Debug)]
+pub enum ApprovalError {
+    #[error("审批不存在或已经结束")]
+    NotFound,
+    #[error("不能批准自己提交的命令")]
+    SelfApproval,
+    #[error("当前账户无权审批该命令")]
+    NotEligible,
+    #[error("会话已经结束，命令未执行")]
+    SessionEnded,
+    #[error("申请人已不能在该会话中输入，命令未执行")]
+    RequesterReadOnly,
+    #[error("写入终端失败: {0}")]
+    Write(String),
+}
+
+/// 从输入中暂扣、等待审批的命令
+pub struct Held {
+    pub command: String,
+    /// 批准后写入终端的数据
+    pub release: String,
+    /// 需要审批的原因
+    pub message: String,
+}
+
+struct Request {
+    session_id: Uuid,
+    requester: String,
+    /// 申请人的 WebSocket 连接，批准时据此确认其仍可写入会话
+    requester_client: Uuid,
+    requester_tx: mpsc::Sender<String>,
+    host: Option<String>,
+    held: Held,
+    expires_at: DateTime<Utc>,
+    /// 收到通知的审批人连接
+    notified: Vec<Uuid>,
+}
+
+struct Approver {
+    identity: Identity,
+    tx: mpsc::Sender<String>,
+}
+
+/// 等待审批的命令和在线的审批人 (`policy.approval`)
+pub struct Approvals {
+    config: ApprovalConfig,
+    requests: DashMap<Uuid, Request>,
+    /// 以 WebSocket 连接 ID 为键
+    approvers: DashMap<Uuid, Approver>,
+}
+
+impl Approvals {
+    pub fn new(config: ApprovalConfig) -> Self {
+        Approvals {
+            config,
+            requests: DashMap::new(),
+            approvers: DashMap::new(),
+        }
+    }
+
+    /// 风险评估达到 `risk_level` 时把策略判定提升为 approve
+    pub fn escalate(&self, verdict: Verdict, command: &str) -> Verdict {
+        let Some(threshold) = self.config.risk_level else {
+            return verdict;
+        };
+        if verdict.action >= PolicyAction::Approve {
+            return verdict;
+        }
+        let assessment = risk::assess(command);
+        if assessment.level < threshold {
+            return verdict;
+        }
+        let reasons: Vec<_> = assessment
+            .reasons
+            .iter()
+            .filter(|r| r.level >= threshold)
+            .map(|r| r.reason.as_str())
+            .collect();
+        Verdict {
+            action: PolicyAction::Approve,
+            rule: None,
+            message: format!("风险等级 {} 的命令需要审批: {}", assessment.level, reasons.join("; ")),
+        }
+    }
+
+    fn eligible(&self, auth: &AuthConfig, identity: &Identity, request: &Request) -> bool {
+        identity.user != request.requester
+            && self.config.roles.contains(&identity.role)
+            && rbac::check_connect(auth, Some(identity), request.host.as_deref()).is_ok()
+    }
+
+    /// 登记在线的审批人，并补发其有权处理的待审批命令
+    pub fn register_approver(&self, auth: &AuthConfig, client_id: Uuid, identity: &Identity, tx: mpsc::Sender<String>) {
+        if !self.config.roles.contains(&identity.role) {
+            return;
+        }
+        for mut request in self.requests.iter_mut() {
+            if self.eligible(auth, identity, &request) {
+                let _ = tx.try_send(request_message(*request.key(), &request));
+                request.notified.push(client_id);
+            }
+        }
+        self.approvers.insert(client_id, Approver { identity: identity.clone(), tx });
+    }
+
+    pub fn remove_approver(&self, client_id: Uuid) {
+        self.approvers.remove(&client_id);
+    }
+
+    /// 暂扣命令并通知审批人，返回回复给申请人的 approval_pending
+    pub fn submit(
+        self: &Arc<Self>,
+        auth: &AuthConfig,
+        requester: &Identity,
+        requester_client: Uuid,
+        requester_tx: mpsc::Sender<String>,
+        session: &SharedSession,
+        held: Held,
+    ) -> WebSocketResponse {
+        let approval_id = Uuid::new_v4();
+        let timeout = Duration::from_secs(self.config.timeout);
+        let mut request = Request {
+            session_id: session.id,
+            requester: requester.user.clone(),
+            requester_client,
+            requester_tx,
+            host: session.host.clone(),
+            held,
+            expires_at: Utc::now() + chrono::Duration::seconds(self.config.timeout.min(i64::MAX as u64) as i64),
+            notified: Vec::new(),
+        };
+        let message = request_message(approval_id, &request);
+        for approver in self.approvers.iter() {
+            if self.eligible(auth, &approver.identity, &request) {
+                let _ = approver.tx.try_send(message.clone());
+                request.notified.push(*approver.key());
+            }
+        }
+        audit::record(Some(&request.requester), request.host.as_deref(), AuditEvent::ApprovalRequested {
+            session_id: session.id,
+            approval_id,
+            command: request.held.command.clone(),
+            reason: request.held.message.clone(),
+        });
+        info!("Command on session {} awaits approval {} ({} approvers notified)", session.id, approval_id, request.notified.len());
+
+        let pending = WebSocketResponse::ApprovalPending {
+            session_id: session.id,
+            approval_id,
+            command: request.held.command.clone(),
+            message: request.held.message.clone(),
+            approvers: request.notified.len(),
+            expires_at: request.expires_at,
+        };
+        self.requests.insert(approval_id, request);
+
+        let approvals = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            if let Some((_, request)) = approvals.requests.remove(&approval_id) {
+                approvals.resolve(approval_id, request, ApprovalOutcome::Expired, None);
+            }
+        });
+        pending
+    }
+
+    /// 处理 approve_command：审批人批准或拒绝，申请人只能撤回
+    pub async fn decide(
+        &self,
+        auth: &AuthConfig,
+        sessions: &Sessions,
+        identity: &Identity,
+        approval_id: Uuid,
+        approve: bool,
+    ) -> Result<ApprovalOutcome, ApprovalError> {
+        {
+            let request = self.requests.get(&approval_id).ok_or(ApprovalError::NotFound)?;
+            if request.requester == identity.user {
+                if approve {
+                    return Err(ApprovalError::SelfApproval);
+                }
+            } else if !self.eligible(auth, identity, &request) {
+                return Err(ApprovalError::NotEligible);
+            }
+        }
+        // 并发的两个决定只有一个能取走申请
+        let (_, request) = self.requests.remove(&approval_id).ok_or(ApprovalError::NotFound)?;
+        let approver = Some(identity.user.as_str());
+
+        if request.requester == identity.user {
+            self.resolve(approval_id, request, ApprovalOutcome::Cancelled, approver);
+            return Ok(ApprovalOutcome::Cancelled);
+        }
+        if !approve {
+            self.resolve(approval_id, request, ApprovalOutcome::Rejected, approver);
+            return Ok(ApprovalOutcome::Rejected);
+        }
+        let session = sessions.get(&request.session_id).map(|s| s.clone());
+        let Some(session) = session else {
+            self.resolve(approval_id, request, ApprovalOutcome::Cancelled, approver);
+            return Err(ApprovalError::SessionEnded);
+        };
+        // 审批期间申请人可能已被降为只读或离开会话
+        if !session.role_of(request.requester_client).is_some_and(|role| role.can_write()) {
+            self.resolve(approval_id, request, ApprovalOutcome::Cancelled, approver);
+            return Err(ApprovalError::RequesterReadOnly);
+        }
+        let result = session.backend.write(&request.held.release).await;
+        self.resolve(approval_id, request, ApprovalOutcome::Approved, approver);
+        result.map(|_| ApprovalOutcome::Approved).map_err(|e| ApprovalError::Write(e.to_string()))
+    }
+
+    /// 通知申请人和收到过申请的审批人，并写入审计日志
+    fn resolve(&self, approval_id: Uuid, request: Request, outcome: ApprovalOutcome, approver: Option<&str>) {
+        let resolved = serde_json::to_string(&WebSocketResponse::ApprovalResolved {
+            approval_id,
+            session_id: request.session_id,
+            command: request.held.command.clone(),
+            outcome,
+            approver: approver.map(str::to_string),
+        })
+        .unwrap();
+        let _ = request.requester_tx.try_send(resolved.clone());
+        for client_id in &request.notified {
+            if let Some(approver) = self.approvers.get(client_id) {
+                let _ = approver.tx.try_send(resolved.clone());
+            }
+        }
+        audit::record(Some(approver.unwrap_or(&request.requester)), request.host.as_deref(), AuditEvent::ApprovalResolved {
+            session_id: request.session_id,
+            approval_id,
+            command: request.held.command,
+            requester: request.requester.clone(),
+            outcome,
+        });
+    }
+}
+
+fn request_message(approval_id: Uuid, request: &Request) -> String {
+    serde_json::to_string(&WebSocketResponse::ApprovalRequest {
+        approval_id,
+        session_id: request.session_id,
+        requester: request.requester.clone(),
+        host: request.host.clone(),
+        command: request.held.command.clone(),
+        message: request.held.message.clone(),
+        expires_at: request.expires_at,
+    })
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserRole;
+    use crate::models::Role;
+    use crate::risk::RiskLevel;
+    use crate::terminal::{TerminalBackend, TerminalError};
+    use async_trait::async_trait;
+    use parking_lot::Mutex;
+
+    /// 记录写入数据的终端
+    struct Recorder(Uuid, Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl TerminalBackend for Recorder {
+        fn id(&self) -> Uuid {
+            self.0
+        }
+
+        fn kind(&self) -> &'static str {
+            "recorder"
+        }
+
+        async fn write(&self, data: &str) -> Result<(), TerminalError> {
+            self.1.lock().push(data.to_string());
+            Ok(())
+        }
+
+        async fn resize(&self, _cols: u16, _rows: u16) -> Result<(), TerminalError> {
+            Ok(())
+        }
+
+        async fn close(&self) {}
+    }
+
+    fn identity(user: &str, role: UserRole) -> Identity {
+        Identity { user: user.to_string(), role, groups: Vec::new() }
+    }
+
+    fn held() -> Held {
+        Held { command: "reboot".to_string(), release: "reboot\r".to_string(), message: "需要审批".to_string() }
+    }
+
+    /// 以 owner 身份打开一个记录终端，返回会话表、写入记录和 owner 的连接ID
+    fn open(owner: &Identity) -> (Sessions, Arc<Recorder>, Arc<SharedSession>, Uuid) {
+        let sessions = Sessions::default();
+        let backend = Arc::new(Recorder(Uuid::new_v4(), Mutex::new(Vec::new())));
+        let (tx, _) = mpsc::channel(10);
+        let client_id = Uuid::new_v4();
+        let session = SharedSession::register(backend.clone(), Some("web-1".to_string()), client_id, Some(owner.user.clone()), tx, &sessions);
+        (sessions, backend, session, client_id)
+    }
+
+    fn submit(
+        approvals: &Arc<Approvals>,
+        auth: &AuthConfig,
+        requester: &Identity,
+        client_id: Uuid,
+        session: &SharedSession,
+    ) -> (Uuid, mpsc::Receiver<String>) {
+        let (tx, rx) = mpsc::channel(10);
+        match approvals.submit(auth, requester, client_id, tx, session, held()) {
+            WebSocketResponse::ApprovalPending { approval_id, .. } => (approval_id, rx),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn risky_commands_escalate_to_approval() {
+        let allow = || Verdict { action: PolicyAction::Allow, rule: None, message: String::new() };
+        let disabled = Approvals::new(ApprovalConfig::default());
+        assert_eq!(disabled.escalate(allow(), "rm -rf /").action, PolicyAction::Allow);
+
+        let approvals = Approvals::new(ApprovalConfig { risk_level: Some(RiskLevel::High), ..Default::default() });
+        assert_eq!(approvals.escalate(allow(), "sudo apt update").action, PolicyAction::Allow);
+        let escalated = approvals.escalate(allow(), "sudo rm -rf /");
+        assert_eq!((escalated.action, escalated.rule), (PolicyAction::Approve, None));
+
+        let denied = Verdict { action: PolicyAction::Deny, rule: Some(0), message: "no".to_string() };
+        assert_eq!(approvals.escalate(denied, "rm -rf /").action, PolicyAction::Deny);
+    }
+
+    #[tokio::test]
+    async fn command_is_written_only_after_approval() {
+        let auth = AuthConfig::default();
+        let approvals = Arc::new(Approvals::new(ApprovalConfig::default()));
+        let requester = identity("alice", UserRole::Admin);
+        let (sessions, backend, session, client_id) = open(&requester);
+        let (approval_id, _rx) = submit(&approvals, &auth, &requester, client_id, &session);
+
+        // 申请人不能批准自己，无权角色也不能审批
+        let self_approval = approvals.decide(&auth, &sessions, &requester, approval_id, true).await;
+        assert!(matches!(self_approval, Err(ApprovalError::SelfApproval)));
+        let operator = identity("bob", UserRole::Operator);
+        let ineligible = approvals.decide(&auth, &sessions, &operator, approval_id, true).await;
+        assert!(matches!(ineligible, Err(ApprovalError::NotEligible)));
+        assert!(backend.1.lock().is_empty());
+
+        let admin = identity("carol", UserRole::Admin);
+        let outcome = approvals.decide(&auth, &sessions, &admin, approval_id, true).await.unwrap();
+        assert_eq!(outcome, ApprovalOutcome::Approved);
+        assert_eq!(*backend.1.lock(), vec!["reboot\r".to_string()]);
+        let again = approvals.decide(&auth, &sessions, &admin, approval_id, true).await;
+        assert!(matches!(again, Err(ApprovalError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn rejected_and_expired_requests_are_never_written() {
+        let auth = AuthConfig::default();
+        let approvals = Arc::new(Approvals::new(ApprovalConfig::default()));
+        let requester = identity("alice", UserRole::Admin);
+        let admin = identity("carol", UserRole::Admin);
+        let (sessions, backend, session, client_id) = open(&requester);
+
+        let (approval_id, _rx) = submit(&approvals, &auth, &requester, client_id, &session);
+        let outcome = approvals.decide(&auth, &sessions, &admin, approval_id, false).await.unwrap();
+        assert_eq!(outcome, ApprovalOutcome::Rejected);
+
+        let approvals = Arc::new(Approvals::new(ApprovalConfig { timeout: 0, ..Default::default() }));
+        let (approval_id, mut rx) = submit(&approvals, &auth, &requester, client_id, &session);
+        let resolved = tokio::time::timeout(Duration::from_secs(1), rx.recv()).await.unwrap().unwrap();
+        let resolved: serde_json::Value = serde_json::from_str(&resolved).unwrap();
+        assert_eq!(resolved["outcome"], "expired");
+        let late = approvals.decide(&auth, &sessions, &admin, approval_id, true).await;
+        assert!(matches!(late, Err(ApprovalError::NotFound)));
+        assert!(backend.1.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn requester_must_still_be_able_to_write() {
+        let auth = AuthConfig::default();
+        let approvals = Arc::new(Approvals::new(ApprovalConfig::default()));
+        let owner = identity("alice", UserRole::Admin);
+        let (sessions, backend, session, _) = open(&owner);
+        let requester = identity("dave", UserRole::Operator);
+        let writer = Uuid::new_v4();
+        let (tx, _writer_rx) = mpsc::channel(10);
+        session.join(writer, tx, Some("dave"), Role::Writer, None).await;
+        let (approval_id, _rx) = submit(&approvals, &auth, &requester, writer, &session);
+
+        // 审批期间被降为只读，批准后命令不写入
+        assert!(session.set_role(writer, Role::Viewer).await);
+        let admin = identity("carol", UserRole::Admin);
+        let outcome = approvals.decide(&auth, &sessions, &admin, approval_id, true).await;
+        assert!(matches!(outcome, Err(ApprovalError::RequesterReadOnly)));
+        assert!(backend.1.lock().is_empty());
+        let again = approvals.decide(&auth, &sessions, &admin, approval_id, true).await;
+        assert!(matches!(again, Err(ApprovalError::NotFound)));
+    }
+}
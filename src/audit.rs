@@ -0,0 +1,422 @@
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::{error, info};
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::Reply;
+
+use crate::AppState;
+use crate::approval::ApprovalOutcome;
+use crate::auth::Identity;
+use crate::config::{matches_pattern, AuditConfig, PolicyAction, UserRole};
+
+/// 链首条目的 prev
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// 单行输入缓冲的上限，超出部分丢弃
+const MAX_LINE: usize = 4096;
+
+static LOG: OnceLock<AuditLog> = OnceLock::new();
+
+/// 审计事件，`event` 字段区分类型
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    Login {
+        /// password、totp、recovery_code 或 sso
+        method: String,
+        source: Option<String>,
+    },
+    LoginFailed {
+        reason: String,
+        source: Option<String>,
+    },
+    Logout,
+    Connect {
+        session_id: Uuid,
+        /// ssh、telnet 或 local
+        kind: String,
+    },
+    ConnectDenied {
+        reason: String,
+    },
+    /// `/ws` 升级因 Origin 或 CSRF 令牌被拒绝
+    UpgradeRejected {
+        reason: String,
+        origin: Option<String>,
+        source: Option<String>,
+    },
+    Disconnect {
+        session_id: Uuid,
+        reason: String,
+    },
+    /// 用户在终端中提交的一行输入
+    Input {
+        session_id: Uuid,
+        line: String,
+    },
+    AutoExecute {
+        session_id: Uuid,
+        command: String,
+    },
+    AiPrompt {
+        session_id: Option<Uuid>,
+        prompt: String,
+    },
+    AiSuggestion {
+        session_id: Option<Uuid>,
+        command: Option<String>,
+        auto_execute: bool,
+    },
+    /// 命中命令策略的 deny 或 confirm 规则
+    PolicyViolation {
+        session_id: Option<Uuid>,
+        command: String,
+        action: PolicyAction,
+        rule: Option<usize>,
+    },
+    /// 用户对需要确认的命令做出的选择
+    CommandConfirmed {
+        session_id: Uuid,
+        command: String,
+        approved: bool,
+    },
+    /// 命令被暂扣等待审批
+    ApprovalRequested {
+        session_id: Uuid,
+        approval_id: Uuid,
+        command: String,
+        reason: String,
+    },
+    /// 审批结束，记录在审批人 (超时则为申请人) 名下
+    ApprovalResolved {
+        session_id: Uuid,
+        approval_id: Uuid,
+        command: String,
+        requester: String,
+        outcome: ApprovalOutcome,
+    },
+    ShareLink {
+        session_id: Uuid,
+        link_id: Uuid,
+        /// created、revoked 或 joined
+        action: String,
+    },
+    /// 两步验证、API令牌等账户安全相关的事件
+    Account {
+        action: String,
+        detail: Option<String>,
+    },
+}
+
+/// 日志中的一行；hash 为 `SHA-256(prev + "\n" + 不含 hash 的 JSON)`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub time: DateTime<Utc>,
+    pub user: Option<String>,
+    /// 目标主机，本地终端和与主机无关的事件为空
+    pub host: Option<String>,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    pub prev: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> String {
+        let mut unsigned = self.clone();
+        unsigned.hash.clear();
+        let body = serde_json::to_string(&unsigned).expect("audit entry serializes");
+        let mut hasher = Sha256::new();
+        hasher.update(self.prev.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(body.as_bytes());
+        HEXLOWER.encode(&hasher.finalize())
+    }
+}
+
+struct Writer {
+    file: File,
+    seq: u64,
+    last_hash: String,
+}
+
+/// 只追加的哈希链审计日志 (`audit.path`)，每个条目记录上一条的哈希，
+/// 删除或修改中间任意一行都能被 `verify` 发现
+pub struct AuditLog {
+    path: PathBuf,
+    writer: Mutex<Writer>,
+}
+
+impl AuditLog {
+    /// 打开日志并接上已有的链尾
+    pub fn open(config: &AuditConfig) -> anyhow::Result<Self> {
+        let path = PathBuf::from(&config.path);
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let (seq, last_hash) = match read_entries(&path) {
+            Ok(entries) => entries
+                .filter_map(Result::ok)
+                .last()
+                .map_or((0, GENESIS.to_string()), |e| (e.seq, e.hash)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (0, GENESIS.to_string()),
+            Err(e) => return Err(anyhow::anyhow!("无法读取审计日志 {}: {}", path.display(), e)),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(&path)
+            .map_err(|e| anyhow::anyhow!("无法打开审计日志 {}: {}", path.display(), e))?;
+        info!("审计日志 {} 共 {} 条，链尾哈希 {}", path.display(), seq, last_hash);
+        Ok(AuditLog { path, writer: Mutex::new(Writer { file, seq, last_hash }) })
+    }
+
+    fn append(&self, user: Option<&str>, host: Option<&str>, event: AuditEvent) -> std::io::Result<()> {
+        let mut writer = self.writer.lock();
+        let mut entry = AuditEntry {
+            seq: writer.seq + 1,
+            time: Utc::now(),
+            user: user.map(str::to_string),
+            host: host.map(str::to_string),
+            event,
+            prev: writer.last_hash.clone(),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        writer.file.write_all(&line)?;
+        writer.file.flush()?;
+        writer.seq = entry.seq;
+        writer.last_hash = entry.hash;
+        Ok(())
+    }
+
+    /// 按条件查询，返回最近的 limit 条 (按时间顺序)
+    pub fn query(&self, filter: &AuditQuery) -> std::io::Result<Vec<AuditEntry>> {
+        let mut matched = std::collections::VecDeque::new();
+        // 正在追加的最后一行可能不完整，解析失败的行直接跳过
+        for entry in read_entries(&self.path)?.filter_map(Result::ok) {
+            if filter.matches(&entry) {
+                if matched.len() == filter.limit {
+                    matched.pop_front();
+                }
+                matched.push_back(entry);
+            }
+        }
+        Ok(matched.into())
+    }
+}
+
+/// 启动时安装全局审计日志；未安装时 (测试或关闭审计) 只输出 tracing 日志
+pub fn install(log: AuditLog) {
+    let _ = LOG.set(log);
+}
+
+/// 记录一条审计事件，写入失败不影响业务，只输出错误日志
+pub fn record(user: Option<&str>, host: Option<&str>, event: AuditEvent) {
+    info!(target: "audit", user, host, event = ?event);
+    if let Some(log) = LOG.get() {
+        if let Err(e) = log.append(user, host, event) {
+            error!("写入审计日志失败: {}", e);
+        }
+    }
+}
+
+fn read_entries(path: &Path) -> std::io::Result<impl Iterator<Item = Result<AuditEntry, String>>> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(reader.lines().map(|line| {
+        let line = line.map_err(|e| e.to_string())?;
+        serde_json::from_str::<AuditEntry>(&line).map_err(|e| e.to_string())
+    }))
+}
+
+/// 校验结果：条目数和链尾哈希
+#[derive(Debug)]
+pub struct Verified {
+    pub entries: u64,
+    pub last_hash: String,
+}
+
+/// 逐条校验哈希链，返回第一处被篡改的位置
+///
+/// 只截掉末尾若干条无法从链本身发现，需要对照之前记录的条目数和链尾哈希。
+pub fn verify(path: &Path) -> Result<Verified, String> {
+    let entries = read_entries(path).map_err(|e| format!("无法读取 {}: {}", path.display(), e))?;
+    let mut prev = GENESIS.to_string();
+    let mut count = 0u64;
+    for (index, entry) in entries.enumerate() {
+        let line = index + 1;
+        let entry = entry.map_err(|e| format!("第 {} 行无法解析: {}", line, e))?;
+        if entry.seq != count + 1 {
+            return Err(format!("第 {} 行序号为 {}，应为 {}，中间的条目可能被删除", line, entry.seq, count + 1));
+        }
+        if entry.prev != prev {
+            return Err(format!("第 {} 行 (seq {}) 与上一条的哈希不一致", line, entry.seq));
+        }
+        if entry.compute_hash() != entry.hash {
+            return Err(format!("第 {} 行 (seq {}) 的内容已被修改", line, entry.seq));
+        }
+        prev = entry.hash;
+        count += 1;
+    }
+    Ok(Verified { entries: count, last_hash: prev })
+}
+
+/// 从终端输入中拼出用户提交的整行
+///
+/// 按回车切分，处理退格、Ctrl-U/Ctrl-C 清行，忽略方向键等转义序列；
+/// Tab 补全等由远端 shell 完成的编辑无法还原，记录的是实际按键。
+pub fn submitted_lines(buffer: &mut String, data: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' | '\n' => {
+                if c == '\r' && chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                lines.push(std::mem::take(buffer));
+            }
+            '\u{7f}' | '\u{8}' => {
+                buffer.pop();
+            }
+            '\u{15}' | '\u{3}' => buffer.clear(),
+            '\u{1b}' => {
+                // CSI (ESC [ 参数 终止字节) 或 SS3 (ESC O x)；遇到控制字符即结束转义序列，
+                // 回车换行等仍按上面的规则处理，不会被当作序列的一部分吞掉
+                let printable = |c: &char| ('\u{20}'..='\u{7e}').contains(c);
+                match chars.peek() {
+                    Some('[') => {
+                        chars.next();
+                        while chars.next_if(|c| ('\u{20}'..='\u{3f}').contains(c)).is_some() {}
+                        chars.next_if(printable);
+                    }
+                    Some('O') => {
+                        chars.next();
+                        chars.next_if(printable);
+                    }
+                    _ => {}
+                }
+            }
+            c if c.is_control() && c != '\t' => {}
+            c => {
+                if buffer.len() < MAX_LINE {
+                    buffer.push(c);
+                }
+            }
+        }
+    }
+    lines
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    user: Option<String>,
+    /// 支持 `*`、`?` 通配符
+    host: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_limit() -> usize {
+    1000
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.user.as_ref().is_none_or(|user| entry.user.as_ref() == Some(user))
+            && self.host.as_ref().is_none_or(|pattern| {
+                entry.host.as_deref().is_some_and(|host| matches_pattern(pattern, host))
+            })
+            && self.from.is_none_or(|from| entry.time >= from)
+            && self.to.is_none_or(|to| entry.time < to)
+    }
+}
+
+/// `GET /api/audit`，仅 admin 可用
+pub async fn handle_query(
+    identity: Option<Identity>,
+    query: AuditQuery,
+    _state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let error = |status, message: &str| {
+        let body = warp::reply::json(&serde_json::json!({ "error": message }));
+        Ok(warp::reply::with_status(body, status).into_response())
+    };
+    if identity.is_some_and(|i| i.role != UserRole::Admin) {
+        return error(StatusCode::FORBIDDEN, "仅管理员可以查看审计日志");
+    }
+    let Some(log) = LOG.get() else {
+        return error(StatusCode::NOT_FOUND, "未启用审计日志");
+    };
+    match log.query(&query) {
+        Ok(entries) => Ok(warp::reply::json(&entries).into_response()),
+        Err(e) => {
+            error!("读取审计日志失败: {}", e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, "读取审计日志失败")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_editing() {
+        let mut buffer = String::new();
+        assert!(submitted_lines(&mut buffer, "ls -la").is_empty());
+        assert_eq!(submitted_lines(&mut buffer, "\u{7f}\u{7f}h\r"), vec!["ls -h"]);
+        assert_eq!(submitted_lines(&mut buffer, "rm -rf /tmp/x\u{15}uptime\x1b[A\r\n"), vec!["uptime"]);
+        assert_eq!(submitted_lines(&mut buffer, "a\rb\r"), vec!["a", "b"]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn escape_sequences_never_swallow_line_ends() {
+        let mut buffer = String::new();
+        assert_eq!(submitted_lines(&mut buffer, "rm -rf /\x1b\r"), vec!["rm -rf /"]);
+        assert_eq!(submitted_lines(&mut buffer, "rm -rf /\x1bO\r"), vec!["rm -rf /"]);
+        assert_eq!(submitted_lines(&mut buffer, "rm -rf /\x1b[12;\r"), vec!["rm -rf /"]);
+        assert_eq!(submitted_lines(&mut buffer, "rm -rf /\x1b[1\x03ls\n"), vec!["ls"]);
+        assert_eq!(submitted_lines(&mut buffer, "ls\x1bOA\x1b[1;5C -l\r"), vec!["ls -l"]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn chain_detects_tampering() {
+        let path = std::env::temp_dir().join(format!("audit-{}.log", Uuid::new_v4()));
+        let log = AuditLog::open(&AuditConfig { enabled: true, path: path.to_string_lossy().into_owned() }).unwrap();
+        for line in ["whoami", "id", "uptime"] {
+            let event = AuditEvent::Input { session_id: Uuid::nil(), line: line.to_string() };
+            log.append(Some("alice"), Some("web-1"), event).unwrap();
+        }
+        drop(log);
+        // 重新打开后接着原来的链写
+        let log = AuditLog::open(&AuditConfig { enabled: true, path: path.to_string_lossy().into_owned() }).unwrap();
+        log.append(Some("bob"), None, AuditEvent::Logout).unwrap();
+        assert_eq!(verify(&path).unwrap().entries, 4);
+
+        let query = AuditQuery { user: Some("alice".to_string()), host: Some("web-*".to_string()), from: None, to: None, limit: 2 };
+        let found = log.query(&query).unwrap();
+        assert_eq!(found.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![2, 3]);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, content.replace("\"id\"", "\"ls\"")).unwrap();
+        assert!(verify(&path).unwrap_err().contains("seq 2"));
+
+        let lines: Vec<_> = content.lines().collect();
+        std::fs::write(&path, [lines[0], lines[2], lines[3]].join("\n")).unwrap();
+        assert!(verify(&path).is_err());
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,634 @@
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use dashmap::DashMap;
+use rand::RngCore;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+use tracing::{error, info, warn};
+use warp::{Filter, Reply};
+use warp::http::{header, StatusCode};
+
+use crate::AppState;
+use crate::audit::{self, AuditEvent};
+use crate::api_token::{ApiTokenStore, TokenScope};
+use crate::config::{AuthConfig, UserRole};
+use crate::totp::{Enrollment, TotpError, TotpStore};
+
+/// 登录会话的 Cookie 名
+pub const SESSION_COOKIE: &str = "ssh_ai_session";
+
+/// 密码通过后提交验证码的期限
+const SECOND_FACTOR_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// WebSocket 令牌签发后必须在该时间内使用
+pub const WS_TOKEN_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("用户名或密码错误")]
+    InvalidCredentials,
+    #[error("登录失败次数过多，请 {retry_after} 秒后重试")]
+    LockedOut { retry_after: u64 },
+    #[error("未登录或登录已过期")]
+    Unauthorized,
+    #[error("验证码错误")]
+    InvalidCode,
+    #[error("API令牌没有访问该接口的权限范围")]
+    InsufficientScope,
+    #[error("服务器内部错误")]
+    Internal,
+}
+
+impl warp::reject::Reject for AuthError {}
+
+/// 通过校验的登录用户
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub user: String,
+    pub role: UserRole,
+    pub groups: Vec<String>,
+}
+
+struct Account {
+    password_hash: String,
+    role: UserRole,
+    groups: Vec<String>,
+}
+
+struct LoginSession {
+    identity: Identity,
+    last_seen: Instant,
+}
+
+/// 签发给某个登录会话、只能使用一次的 WebSocket 令牌
+struct WsToken {
+    session: String,
+    created: Instant,
+}
+
+/// 密码已通过、等待验证码的登录
+struct PendingLogin {
+    identity: Identity,
+    created: Instant,
+    attempts: u32,
+}
+
+/// 密码校验通过后的结果
+pub enum LoginOutcome {
+    /// 已创建登录会话
+    Session { token: String, identity: Identity },
+    /// 还需要提交验证码；enrollment 不为空表示账户必须先绑定验证器
+    SecondFactor { mfa_token: String, enrollment: Option<Enrollment> },
+}
+
+#[derive(Default)]
+struct Failures {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+/// 登录校验、会话和失败锁定，全部保存在内存中，重启后需要重新登录
+pub struct Auth {
+    config: AuthConfig,
+    accounts: HashMap<String, Account>,
+    /// 用户名不存在时用于校验的哈希，使耗时与真实账户一致
+    dummy_hash: String,
+    sessions: DashMap<String, LoginSession>,
+    /// 按来源地址统计连续失败次数
+    failures: DashMap<String, Failures>,
+    totp: TotpStore,
+    pending: DashMap<String, PendingLogin>,
+    tokens: ApiTokenStore,
+    ws_tokens: DashMap<String, WsToken>,
+}
+
+impl Auth {
+    pub fn new(config: &AuthConfig) -> anyhow::Result<Self> {
+        let mut accounts = HashMap::new();
+        if !config.username.is_empty() && !config.password.is_empty() {
+            accounts.insert(config.username.clone(), Account {
+                password_hash: load_hash(&config.username, &config.password, config.enabled)?,
+                role: UserRole::Admin,
+                groups: Vec::new(),
+            });
+        }
+        for user in &config.users {
+            accounts.insert(user.username.clone(), Account {
+                password_hash: load_hash(&user.username, &user.password, config.enabled)?,
+                role: user.role,
+                groups: user.groups.clone(),
+            });
+        }
+        let mut random = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut random);
+        Ok(Auth {
+            config: config.clone(),
+            accounts,
+            dummy_hash: hash_password(&URL_SAFE_NO_PAD.encode(random)),
+            sessions: DashMap::new(),
+            failures: DashMap::new(),
+            totp: TotpStore::open(&config.totp)?,
+            pending: DashMap::new(),
+            tokens: ApiTokenStore::open(&config.api_tokens)?,
+            ws_tokens: DashMap::new(),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// 是否为配置文件中的本地账户 (单点登录账户不在其中)
+    pub fn has_account(&self, user: &str) -> bool {
+        self.accounts.contains_key(user)
+    }
+
+    pub fn totp(&self) -> &TotpStore {
+        &self.totp
+    }
+
+    pub fn tokens(&self) -> &ApiTokenStore {
+        &self.tokens
+    }
+
+    /// 校验 API 令牌及其权限范围
+    ///
+    /// 本地账户按配置中当前的角色和组授权，账户删除后令牌随之失效；
+    /// 单点登录账户使用签发令牌时的角色和组。
+    pub fn authenticate_token(&self, token: &str, scope: TokenScope) -> Result<Identity, AuthError> {
+        let (info, role, groups) = self.tokens.authenticate(token).ok_or(AuthError::Unauthorized)?;
+        let identity = match self.accounts.get(&info.user) {
+            Some(account) => Identity { user: info.user, role: account.role, groups: account.groups.clone() },
+            None if self.config.oidc.is_some() => Identity { user: info.user, role, groups },
+            None => return Err(AuthError::Unauthorized),
+        };
+        if !info.scopes.contains(&scope) {
+            audit::record(Some(&identity.user), None, AuditEvent::Account {
+                action: "api_token_scope_denied".to_string(),
+                detail: Some(format!("token {} scope {:?}", info.id, scope)),
+            });
+            return Err(AuthError::InsufficientScope);
+        }
+        Ok(identity)
+    }
+
+    /// 校验用户名密码；启用了两步验证的账户还需要调用 verify_second_factor
+    pub async fn login(&self, username: &str, password: &str, source: &str) -> Result<LoginOutcome, AuthError> {
+        self.check_lockout(source)?;
+
+        // 用户名不存在时同样执行哈希校验，避免通过响应时间区分
+        let account = self.accounts.get(username);
+        let hash = account.map_or(&self.dummy_hash, |a| &a.password_hash).clone();
+        let candidate = password.to_string();
+        let password_ok = tokio::task::spawn_blocking(move || verify_password(&hash, &candidate))
+            .await
+            .unwrap_or(false);
+        let Some(account) = account.filter(|_| password_ok) else {
+            audit::record(Some(username), None, AuditEvent::LoginFailed {
+                reason: "invalid_credentials".to_string(),
+                source: Some(source.to_string()),
+            });
+            return Err(self.record_failure(source, AuthError::InvalidCredentials));
+        };
+
+        self.failures.remove(source);
+        let identity = Identity {
+            user: username.to_string(),
+            role: account.role,
+            groups: account.groups.clone(),
+        };
+
+        let enrolled = self.totp.enabled(username);
+        if enrolled || self.config.totp.required_for(identity.role) {
+            // 并发绑定完成时 begin 失败，此时按已启用处理
+            let enrollment = if enrolled { None } else { self.totp.begin(username).ok() };
+            let mfa_token = new_token();
+            self.pending.retain(|_, p| p.created.elapsed() <= SECOND_FACTOR_TIMEOUT);
+            self.pending.insert(mfa_token.clone(), PendingLogin { identity, created: Instant::now(), attempts: 0 });
+            info!(user = username, source, "password accepted, second factor required");
+            return Ok(LoginOutcome::SecondFactor { mfa_token, enrollment });
+        }
+
+        audit::record(Some(username), None, AuditEvent::Login {
+            method: "password".to_string(),
+            source: Some(source.to_string()),
+        });
+        Ok(LoginOutcome::Session { token: self.start_session(identity.clone()), identity })
+    }
+
+    /// 校验登录第二步的验证码或恢复码；账户尚未绑定时这一步同时完成绑定并返回恢复码
+    pub fn verify_second_factor(
+        &self,
+        mfa_token: &str,
+        code: &str,
+        source: &str,
+    ) -> Result<(String, Identity, Option<Vec<String>>), AuthError> {
+        self.check_lockout(source)?;
+        let identity = match self.pending.get(mfa_token) {
+            Some(pending) if pending.created.elapsed() <= SECOND_FACTOR_TIMEOUT => pending.identity.clone(),
+            _ => {
+                self.pending.remove(mfa_token);
+                return Err(AuthError::Unauthorized);
+            }
+        };
+
+        let user = identity.user.as_str();
+        let result = if self.totp.enabled(user) {
+            self.totp.verify(user, code).map(|_| None)
+        } else {
+            self.totp.activate(user, code).map(Some)
+        };
+        match result {
+            Ok(recovery_codes) => {
+                self.pending.remove(mfa_token);
+                self.failures.remove(source);
+                audit::record(Some(user), None, AuditEvent::Login {
+                    method: "totp".to_string(),
+                    source: Some(source.to_string()),
+                });
+                Ok((self.start_session(identity.clone()), identity, recovery_codes))
+            }
+            Err(TotpError::Store(e)) => {
+                error!("保存两步验证数据失败: {}", e);
+                Err(AuthError::Internal)
+            }
+            Err(_) => {
+                // 同一次登录连续输错达到上限后需要重新输入密码
+                let exhausted = self.pending.get_mut(mfa_token).is_some_and(|mut pending| {
+                    pending.attempts += 1;
+                    pending.attempts >= self.config.max_attempts
+                });
+                if exhausted {
+                    self.pending.remove(mfa_token);
+                }
+                audit::record(Some(user), None, AuditEvent::LoginFailed {
+                    reason: "invalid_code".to_string(),
+                    source: Some(source.to_string()),
+                });
+                Err(self.record_failure(source, AuthError::InvalidCode))
+            }
+        }
+    }
+
+    /// 为已通过校验的账户创建登录会话，本地密码和单点登录共用
+    pub fn start_session(&self, identity: Identity) -> String {
+        self.expire_idle();
+        let token = new_token();
+        self.sessions.insert(token.clone(), LoginSession { identity, last_seen: Instant::now() });
+        token
+    }
+
+    fn check_lockout(&self, source: &str) -> Result<(), AuthError> {
+        let now = Instant::now();
+        match self.failures.get(source).and_then(|f| f.locked_until) {
+            Some(until) if until > now => Err(AuthError::LockedOut { retry_after: (until - now).as_secs().max(1) }),
+            _ => Ok(()),
+        }
+    }
+
+    fn record_failure(&self, source: &str, error: AuthError) -> AuthError {
+        let now = Instant::now();
+        let mut failures = self.failures.entry(source.to_string()).or_default();
+        if failures.locked_until.is_some_and(|until| until <= now) {
+            *failures = Failures::default();
+        }
+        failures.count += 1;
+        warn!(source, attempts = failures.count, "login failed");
+        if failures.count >= self.config.max_attempts {
+            let lockout = Duration::from_secs(self.config.lockout_duration);
+            failures.locked_until = Some(now + lockout);
+            warn!(source, "login locked for {} seconds", lockout.as_secs());
+            return AuthError::LockedOut { retry_after: lockout.as_secs().max(1) };
+        }
+        error
+    }
+
+    /// 校验会话令牌并刷新空闲计时
+    pub fn authenticate(&self, token: &str) -> Option<Identity> {
+        let timeout = Duration::from_secs(self.config.session_timeout);
+        let mut session = self.sessions.get_mut(token)?;
+        if session.last_seen.elapsed() > timeout {
+            drop(session);
+            self.sessions.remove(token);
+            return None;
+        }
+        session.last_seen = Instant::now();
+        Some(session.identity.clone())
+    }
+
+    /// 为登录会话签发打开 `/ws` 用的一次性令牌
+    pub fn issue_ws_token(&self, session: &str) -> Option<String> {
+        self.authenticate(session)?;
+        self.ws_tokens.retain(|_, t| t.created.elapsed() <= WS_TOKEN_TTL);
+        let token = new_token();
+        self.ws_tokens.insert(token.clone(), WsToken { session: session.to_string(), created: Instant::now() });
+        Some(token)
+    }
+
+    /// 消耗令牌，只有未过期且属于同一登录会话时成功
+    pub fn consume_ws_token(&self, token: &str, session: &str) -> bool {
+        match self.ws_tokens.remove(token) {
+            Some((_, issued)) => {
+                issued.created.elapsed() <= WS_TOKEN_TTL && bool::from(issued.session.as_bytes().ct_eq(session.as_bytes()))
+            }
+            None => false,
+        }
+    }
+
+    pub fn logout(&self, token: &str) {
+        self.ws_tokens.retain(|_, t| t.session != token);
+        if let Some((_, session)) = self.sessions.remove(token) {
+            audit::record(Some(&session.identity.user), None, AuditEvent::Logout);
+        }
+    }
+
+    fn expire_idle(&self) {
+        let timeout = Duration::from_secs(self.config.session_timeout);
+        self.sessions.retain(|_, s| s.last_seen.elapsed() <= timeout);
+    }
+}
+
+/// 读取配置中的密码，明文密码在内存中哈希后使用
+fn load_hash(username: &str, password: &str, enabled: bool) -> anyhow::Result<String> {
+    if password.starts_with("$argon2") {
+        PasswordHash::new(password)
+            .map_err(|e| anyhow::anyhow!("用户 {} 的密码不是有效的 argon2 哈希: {}", username, e))?;
+        return Ok(password.to_string());
+    }
+    if enabled {
+        warn!("用户 {} 的密码为明文，建议用 `ssh-ai-terminal hash-password` 生成哈希后替换", username);
+    }
+    Ok(hash_password(password))
+}
+
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("argon2 hashing with default params")
+        .to_string()
+}
+
+fn verify_password(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+        .unwrap_or(false)
+}
+
+pub(crate) fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// 启用 TLS 时 Cookie 只通过 HTTPS 发送
+pub(crate) fn secure_flag(state: &AppState) -> &'static str {
+    if state.config.server.tls.enabled {
+        "; Secure"
+    } else {
+        ""
+    }
+}
+
+pub(crate) fn session_cookie(token: &str, state: &AppState) -> String {
+    format!("{}={}; Path=/; HttpOnly; SameSite=Strict{}", SESSION_COOKIE, token, secure_flag(state))
+}
+
+fn clear_cookie(state: &AppState) -> String {
+    format!("{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0{}", SESSION_COOKIE, secure_flag(state))
+}
+
+/// 要求有效的登录会话；未启用登录时放行且不带身份
+pub fn authenticated(state: AppState) -> impl Filter<Extract = (Option<Identity>,), Error = warp::Rejection> + Clone {
+    warp::cookie::optional(SESSION_COOKIE).and_then(move |token: Option<String>| {
+        let state = state.clone();
+        async move {
+            if !state.auth.enabled() {
+                return Ok(None);
+            }
+            token
+                .and_then(|token| state.auth.authenticate(&token))
+                .map(Some)
+                .ok_or_else(|| warp::reject::custom(AuthError::Unauthorized))
+        }
+    })
+}
+
+/// 接受登录会话 Cookie，或带有指定权限范围的 API 令牌 (`Authorization: Bearer ...`)
+pub fn authorized(
+    state: AppState,
+    scope: TokenScope,
+) -> impl Filter<Extract = (Option<Identity>,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>(header::AUTHORIZATION.as_str())
+        .and(warp::cookie::optional(SESSION_COOKIE))
+        .and_then(move |authorization: Option<String>, token: Option<String>| {
+            let state = state.clone();
+            async move {
+                if !state.auth.enabled() {
+                    return Ok(None);
+                }
+                let result = match authorization.as_deref().and_then(|value| value.strip_prefix("Bearer ")) {
+                    Some(bearer) => state.auth.authenticate_token(bearer.trim(), scope),
+                    None => token
+                        .and_then(|token| state.auth.authenticate(&token))
+                        .ok_or(AuthError::Unauthorized),
+                };
+                result.map(Some).map_err(warp::reject::custom)
+            }
+        })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+pub async fn handle_login(
+    request: LoginRequest,
+    remote: Option<SocketAddr>,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let source = remote.map(|addr| addr.ip().to_string()).unwrap_or_else(|| request.username.clone());
+    match state.auth.login(&request.username, &request.password, &source).await {
+        Ok(LoginOutcome::Session { token, identity }) => {
+            let body = warp::reply::json(&serde_json::json!({ "user": identity.user, "role": identity.role }));
+            Ok(warp::reply::with_header(body, header::SET_COOKIE, session_cookie(&token, &state)).into_response())
+        }
+        Ok(LoginOutcome::SecondFactor { mfa_token, enrollment }) => Ok(warp::reply::json(&serde_json::json!({
+            "second_factor": if enrollment.is_some() { "enroll" } else { "verify" },
+            "mfa_token": mfa_token,
+            "enrollment": enrollment,
+        }))
+        .into_response()),
+        Err(e) => Ok(rejection_reply(&e)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SecondFactorRequest {
+    mfa_token: String,
+    code: String,
+}
+
+pub async fn handle_second_factor(
+    request: SecondFactorRequest,
+    remote: Option<SocketAddr>,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let source = remote.map(|addr| addr.ip().to_string()).unwrap_or_else(|| request.mfa_token.clone());
+    match state.auth.verify_second_factor(&request.mfa_token, &request.code, &source) {
+        Ok((token, identity, recovery_codes)) => {
+            let body = warp::reply::json(&serde_json::json!({
+                "user": identity.user,
+                "role": identity.role,
+                "recovery_codes": recovery_codes,
+            }));
+            Ok(warp::reply::with_header(body, header::SET_COOKIE, session_cookie(&token, &state)).into_response())
+        }
+        Err(e) => Ok(rejection_reply(&e)),
+    }
+}
+
+pub async fn handle_logout(token: Option<String>, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Some(token) = token {
+        state.auth.logout(&token);
+    }
+    let body = warp::reply::json(&serde_json::json!({ "ok": true }));
+    Ok(warp::reply::with_header(body, header::SET_COOKIE, clear_cookie(&state)))
+}
+
+/// 签发打开 `/ws` 用的一次性令牌；跨站页面无法读取响应，因此拿不到令牌
+pub async fn handle_ws_token(token: Option<String>, state: AppState) -> Result<warp::reply::Response, warp::Rejection> {
+    if !state.auth.enabled() {
+        return Ok(warp::reply::json(&serde_json::json!({ "token": null })).into_response());
+    }
+    match token.and_then(|token| state.auth.issue_ws_token(&token)) {
+        Some(ws_token) => Ok(warp::reply::json(&serde_json::json!({
+            "token": ws_token,
+            "expires_in": WS_TOKEN_TTL.as_secs(),
+        }))
+        .into_response()),
+        None => Ok(rejection_reply(&AuthError::Unauthorized)),
+    }
+}
+
+pub async fn handle_session(identity: Option<Identity>) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&serde_json::json!({
+        "user": identity.as_ref().map(|i| &i.user),
+        "role": identity.as_ref().map(|i| i.role),
+    })))
+}
+
+/// 把登录相关的拒绝转换为 401/429 JSON 响应
+pub fn rejection_reply(error: &AuthError) -> warp::reply::Response {
+    let status = match error {
+        AuthError::LockedOut { .. } => StatusCode::TOO_MANY_REQUESTS,
+        AuthError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        AuthError::InsufficientScope => StatusCode::FORBIDDEN,
+        _ => StatusCode::UNAUTHORIZED,
+    };
+    let body = warp::reply::json(&serde_json::json!({ "error": error.to_string() }));
+    let mut response = warp::reply::with_status(body, status).into_response();
+    if let AuthError::LockedOut { retry_after } = error {
+        response.headers_mut().insert(header::RETRY_AFTER, (*retry_after).into());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{TotpConfig, UserConfig};
+
+    fn auth(max_attempts: u32) -> Auth {
+        auth_with(max_attempts, TotpConfig::default())
+    }
+
+    fn auth_with(max_attempts: u32, totp: TotpConfig) -> Auth {
+        Auth::new(&AuthConfig {
+            enabled: true,
+            username: "admin".to_string(),
+            password: hash_password("secret"),
+            session_timeout: 60,
+            max_attempts,
+            lockout_duration: 60,
+            users: vec![UserConfig {
+                username: "bob".to_string(),
+                password: "hunter2".to_string(),
+                role: UserRole::Viewer,
+                groups: vec!["ops".to_string()],
+            }],
+            totp,
+            ..Default::default()
+        }).unwrap()
+    }
+
+    fn session(outcome: Result<LoginOutcome, AuthError>) -> (String, Identity) {
+        match outcome {
+            Ok(LoginOutcome::Session { token, identity }) => (token, identity),
+            _ => panic!("expected a login session"),
+        }
+    }
+
+    #[tokio::test]
+    async fn login_issues_session() {
+        let auth = auth(3);
+        let (token, identity) = session(auth.login("admin", "secret", "1.2.3.4").await);
+        assert_eq!(identity.user, "admin");
+        assert_eq!(identity.role, UserRole::Admin);
+        let (_, bob) = session(auth.login("bob", "hunter2", "1.2.3.4").await);
+        assert_eq!((bob.role, bob.groups), (UserRole::Viewer, vec!["ops".to_string()]));
+        assert_eq!(auth.authenticate(&token).unwrap().user, "admin");
+        auth.logout(&token);
+        assert!(auth.authenticate(&token).is_none());
+    }
+
+    #[tokio::test]
+    async fn repeated_failures_lock_source() {
+        let auth = auth(2);
+        assert!(matches!(auth.login("admin", "x", "a").await, Err(AuthError::InvalidCredentials)));
+        assert!(matches!(auth.login("root", "secret", "a").await, Err(AuthError::LockedOut { .. })));
+        // 锁定期间正确的密码也被拒绝，其它来源不受影响
+        assert!(matches!(auth.login("admin", "secret", "a").await, Err(AuthError::LockedOut { .. })));
+        assert!(auth.login("admin", "secret", "b").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn required_second_factor() {
+        let path = std::env::temp_dir().join(format!("totp-{}.json", uuid::Uuid::new_v4()));
+        let auth = auth_with(3, TotpConfig {
+            required: true,
+            store: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        });
+
+        // viewer 不能打开可写会话，不受强制策略影响
+        session(auth.login("bob", "hunter2", "a").await);
+
+        let Ok(LoginOutcome::SecondFactor { mfa_token, enrollment: Some(enrollment) }) =
+            auth.login("admin", "secret", "a").await
+        else {
+            panic!("admin must enroll first");
+        };
+        assert!(matches!(auth.verify_second_factor(&mfa_token, "000000x", "a"), Err(AuthError::InvalidCode)));
+        let code = crate::totp::current_code(&enrollment.secret);
+        let (token, identity, recovery_codes) = auth.verify_second_factor(&mfa_token, &code, "a").unwrap();
+        assert_eq!(identity.user, "admin");
+        assert_eq!(recovery_codes.unwrap().len(), 10);
+        assert!(auth.authenticate(&token).is_some());
+        // 登录第二步的令牌只能用一次
+        assert!(matches!(auth.verify_second_factor(&mfa_token, &code, "a"), Err(AuthError::Unauthorized)));
+
+        let Ok(LoginOutcome::SecondFactor { enrollment: None, .. }) = auth.login("admin", "secret", "b").await else {
+            panic!("enrolled admin must verify");
+        };
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::models::BroadcastFailure;
+use crate::session::SharedSession;
+
+/// 单次广播写入的超时，卡住的主机不会拖慢其它主机
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 单个WebSocket连接上的广播组 (cluster-ssh 模式)
+///
+/// 一个会话同一时间只属于一个组；向组内任一会话输入的数据会写入组内所有会话。
+#[derive(Default)]
+pub struct BroadcastGroups {
+    groups: HashMap<Uuid, HashSet<Uuid>>,
+}
+
+impl BroadcastGroups {
+    /// 把会话加入组，已在其它组中的会话会先移出；group_id 为空时新建组
+    pub fn join(&mut self, group_id: Option<Uuid>, session_ids: &[Uuid]) -> Uuid {
+        let group_id = group_id.unwrap_or_else(Uuid::new_v4);
+        for session_id in session_ids {
+            self.remove_session(*session_id);
+        }
+        self.groups.entry(group_id).or_default().extend(session_ids);
+        group_id
+    }
+
+    /// 移出会话，组为空时一并删除
+    pub fn leave(&mut self, group_id: Uuid, session_ids: &[Uuid]) {
+        if let Some(members) = self.groups.get_mut(&group_id) {
+            for session_id in session_ids {
+                members.remove(session_id);
+            }
+            if members.is_empty() {
+                self.groups.remove(&group_id);
+            }
+        }
+    }
+
+    /// 会话结束或断开时调用
+    pub fn remove_session(&mut self, session_id: Uuid) {
+        if let Some(group_id) = self.group_of(session_id) {
+            self.leave(group_id, &[session_id]);
+        }
+    }
+
+    pub fn group_of(&self, session_id: Uuid) -> Option<Uuid> {
+        self.groups
+            .iter()
+            .find(|(_, members)| members.contains(&session_id))
+            .map(|(group_id, _)| *group_id)
+    }
+
+    pub fn members(&self, group_id: Uuid) -> Vec<Uuid> {
+        self.groups
+            .get(&group_id)
+            .map(|members| members.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// 并发写入各会话各自的数据，返回失败的会话及原因
+///
+/// 每个会话在独立任务中写入，SSH 后端的阻塞写不会互相拖累。
+pub async fn fan_out(writes: Vec<(Arc<SharedSession>, String)>) -> Vec<BroadcastFailure> {
+    let handles: Vec<_> = writes
+        .into_iter()
+        .filter(|(_, data)| !data.is_empty())
+        .map(|(session, data)| {
+            let session_id = session.id;
+            let handle = tokio::spawn(async move {
+                match tokio::time::timeout(WRITE_TIMEOUT, session.backend.write(&data)).await {
+                    Ok(Ok(())) => Ok(()),
+                    Ok(Err(e)) => Err(e.to_string()),
+                    Err(_) => Err("write timed out".to_string()),
+                }
+            });
+            (session_id, handle)
+        })
+        .collect();
+
+    let mut failures = Vec::new();
+    for (session_id, handle) in handles {
+        let result = handle.await.unwrap_or_else(|e| Err(e.to_string()));
+        if let Err(message) = result {
+            failures.push(BroadcastFailure { session_id, message });
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_belongs_to_one_group() {
+        let mut groups = BroadcastGroups::default();
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        let first = groups.join(None, &[a, b]);
+        let second = groups.join(None, &[b, c]);
+        assert_eq!(groups.group_of(a), Some(first));
+        assert_eq!(groups.group_of(b), Some(second));
+        assert_eq!(groups.members(first), vec![a]);
+    }
+
+    #[test]
+    fn empty_group_is_removed() {
+        let mut groups = BroadcastGroups::default();
+        let a = Uuid::new_v4();
+        let group_id = groups.join(None, &[a]);
+        groups.remove_session(a);
+        assert!(groups.members(group_id).is_empty());
+        assert_eq!(groups.group_of(a), None);
+    }
+}
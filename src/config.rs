@@ -1,8 +1,11 @@
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use serde::{Serialize, Deserialize};
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+
+use crate::risk::RiskLevel;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -10,6 +13,26 @@ pub struct Config {
     pub database: DatabaseConfig,
     pub cache: CacheConfig,
     pub ai: AIConfig,
+    #[serde(default)]
+    pub ssh: SSHConfig,
+    #[serde(default)]
+    pub local_shell: LocalShellConfig,
+    #[serde(default)]
+    pub share: ShareConfig,
+    #[serde(default)]
+    pub session: SessionConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub policy: PolicyConfig,
+    #[serde(default)]
+    pub performance: PerformanceConfig,
+    #[serde(default)]
+    pub destinations: DestinationConfig,
+    #[serde(default)]
+    pub vault: VaultConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,6 +40,64 @@ pub struct ServerConfig {
     pub port: u16,
     pub address: String,
     pub log_level: String,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    #[serde(default)]
+    pub websocket: WebSocketConfig,
+}
+
+/// `/ws` 升级时的跨站检查
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebSocketConfig {
+    /// 允许的 Origin，支持 `*` 和 `?` 通配符，为空时只允许与 Host 相同的来源
+    pub allowed_origins: Vec<String>,
+    /// 使用登录 Cookie 的连接必须携带 `/api/auth/ws-token` 签发的一次性令牌
+    pub require_csrf_token: bool,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        WebSocketConfig {
+            allowed_origins: Vec::new(),
+            require_csrf_token: true,
+        }
+    }
+}
+
+/// 直接以 HTTPS 提供服务
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    /// PEM 证书链，服务器证书在前
+    pub cert_path: String,
+    /// PEM 私钥
+    pub key_path: String,
+    /// 设置后要求客户端出示该 CA 签发的证书 (mTLS)
+    pub client_ca_path: Option<String>,
+    /// 检查证书文件是否变化的间隔 (秒)，0 表示不自动重新加载
+    pub reload_interval: u64,
+    /// 在该端口监听 HTTP 并跳转到 HTTPS
+    pub redirect_http_port: Option<u16>,
+    /// `Strict-Transport-Security` 的 max-age (秒)，0 表示不发送
+    pub hsts_max_age: u64,
+    pub hsts_include_subdomains: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            enabled: false,
+            cert_path: "certs/server.crt".to_string(),
+            key_path: "certs/server.key".to_string(),
+            client_ca_path: None,
+            reload_interval: 30,
+            redirect_http_port: None,
+            hsts_max_age: 31_536_000,
+            hsts_include_subdomains: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,6 +118,708 @@ pub struct AIConfig {
     pub providers: Vec<String>,
     pub timeout: u64,
     pub retry_count: u8,
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+}
+
+/// 发送给AI提供商之前遮盖密钥
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub enabled: bool,
+    /// 额外的正则，含 `secret` 命名分组时只遮盖该分组
+    pub patterns: Vec<String>,
+    /// 高熵检测的最小长度，0 表示关闭
+    pub min_length: usize,
+    /// 每个字符的最小香农熵 (比特)
+    pub min_entropy: f64,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        RedactionConfig {
+            enabled: true,
+            patterns: Vec::new(),
+            min_length: 20,
+            min_entropy: 4.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SSHConfig {
+    /// 同时打开的SSH会话上限，0 表示不限
+    pub max_sessions: usize,
+    /// 每个账户同时打开的SSH会话上限，0 表示不限
+    pub max_sessions_per_user: usize,
+    pub timeout: u64,
+    /// 保活间隔 (秒)，0 表示关闭
+    pub keep_alive: u32,
+    /// 连续多少次保活探测无应答后判定连接失效
+    pub keep_alive_count_max: u32,
+    pub compression: bool,
+    pub auto_reconnect: bool,
+    pub reconnect_attempts: u32,
+    /// 每次重连前的等待时间 (秒)
+    pub reconnect_delay: u64,
+    #[serde(flatten)]
+    pub algorithms: SSHAlgorithms,
+    /// 按主机覆盖，用于只支持旧算法的设备
+    pub hosts: Vec<SSHHostOverride>,
+}
+
+impl Default for SSHConfig {
+    fn default() -> Self {
+        SSHConfig {
+            max_sessions: 500,
+            max_sessions_per_user: 0,
+            timeout: 600,
+            keep_alive: 60,
+            keep_alive_count_max: 3,
+            compression: false,
+            auto_reconnect: false,
+            reconnect_attempts: 3,
+            reconnect_delay: 5,
+            algorithms: SSHAlgorithms::default(),
+            hosts: Vec::new(),
+        }
+    }
+}
+
+/// 算法偏好，均为逗号分隔的列表，留空则使用 libssh2 默认值
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SSHAlgorithms {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cipher: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_exchange: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_key: Option<String>,
+}
+
+impl SSHAlgorithms {
+    fn merge(&mut self, other: &SSHAlgorithms) {
+        if other.cipher.is_some() {
+            self.cipher = other.cipher.clone();
+        }
+        if other.key_exchange.is_some() {
+            self.key_exchange = other.key_exchange.clone();
+        }
+        if other.mac.is_some() {
+            self.mac = other.mac.clone();
+        }
+        if other.host_key.is_some() {
+            self.host_key = other.host_key.clone();
+        }
+    }
+
+    fn validate(&self, scope: &str) -> Result<()> {
+        use crate::ssh::validate_algorithms;
+        use ssh2::MethodType;
+
+        let fields = [
+            ("cipher", MethodType::CryptCs, &self.cipher),
+            ("key_exchange", MethodType::Kex, &self.key_exchange),
+            ("mac", MethodType::MacCs, &self.mac),
+            ("host_key", MethodType::HostKey, &self.host_key),
+        ];
+        for (name, method, value) in fields {
+            if let Some(prefs) = value {
+                validate_algorithms(method, prefs)
+                    .map_err(|e| anyhow!("{}.{}: {}", scope, name, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SSHHostOverride {
+    /// 主机名或IP，支持 `*` 和 `?` 通配符
+    pub host: String,
+    #[serde(flatten)]
+    pub algorithms: SSHAlgorithms,
+    pub compression: Option<bool>,
+    pub keep_alive: Option<u32>,
+}
+
+/// 针对单个目标主机合并后的SSH选项
+#[derive(Debug, Clone, Default)]
+pub struct SSHOptions {
+    pub algorithms: SSHAlgorithms,
+    pub compression: bool,
+    pub keep_alive: u32,
+    pub keep_alive_count_max: u32,
+    pub auto_reconnect: bool,
+    pub reconnect_attempts: u32,
+    pub reconnect_delay: u64,
+}
+
+impl SSHConfig {
+    /// 第一个匹配的主机覆盖项生效，未设置的字段继承全局配置
+    pub fn options_for(&self, host: &str) -> SSHOptions {
+        let mut options = SSHOptions {
+            algorithms: self.algorithms.clone(),
+            compression: self.compression,
+            keep_alive: self.keep_alive,
+            keep_alive_count_max: self.keep_alive_count_max.max(1),
+            auto_reconnect: self.auto_reconnect,
+            reconnect_attempts: self.reconnect_attempts,
+            reconnect_delay: self.reconnect_delay,
+        };
+
+        if let Some(o) = self.hosts.iter().find(|o| matches_pattern(&o.host, host)) {
+            options.algorithms.merge(&o.algorithms);
+            if let Some(compression) = o.compression {
+                options.compression = compression;
+            }
+            if let Some(keep_alive) = o.keep_alive {
+                options.keep_alive = keep_alive;
+            }
+        }
+
+        options
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.algorithms.validate("ssh")?;
+        for o in &self.hosts {
+            if o.host.is_empty() {
+                bail!("ssh.hosts: host 不能为空");
+            }
+            o.algorithms.validate(&format!("ssh.hosts[{}]", o.host))?;
+        }
+        Ok(())
+    }
+}
+
+/// 网关本机Shell，默认关闭
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LocalShellConfig {
+    pub enabled: bool,
+    /// 未设置时使用 $SHELL，Windows 上使用 %COMSPEC%
+    pub shell: Option<String>,
+    pub args: Vec<String>,
+    pub cwd: Option<String>,
+}
+
+impl LocalShellConfig {
+    pub fn shell(&self) -> String {
+        if let Some(shell) = &self.shell {
+            return shell.clone();
+        }
+        if cfg!(windows) {
+            std::env::var("COMSPEC").unwrap_or_else(|_| "cmd.exe".to_string())
+        } else {
+            std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())
+        }
+    }
+}
+
+/// 会话分享链接
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShareConfig {
+    /// 签名密钥，未设置时每次启动随机生成
+    pub secret: Option<String>,
+    /// 默认有效期 (秒)
+    pub default_ttl: u64,
+    /// 最长有效期 (秒)
+    pub max_ttl: u64,
+}
+
+impl Default for ShareConfig {
+    fn default() -> Self {
+        ShareConfig {
+            secret: None,
+            default_ttl: 3600,
+            max_ttl: 86400,
+        }
+    }
+}
+
+/// 终端会话生命周期
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// WebSocket断开后会话保留等待恢复的时间 (秒)，0 表示立即关闭
+    pub resume_grace: u64,
+}
+
+/// 请求限流
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PerformanceConfig {
+    /// 同时处理的 `/api/ai/chat` 请求上限，0 表示不限
+    pub max_concurrent_requests: usize,
+    pub rate_limit: RateLimitConfig,
+}
+
+impl Default for PerformanceConfig {
+    fn default() -> Self {
+        PerformanceConfig {
+            max_concurrent_requests: 100,
+            rate_limit: RateLimitConfig::default(),
+        }
+    }
+}
+
+/// 令牌桶限流，同时按账户和客户端IP计数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// 计数窗口 (秒)，令牌按 次数/窗口 的速度补充
+    pub window: u64,
+    /// 每个窗口允许的请求数，也是桶的容量
+    pub max_requests: u32,
+    /// `/api/ai/chat` 单独的次数，不设置时使用 max_requests
+    pub ai_chat: Option<u32>,
+    /// WebSocket 打开会话 (connect、open_channel) 单独的次数，不设置时使用 max_requests
+    pub connect: Option<u32>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: true,
+            window: 60,
+            max_requests: 1000,
+            ai_chat: None,
+            connect: None,
+        }
+    }
+}
+
+/// 哈希链审计日志
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// 只追加的 JSON Lines 文件
+    pub path: String,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            enabled: true,
+            path: "data/audit.log".to_string(),
+        }
+    }
+}
+
+/// 服务端命令策略，按顺序匹配，第一条命中的规则生效，都不命中时放行
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyConfig {
+    pub rules: Vec<PolicyRule>,
+    pub approval: ApprovalConfig,
+}
+
+/// 双人审批：命中 approve 规则或风险评估达到 risk_level 的命令需要另一位账户批准
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApprovalConfig {
+    /// 等待审批的时间 (秒)，超时视为拒绝
+    pub timeout: u64,
+    /// 达到该风险等级的命令也需要审批，不设置时只看 approve 规则
+    pub risk_level: Option<RiskLevel>,
+    /// 可以审批的角色，审批人还必须有权连接该主机
+    pub roles: Vec<UserRole>,
+}
+
+impl Default for ApprovalConfig {
+    fn default() -> Self {
+        ApprovalConfig {
+            timeout: 300,
+            risk_level: None,
+            roles: vec![UserRole::Admin],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyRule {
+    /// 适用的角色，为空时适用于所有账户
+    pub roles: Vec<UserRole>,
+    /// 主机通配符或 `@主机组`，为空时适用于所有主机 (含本地终端)
+    pub hosts: Vec<String>,
+    /// 按解析后的命令匹配，如 `shutdown`、`mkfs*`、`rm -rf /`：
+    /// 第一个词匹配程序名，其余的词必须都出现在参数中
+    pub commands: Vec<String>,
+    /// 对整行做正则匹配
+    pub pattern: Option<String>,
+    pub action: PolicyAction,
+    /// 拦截或确认时提示给用户的说明
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    #[default]
+    Allow,
+    /// 用户确认后才执行
+    Confirm,
+    /// 另一位账户批准后才执行
+    Approve,
+    Deny,
+}
+
+/// SSH和telnet可以连接的目标，按解析后的地址判定，第一条命中的规则生效
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DestinationConfig {
+    pub rules: Vec<DestinationRule>,
+    /// 没有规则命中时的动作
+    pub default_action: DestinationAction,
+}
+
+/// 各项条件都满足时命中，为空的条件不限
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DestinationRule {
+    /// 按用户填写的主机名匹配的通配符
+    pub hosts: Vec<String>,
+    /// 解析后的地址所在网段，如 `10.0.0.0/8`、`fd00::/8`，也可以是单个地址
+    pub cidrs: Vec<String>,
+    pub ports: Vec<PortSpec>,
+    pub action: DestinationAction,
+}
+
+/// 单个端口或 `"起-止"` 范围
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PortSpec {
+    Port(u16),
+    Range(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DestinationAction {
+    #[default]
+    Allow,
+    Deny,
+}
+
+/// 服务端加密保存的主机密码、私钥和AI API Key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VaultConfig {
+    pub enabled: bool,
+    /// 加密后的凭据文件，不能放在会被直接提供给浏览器的 static 目录下
+    pub path: String,
+    /// 主密钥所在的环境变量，值为 base64 编码的 32 字节 (`ssh-ai-terminal gen-vault-key` 生成)
+    pub key_env: String,
+    /// 环境变量未设置时从该文件读取主密钥
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<String>,
+    /// 轮换前的旧密钥文件，启动时用当前密钥重新加密仍由旧密钥加密的凭据
+    pub previous_key_files: Vec<String>,
+}
+
+impl Default for VaultConfig {
+    fn default() -> Self {
+        VaultConfig {
+            enabled: false,
+            path: "data/vault.json".to_string(),
+            key_env: "SSH_AI_TERMINAL_VAULT_KEY".to_string(),
+            key_file: None,
+            previous_key_files: Vec::new(),
+        }
+    }
+}
+
+/// Web界面登录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    pub username: String,
+    /// argon2 哈希 (`ssh-ai-terminal hash-password` 生成)，兼容明文但启动时会告警
+    pub password: String,
+    /// 登录会话的空闲过期时间 (秒)
+    pub session_timeout: u64,
+    /// 连续失败多少次后锁定来源地址
+    pub max_attempts: u32,
+    /// 锁定时长 (秒)
+    pub lockout_duration: u64,
+    /// 其它账户；上面 username/password 对应的账户始终是 admin
+    pub users: Vec<UserConfig>,
+    /// 主机组名到主机通配符列表，规则中以 `@组名` 引用
+    pub host_groups: BTreeMap<String, Vec<String>>,
+    /// operator 可以连接的主机，按用户或用户组授予
+    pub rules: Vec<AccessRule>,
+    /// 单点登录，与本地账户并存
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oidc: Option<OidcConfig>,
+    /// 本地账户的两步验证
+    pub totp: TotpConfig,
+    /// 供脚本使用的个人API令牌
+    pub api_tokens: ApiTokenConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiTokenConfig {
+    /// 保存令牌哈希的文件
+    pub store: String,
+    /// 最长有效期 (秒)，签发时未指定有效期则使用该值；0 表示允许永不过期
+    pub max_ttl: u64,
+}
+
+impl Default for ApiTokenConfig {
+    fn default() -> Self {
+        ApiTokenConfig {
+            store: "data/api_tokens.json".to_string(),
+            max_ttl: 90 * 86400,
+        }
+    }
+}
+
+/// TOTP 两步验证 (RFC 6238)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TotpConfig {
+    /// 显示在验证器应用中的发行方名称
+    pub issuer: String,
+    /// 为 true 时 admin 和 operator 必须启用两步验证，未绑定的账户登录时先绑定
+    ///
+    /// 只约束本地账户：单点登录 (`oidc`) 不经过这里的检查，多因素认证需要在 IdP 中强制。
+    pub required: bool,
+    /// 允许的时间偏差 (30 秒步长的个数)
+    pub skew: u64,
+    /// 保存各账户密钥和恢复码的文件
+    pub store: String,
+}
+
+impl Default for TotpConfig {
+    fn default() -> Self {
+        TotpConfig {
+            issuer: "SSH AI Terminal".to_string(),
+            required: false,
+            skew: 1,
+            store: "data/totp.json".to_string(),
+        }
+    }
+}
+
+impl TotpConfig {
+    /// 能打开可写会话的角色在启用强制策略后必须使用两步验证
+    pub fn required_for(&self, role: UserRole) -> bool {
+        self.required && role != UserRole::Viewer
+    }
+}
+
+/// OpenID Connect 授权码登录 (PKCE)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OidcConfig {
+    /// 通过 `{issuer}/.well-known/openid-configuration` 发现各端点
+    pub issuer: String,
+    pub client_id: String,
+    /// 公开客户端可不设置；设置后同时用于校验 HS256 签名的 ID Token
+    pub client_secret: Option<String>,
+    /// 指向 `/api/auth/oidc/callback` 的完整地址
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    /// 作为用户名的声明，默认 sub，缺失时同样使用 sub
+    ///
+    /// 用户名决定角色规则、令牌和审计归属，应选 IdP 中唯一且不可修改的声明；
+    /// `preferred_username`、`email` 等通常可由用户自行修改或被重新分配，不建议使用。
+    pub username_claim: String,
+    pub groups_claim: String,
+    /// IdP 组名到角色，命中多个时取权限最高的
+    pub role_mapping: BTreeMap<String, UserRole>,
+    /// 没有命中任何组时的角色，不设置则拒绝登录
+    pub default_role: Option<UserRole>,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        OidcConfig {
+            issuer: String::new(),
+            client_id: String::new(),
+            client_secret: None,
+            redirect_uri: String::new(),
+            scopes: vec!["openid".to_string(), "profile".to_string(), "email".to_string()],
+            username_claim: "sub".to_string(),
+            groups_claim: "groups".to_string(),
+            role_mapping: BTreeMap::new(),
+            default_role: None,
+        }
+    }
+}
+
+/// 账户角色，按权限从高到低排列
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    /// 不受主机规则限制，可使用本地终端
+    Admin,
+    /// 只能连接规则允许的主机
+    #[default]
+    Operator,
+    /// 只能查看分享给自己的会话
+    Viewer,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserConfig {
+    pub username: String,
+    /// argon2 哈希，兼容明文
+    pub password: String,
+    #[serde(default)]
+    pub role: UserRole,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessRule {
+    pub users: Vec<String>,
+    pub groups: Vec<String>,
+    /// 主机通配符或 `@主机组`
+    pub hosts: Vec<String>,
+    /// 是否允许自动执行AI建议的命令
+    pub ai_auto_execute: bool,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            enabled: false,
+            username: String::new(),
+            password: String::new(),
+            session_timeout: 3600,
+            max_attempts: 5,
+            lockout_duration: 300,
+            users: Vec::new(),
+            host_groups: BTreeMap::new(),
+            rules: Vec::new(),
+            oidc: None,
+            totp: TotpConfig::default(),
+            api_tokens: ApiTokenConfig::default(),
+        }
+    }
+}
+
+impl AuthConfig {
+    fn validate(&self) -> Result<()> {
+        if self.enabled && self.oidc.is_none() && (self.username.is_empty() || self.password.is_empty()) {
+            bail!("auth.enabled 为 true 时必须设置 username 和 password");
+        }
+        if let Some(oidc) = &self.oidc {
+            if oidc.issuer.is_empty() || oidc.client_id.is_empty() || oidc.redirect_uri.is_empty() {
+                bail!("auth.oidc 必须设置 issuer、client_id 和 redirect_uri");
+            }
+            if !oidc.scopes.iter().any(|s| s == "openid") {
+                bail!("auth.oidc.scopes 必须包含 openid");
+            }
+        }
+        if self.enabled && (self.session_timeout == 0 || self.max_attempts == 0) {
+            bail!("auth.session_timeout 和 auth.max_attempts 必须大于0");
+        }
+        if self.totp.issuer.is_empty() || self.totp.store.is_empty() {
+            bail!("auth.totp.issuer 和 auth.totp.store 不能为空");
+        }
+        if self.api_tokens.store.is_empty() {
+            bail!("auth.api_tokens.store 不能为空");
+        }
+        let mut names = std::collections::HashSet::new();
+        names.insert(self.username.as_str());
+        for user in &self.users {
+            if user.username.is_empty() || user.password.is_empty() {
+                bail!("auth.users 中的账户必须设置 username 和 password");
+            }
+            if !names.insert(user.username.as_str()) {
+                bail!("auth.users 中存在重复的用户名: {}", user.username);
+            }
+        }
+        for host in self.rules.iter().flat_map(|r| &r.hosts) {
+            if let Some(group) = host.strip_prefix('@') {
+                if !self.host_groups.contains_key(group) {
+                    bail!("auth.rules 引用了不存在的主机组: {}", group);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// 启动时校验配置，拒绝无法生效的取值
+    pub fn validate(&self) -> Result<()> {
+        self.ssh.validate()?;
+        self.auth.validate()?;
+        for (index, rule) in self.policy.rules.iter().enumerate() {
+            if rule.commands.is_empty() && rule.pattern.is_none() {
+                bail!("policy.rules[{}] 必须设置 commands 或 pattern", index);
+            }
+            if let Some(group) = rule.hosts.iter().find_map(|h| h.strip_prefix('@')) {
+                if !self.auth.host_groups.contains_key(group) {
+                    bail!("policy.rules[{}] 引用了不存在的主机组: {}", index, group);
+                }
+            }
+        }
+        let rate = &self.performance.rate_limit;
+        if rate.enabled && (rate.window == 0 || [Some(rate.max_requests), rate.ai_chat, rate.connect].contains(&Some(0))) {
+            bail!("performance.rate_limit 的 window 和各项次数必须大于 0");
+        }
+        let tls = &self.server.tls;
+        if tls.enabled && (tls.cert_path.is_empty() || tls.key_path.is_empty()) {
+            bail!("server.tls.enabled 为 true 时必须设置 cert_path 和 key_path");
+        }
+        if tls.enabled && tls.redirect_http_port == Some(self.server.port) {
+            bail!("server.tls.redirect_http_port 不能与 server.port 相同");
+        }
+        if self.vault.enabled && self.vault.path.is_empty() {
+            bail!("vault.path 不能为空");
+        }
+        if self.vault.enabled && Path::new(self.vault.path.trim_start_matches("./")).starts_with("static") {
+            bail!("vault.path 不能位于 static 目录下");
+        }
+        if self.policy.approval.timeout == 0 {
+            bail!("policy.approval.timeout 必须大于 0");
+        }
+        Ok(())
+    }
+}
+
+/// 简单的通配符匹配，`*` 匹配任意长度，`?` 匹配单个字符，忽略大小写
+pub fn matches_pattern(pattern: &str, value: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let v: Vec<char> = value.to_lowercase().chars().collect();
+    let (mut pi, mut vi) = (0, 0);
+    let (mut star, mut mark) = (None, 0);
+
+    while vi < v.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == v[vi]) {
+            pi += 1;
+            vi += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some(pi);
+            mark = vi;
+            pi += 1;
+        } else if let Some(s) = star {
+            pi = s + 1;
+            mark += 1;
+            vi = mark;
+        } else {
+            return false;
+        }
+    }
+
+    p[pi..].iter().all(|&c| c == '*')
 }
 
 #[derive(Debug)]
@@ -68,6 +851,8 @@ impl ConfigManager {
                     port: 8080,
                     address: "127.0.0.1".to_string(),
                     log_level: "info".to_string(),
+                    tls: TlsConfig::default(),
+                    websocket: WebSocketConfig::default(),
                 },
                 database: DatabaseConfig {
                     path: "data/db".to_string(),
@@ -82,7 +867,18 @@ impl ConfigManager {
                     providers: vec!["openai".to_string(), "anthropic".to_string()],
                     timeout: 30, // 30 seconds
                     retry_count: 3,
+                    redaction: RedactionConfig::default(),
                 },
+                ssh: SSHConfig::default(),
+                local_shell: LocalShellConfig::default(),
+                share: ShareConfig::default(),
+                session: SessionConfig::default(),
+                auth: AuthConfig::default(),
+                audit: AuditConfig::default(),
+                policy: PolicyConfig::default(),
+                performance: PerformanceConfig::default(),
+                destinations: DestinationConfig::default(),
+                vault: VaultConfig::default(),
             })
         }
     }
@@ -111,3 +907,62 @@ impl ConfigManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_patterns() {
+        assert!(matches_pattern("*", "anything"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("web-*.example.com", "web-01.example.com"));
+        assert!(matches_pattern("WEB-?.example.com", "web-1.Example.com"));
+        assert!(matches_pattern("10.0.*.*", "10.0.3.17"));
+        assert!(matches_pattern("*.example.com*", "a.example.com:22"));
+        assert!(!matches_pattern("web-?.example.com", "web-10.example.com"));
+        assert!(!matches_pattern("web-*.example.com", "db-1.example.com"));
+        assert!(!matches_pattern("*.example.com", "example.com"));
+        assert!(!matches_pattern("", "host"));
+    }
+
+    #[test]
+    fn first_matching_host_override_wins() {
+        let algorithms = |cipher: Option<&str>, mac: Option<&str>| SSHAlgorithms {
+            cipher: cipher.map(str::to_string),
+            mac: mac.map(str::to_string),
+            ..Default::default()
+        };
+        let config = SSHConfig {
+            keep_alive: 60,
+            compression: false,
+            algorithms: algorithms(Some("aes256-ctr"), Some("hmac-sha2-256")),
+            hosts: vec![
+                SSHHostOverride {
+                    host: "legacy-*".to_string(),
+                    algorithms: algorithms(Some("aes128-cbc"), None),
+                    compression: Some(true),
+                    keep_alive: None,
+                },
+                SSHHostOverride {
+                    host: "legacy-switch".to_string(),
+                    algorithms: algorithms(Some("3des-cbc"), Some("hmac-sha1")),
+                    compression: None,
+                    keep_alive: Some(0),
+                },
+            ],
+            ..Default::default()
+        };
+
+        // 两项都匹配时只有第一项生效，未设置的字段继承全局值
+        let options = config.options_for("legacy-switch");
+        assert_eq!(options.algorithms.cipher.as_deref(), Some("aes128-cbc"));
+        assert_eq!(options.algorithms.mac.as_deref(), Some("hmac-sha2-256"));
+        assert!(options.compression);
+        assert_eq!(options.keep_alive, 60);
+
+        let options = config.options_for("web-1");
+        assert_eq!(options.algorithms.cipher.as_deref(), Some("aes256-ctr"));
+        assert!(!options.compression);
+    }
+}
@@ -0,0 +1,147 @@
+use metrics::counter;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use thiserror::Error;
+use tracing::warn;
+use warp::http::{header, StatusCode};
+use warp::{Filter, Reply};
+
+use crate::audit::{self, AuditEvent};
+use crate::auth::SESSION_COOKIE;
+use crate::config::{matches_pattern, WebSocketConfig};
+use crate::{tls, AppState};
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpgradeError {
+    #[error("不允许来自该 Origin 的 WebSocket 连接")]
+    OriginNotAllowed,
+    #[error("缺少 WebSocket 令牌，请先请求 /api/auth/ws-token")]
+    MissingToken,
+    #[error("WebSocket 令牌无效、已使用或已过期")]
+    InvalidToken,
+}
+
+impl warp::reject::Reject for UpgradeError {}
+
+impl UpgradeError {
+    fn code(&self) -> &'static str {
+        match self {
+            UpgradeError::OriginNotAllowed => "origin_not_allowed",
+            UpgradeError::MissingToken => "missing_csrf_token",
+            UpgradeError::InvalidToken => "invalid_csrf_token",
+        }
+    }
+
+    pub fn reply(&self) -> warp::reply::Response {
+        let body = warp::reply::json(&serde_json::json!({ "error": self.to_string(), "code": self.code() }));
+        warp::reply::with_status(body, StatusCode::FORBIDDEN).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenQuery {
+    csrf: Option<String>,
+}
+
+/// 在 `/ws` 升级前检查 Origin 和一次性令牌，防止跨站页面借用登录 Cookie 打开终端
+///
+/// 放在登录校验之后，被拒绝的请求写入日志和审计日志。
+pub fn guard(state: AppState) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>(header::ORIGIN.as_str())
+        .and(warp::header::optional::<String>(header::HOST.as_str()))
+        .and(warp::header::optional::<String>(header::AUTHORIZATION.as_str()))
+        .and(warp::cookie::optional(SESSION_COOKIE))
+        .and(warp::query::<TokenQuery>())
+        .and(tls::remote())
+        .and_then(
+            move |origin: Option<String>,
+                  host: Option<String>,
+                  authorization: Option<String>,
+                  session: Option<String>,
+                  query: TokenQuery,
+                  remote: Option<SocketAddr>| {
+                let state = state.clone();
+                async move {
+                    let bearer = authorization.is_some_and(|value| value.starts_with("Bearer "));
+                    let result = check_origin(&state.config.server.websocket, origin.as_deref(), host.as_deref())
+                        .and_then(|()| check_token(&state, bearer, session.as_deref(), query.csrf.as_deref()));
+                    result.map_err(|e| {
+                        let source = remote.map(|addr| addr.ip().to_string());
+                        warn!(origin = ?origin, source = ?source, "WebSocket upgrade rejected: {}", e.code());
+                        counter!("ws_upgrades_rejected_total", 1, "reason" => e.code());
+                        audit::record(None, None, AuditEvent::UpgradeRejected {
+                            reason: e.code().to_string(),
+                            origin,
+                            source,
+                        });
+                        warp::reject::custom(e)
+                    })
+                }
+            },
+        )
+        .untuple_one()
+}
+
+/// 浏览器发起的升级总会带上 Origin，命令行客户端一般不带
+fn check_origin(config: &WebSocketConfig, origin: Option<&str>, host: Option<&str>) -> Result<(), UpgradeError> {
+    let Some(origin) = origin else {
+        return Ok(());
+    };
+    let allowed = if config.allowed_origins.is_empty() {
+        // 只比较主机和端口，TLS 可能由前面的反向代理终止
+        match (origin.split_once("://"), host) {
+            (Some((_, authority)), Some(host)) => authority.eq_ignore_ascii_case(host),
+            _ => false,
+        }
+    } else {
+        config.allowed_origins.iter().any(|pattern| matches_pattern(pattern, origin))
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(UpgradeError::OriginNotAllowed)
+    }
+}
+
+/// 使用登录 Cookie 的连接必须出示签发给同一登录会话的令牌
+fn check_token(state: &AppState, bearer: bool, session: Option<&str>, token: Option<&str>) -> Result<(), UpgradeError> {
+    // API令牌在请求头中，跨站页面无法附带；没有 Cookie 的请求已被登录校验拒绝
+    let Some(session) = session.filter(|_| !bearer) else {
+        return Ok(());
+    };
+    if !state.auth.enabled() || !state.config.server.websocket.require_csrf_token {
+        return Ok(());
+    }
+    let token = token.ok_or(UpgradeError::MissingToken)?;
+    if state.auth.consume_ws_token(token, session) {
+        Ok(())
+    } else {
+        Err(UpgradeError::InvalidToken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_must_match_host_or_allow_list() {
+        let same_origin = WebSocketConfig::default();
+        let host = Some("terminal.example.com:8443");
+        assert_eq!(check_origin(&same_origin, Some("https://terminal.example.com:8443"), host), Ok(()));
+        assert_eq!(check_origin(&same_origin, None, host), Ok(()));
+        for origin in ["https://evil.example.com", "https://terminal.example.com", "null"] {
+            assert_eq!(check_origin(&same_origin, Some(origin), host), Err(UpgradeError::OriginNotAllowed), "{}", origin);
+        }
+
+        let listed = WebSocketConfig {
+            allowed_origins: vec!["https://*.corp.example.com".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(check_origin(&listed, Some("https://ops.corp.example.com"), host), Ok(()));
+        assert_eq!(
+            check_origin(&listed, Some("https://terminal.example.com:8443"), host),
+            Err(UpgradeError::OriginNotAllowed)
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::config::{matches_pattern, DestinationAction, DestinationConfig, PortSpec};
+
+#[derive(Error, Debug)]
+pub enum DestinationError {
+    #[error("解析地址失败: {0}")]
+    Resolve(String),
+    #[error("目标 {host}:{port} 解析为 {addr}，不在允许连接的范围内")]
+    Denied {
+        host: String,
+        port: u16,
+        addr: IpAddr,
+        /// 命中的规则序号，为空表示没有规则命中、按 default_action 拒绝
+        rule: Option<usize>,
+    },
+}
+
+/// 网段，如 `10.0.0.0/8`，不带前缀长度时表示单个地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = addr.trim().parse().map_err(|_| format!("无效的地址: {}", s))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse::<u8>().ok().filter(|p| *p <= max).ok_or_else(|| format!("无效的前缀长度: {}", s))?,
+            None => max,
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl Cidr {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+fn parse_ports(spec: &PortSpec) -> Result<(u16, u16), String> {
+    match spec {
+        PortSpec::Port(port) => Ok((*port, *port)),
+        PortSpec::Range(range) => {
+            let parsed = match range.split_once('-') {
+                Some((start, end)) => start.trim().parse().ok().zip(end.trim().parse().ok()),
+                None => range.trim().parse().ok().map(|port| (port, port)),
+            };
+            parsed.filter(|(start, end)| start <= end).ok_or_else(|| format!("无效的端口范围: {}", range))
+        }
+    }
+}
+
+struct Rule {
+    hosts: Vec<String>,
+    cidrs: Vec<Cidr>,
+    ports: Vec<(u16, u16)>,
+    action: DestinationAction,
+}
+
+impl Rule {
+    fn matches(&self, host: &str, ip: IpAddr, port: u16) -> bool {
+        (self.hosts.is_empty() || self.hosts.iter().any(|pattern| matches_pattern(pattern, host)))
+            && (self.cidrs.is_empty() || self.cidrs.iter().any(|cidr| cidr.contains(ip)))
+            && (self.ports.is_empty() || self.ports.iter().any(|(start, end)| (*start..=*end).contains(&port)))
+    }
+}
+
+/// 启动时编译好的 `destinations` 规则
+pub struct Destinations {
+    rules: Vec<Rule>,
+    default_action: DestinationAction,
+}
+
+impl Destinations {
+    pub fn new(config: &DestinationConfig) -> anyhow::Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| {
+                let invalid = |e: String| anyhow::anyhow!("destinations.rules[{}] {}", index, e);
+                Ok(Rule {
+                    hosts: rule.hosts.clone(),
+                    cidrs: rule.cidrs.iter().map(|c| c.parse()).collect::<Result<_, _>>().map_err(invalid)?,
+                    ports: rule.ports.iter().map(parse_ports).collect::<Result<_, _>>().map_err(invalid)?,
+                    action: rule.action,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Destinations { rules, default_action: config.default_action })
+    }
+
+    /// 单个解析后地址的判定结果及命中的规则
+    fn evaluate(&self, host: &str, ip: IpAddr, port: u16) -> (DestinationAction, Option<usize>) {
+        match self.rules.iter().position(|rule| rule.matches(host, ip, port)) {
+            Some(index) => (self.rules[index].action, Some(index)),
+            None => (self.default_action, None),
+        }
+    }
+
+    /// 解析主机名并检查每个地址，任何一个地址被拒绝则整个目标被拒绝
+    ///
+    /// 调用方必须连接返回的地址而不是再次解析，避免两次解析结果不同。
+    pub async fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, DestinationError> {
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|e| DestinationError::Resolve(format!("{}: {}", host, e)))?
+            .collect();
+        if addrs.is_empty() {
+            return Err(DestinationError::Resolve(format!("无法解析地址: {}", host)));
+        }
+        for addr in &addrs {
+            if let (DestinationAction::Deny, rule) = self.evaluate(host, addr.ip(), port) {
+                return Err(DestinationError::Denied { host: host.to_string(), port, addr: addr.ip(), rule });
+            }
+        }
+        Ok(addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DestinationRule;
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let rule = |hosts: &[&str], cidrs: &[&str], ports: Vec<PortSpec>, action| DestinationRule {
+            hosts: hosts.iter().map(|h| h.to_string()).collect(),
+            cidrs: cidrs.iter().map(|c| c.to_string()).collect(),
+            ports,
+            action,
+        };
+        let destinations = Destinations::new(&DestinationConfig {
+            rules: vec![
+                rule(&[], &["169.254.0.0/16", "127.0.0.0/8", "::1"], vec![], DestinationAction::Deny),
+                rule(&["*.prod.example.com"], &[], vec![PortSpec::Port(22)], DestinationAction::Allow),
+                rule(&[], &["10.0.0.0/8"], vec![PortSpec::Port(22), PortSpec::Range("2200-2299".to_string())], DestinationAction::Allow),
+            ],
+            default_action: DestinationAction::Deny,
+        })
+        .unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        assert_eq!(destinations.evaluate("web-1.prod.example.com", ip("203.0.113.5"), 22), (DestinationAction::Allow, Some(1)));
+        assert_eq!(destinations.evaluate("web-1.prod.example.com", ip("203.0.113.5"), 3306), (DestinationAction::Deny, None));
+        assert_eq!(destinations.evaluate("10.1.2.3", ip("10.1.2.3"), 2222), (DestinationAction::Allow, Some(2)));
+        assert_eq!(destinations.evaluate("10.1.2.3", ip("10.1.2.3"), 2300), (DestinationAction::Deny, None));
+        // 名称匹配但解析到元数据服务或回环地址
+        assert_eq!(destinations.evaluate("x.prod.example.com", ip("169.254.169.254"), 22), (DestinationAction::Deny, Some(0)));
+        assert_eq!(destinations.evaluate("localhost", ip("::ffff:127.0.0.1"), 22), (DestinationAction::Deny, Some(0)));
+        assert_eq!(destinations.evaluate("localhost", ip("::1"), 22), (DestinationAction::Deny, Some(0)));
+
+        assert!(Destinations::new(&DestinationConfig {
+            rules: vec![rule(&[], &["10.0.0.0/33"], vec![], DestinationAction::Deny)],
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
@@ -148,8 +148,15 @@ async fn persist_config(config: &ConfigData) -> Result<(), std::io::Error> {
     let config_dir = "static/configs";
     fs::create_dir_all(config_dir).await?;
     
+    // static 目录会被直接提供给浏览器，密码和API Key只能保存在凭据库 (vault) 中
+    let mut config = config.clone();
+    config.password = None;
+    if let Some(ai_config) = config.ai_config.as_mut() {
+        ai_config.api_key.clear();
+    }
+
     let file_path = format!("{}/{}.json", config_dir, config.id);
-    let json = serde_json::to_string_pretty(config)?;
+    let json = serde_json::to_string_pretty(&config)?;
     
     let mut file = fs::File::create(file_path).await?;
     file.write_all(json.as_bytes()).await?;
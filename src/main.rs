@@ -1,7 +1,8 @@
+#![recursion_limit = "256"]
+
 use std::sync::Arc;
 use std::net::SocketAddr;
-use warp::Filter;
-use tokio::sync::Mutex;
+use warp::{Filter, Reply};
 use dashmap::DashMap;
 use uuid::Uuid;
 use tracing::{info, error};
@@ -9,18 +10,79 @@ use tracing::{info, error};
 mod models;
 mod ssh;
 mod websocket;
+#[allow(clippy::single_component_path_imports)]
 mod ai;
 mod config;
+mod terminal;
+mod pty;
+mod telnet;
+mod session;
+mod share;
+mod broadcast;
+mod auth;
+mod rbac;
+mod oidc;
+mod totp;
+mod api_token;
+mod audit;
+mod policy;
+mod approval;
+mod risk;
+mod redact;
+mod ratelimit;
+mod tls;
+mod csrf;
+mod destination;
+mod vault;
 
 use models::*;
 use websocket::handle_websocket;
+use api_token::TokenScope;
+use config::{PolicyAction, UserRole};
+use session::SharedSession;
+
+type Sessions = Arc<DashMap<Uuid, Arc<session::SharedSession>>>;
+type Connections = Arc<DashMap<Uuid, Arc<ssh::SSHConnection>>>;
 
-type Sessions = Arc<DashMap<Uuid, Arc<Mutex<ssh::SSHSession>>>>;
+/// 各个路由共享的服务端状态
+#[derive(Clone)]
+pub struct AppState {
+    pub sessions: Sessions,
+    pub connections: Connections,
+    pub config: Arc<config::Config>,
+    pub share: Arc<share::ShareSigner>,
+    pub auth: Arc<auth::Auth>,
+    pub oidc: Option<Arc<oidc::Oidc>>,
+    pub policy: Arc<policy::Policy>,
+    pub approvals: Arc<approval::Approvals>,
+    pub redactor: Arc<redact::Redactor>,
+    pub limits: Arc<ratelimit::Limits>,
+    pub destinations: Arc<destination::Destinations>,
+    /// 未启用 `vault` 时为空
+    pub vault: Option<Arc<vault::Vault>>,
+}
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
+    // 生成 auth.password 使用的哈希: echo -n 'password' | ssh-ai-terminal hash-password
+    if std::env::args().nth(1).as_deref() == Some("hash-password") {
+        let mut password = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut password) {
+            error!("Failed to read password: {}", e);
+            return;
+        }
+        println!("{}", auth::hash_password(password.trim_end_matches(['\r', '\n'])));
+        return;
+    }
+
+    // 生成凭据库的主密钥: ssh-ai-terminal gen-vault-key > vault.key
+    if std::env::args().nth(1).as_deref() == Some("gen-vault-key") {
+        println!("{}", vault::generate_key());
+        return;
+    }
+
     // Load configuration
     let config_manager = match config::ConfigManager::new("config.json").await {
         Ok(cm) => cm,
@@ -37,51 +99,458 @@ async fn main() {
             return;
         }
     };
+    if let Err(e) = config.validate() {
+        error!("Invalid configuration: {}", e);
+        return;
+    }
+
+    // 校验审计日志的哈希链: ssh-ai-terminal verify-audit [path]
+    if std::env::args().nth(1).as_deref() == Some("verify-audit") {
+        let path = std::env::args().nth(2).unwrap_or_else(|| config.audit.path.clone());
+        match audit::verify(std::path::Path::new(&path)) {
+            Ok(verified) => println!("OK: {} entries, last hash {}", verified.entries, verified.last_hash),
+            Err(e) => {
+                println!("FAILED: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if config.audit.enabled {
+        match audit::AuditLog::open(&config.audit) {
+            Ok(log) => audit::install(log),
+            Err(e) => {
+                error!("Failed to open audit log: {}", e);
+                return;
+            }
+        }
+    }
+
+    let metrics = match metrics_exporter_prometheus::PrometheusBuilder::new().install_recorder() {
+        Ok(handle) => handle,
+        Err(e) => {
+            error!("Failed to install metrics recorder: {}", e);
+            return;
+        }
+    };
+    init_metrics();
+
     let port = config.server.port;
-    let address = config.server.address;
+    let address = config.server.address.clone();
+    let config = Arc::new(config);
 
-    let sessions: Sessions = Arc::new(DashMap::new());
+    let auth = match auth::Auth::new(&config.auth) {
+        Ok(auth) => Arc::new(auth),
+        Err(e) => {
+            error!("Invalid auth configuration: {}", e);
+            return;
+        }
+    };
+
+    let policy = match policy::Policy::new(&config.policy, &config.auth.host_groups) {
+        Ok(policy) => Arc::new(policy),
+        Err(e) => {
+            error!("Invalid command policy: {}", e);
+            return;
+        }
+    };
+
+    let redactor = match redact::Redactor::new(&config.ai.redaction) {
+        Ok(redactor) => Arc::new(redactor),
+        Err(e) => {
+            error!("Invalid redaction config: {}", e);
+            return;
+        }
+    };
+
+    let destinations = match destination::Destinations::new(&config.destinations) {
+        Ok(destinations) => Arc::new(destinations),
+        Err(e) => {
+            error!("Invalid destinations config: {}", e);
+            return;
+        }
+    };
+
+    let vault = match config.vault.enabled.then(|| vault::Vault::open(&config.vault)).transpose() {
+        Ok(vault) => vault.map(Arc::new),
+        Err(e) => {
+            error!("Invalid vault configuration: {}", e);
+            return;
+        }
+    };
+
+    let tls_acceptor = match config.server.tls.enabled.then(|| tls::TlsAcceptor::new(&config.server.tls)).transpose() {
+        Ok(acceptor) => acceptor,
+        Err(e) => {
+            error!("Invalid TLS configuration: {:#}", e);
+            return;
+        }
+    };
+
+    let state = AppState {
+        sessions: Arc::new(DashMap::new()),
+        connections: Arc::new(DashMap::new()),
+        share: Arc::new(share::ShareSigner::new(config.share.secret.as_deref())),
+        auth,
+        oidc: config.auth.oidc.clone().map(|c| Arc::new(oidc::Oidc::new(c))),
+        policy,
+        approvals: Arc::new(approval::Approvals::new(config.policy.approval.clone())),
+        redactor,
+        limits: Arc::new(ratelimit::Limits::new(&config.performance, &config.ssh)),
+        destinations,
+        vault,
+        config,
+    };
 
     let static_files = warp::fs::dir("static");
 
     let ws_route = warp::path("ws")
+        .and(auth::authorized(state.clone(), TokenScope::Exec).or(websocket::share_guest(state.clone())).unify())
+        .and(csrf::guard(state.clone()))
         .and(warp::ws())
-        .and(with_sessions(sessions.clone()))
+        .and(warp::query::<websocket::WsQuery>())
+        .and(tls::remote())
+        .and(with_state(state.clone()))
         .and_then(handle_websocket);
 
+    let login_route = warp::path!("api" / "auth" / "login")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(tls::remote())
+        .and(with_state(state.clone()))
+        .and_then(auth::handle_login);
+
+    let second_factor_route = warp::path!("api" / "auth" / "login" / "totp")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(tls::remote())
+        .and(with_state(state.clone()))
+        .and_then(auth::handle_second_factor);
+
+    let totp_status_route = warp::path!("api" / "auth" / "totp")
+        .and(warp::get())
+        .and(auth::authenticated(state.clone()))
+        .and(with_state(state.clone()))
+        .and_then(totp::handle_status);
+
+    let totp_enroll_route = warp::path!("api" / "auth" / "totp" / "enroll")
+        .and(warp::post())
+        .and(auth::authenticated(state.clone()))
+        .and(with_state(state.clone()))
+        .and_then(totp::handle_enroll);
+
+    let totp_activate_route = warp::path!("api" / "auth" / "totp" / "activate")
+        .and(warp::post())
+        .and(auth::authenticated(state.clone()))
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(totp::handle_activate);
+
+    let totp_recovery_route = warp::path!("api" / "auth" / "totp" / "recovery-codes")
+        .and(warp::post())
+        .and(auth::authenticated(state.clone()))
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(totp::handle_recovery_codes);
+
+    let totp_disable_route = warp::path!("api" / "auth" / "totp" / "disable")
+        .and(warp::post())
+        .and(auth::authenticated(state.clone()))
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(totp::handle_disable);
+
+    let logout_route = warp::path!("api" / "auth" / "logout")
+        .and(warp::post())
+        .and(warp::cookie::optional(auth::SESSION_COOKIE))
+        .and(with_state(state.clone()))
+        .and_then(auth::handle_logout);
+
+    let ws_token_route = warp::path!("api" / "auth" / "ws-token")
+        .and(warp::post())
+        .and(warp::cookie::optional(auth::SESSION_COOKIE))
+        .and(with_state(state.clone()))
+        .and_then(auth::handle_ws_token);
+
+    let methods_route = warp::path!("api" / "auth" / "methods")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .map(|state: AppState| warp::reply::json(&serde_json::json!({ "oidc": state.oidc.is_some() })));
+
+    let oidc_login_route = warp::path!("api" / "auth" / "oidc" / "login")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(oidc::handle_login);
+
+    let oidc_callback_route = warp::path!("api" / "auth" / "oidc" / "callback")
+        .and(warp::get())
+        .and(warp::query::<oidc::CallbackQuery>())
+        .and(oidc::state_cookie())
+        .and(with_state(state.clone()))
+        .and_then(oidc::handle_callback);
+
+    let session_route = warp::path!("api" / "auth" / "session")
+        .and(warp::get())
+        .and(auth::authenticated(state.clone()))
+        .and_then(auth::handle_session);
+
+    let tokens_route = warp::path!("api" / "tokens")
+        .and(warp::get())
+        .and(auth::authenticated(state.clone()))
+        .and(with_state(state.clone()))
+        .and_then(api_token::handle_list);
+
+    let create_token_route = warp::path!("api" / "tokens")
+        .and(warp::post())
+        .and(auth::authenticated(state.clone()))
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(api_token::handle_create);
+
+    let revoke_token_route = warp::path!("api" / "tokens" / Uuid)
+        .and(warp::delete())
+        .and(auth::authenticated(state.clone()))
+        .and(with_state(state.clone()))
+        .and_then(api_token::handle_revoke);
+
+    let credentials_route = warp::path!("api" / "credentials")
+        .and(warp::get())
+        .and(auth::authenticated(state.clone()))
+        .and(with_state(state.clone()))
+        .and_then(vault::handle_list);
+
+    let create_credential_route = warp::path!("api" / "credentials")
+        .and(warp::post())
+        .and(auth::authenticated(state.clone()))
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(vault::handle_create);
+
+    let delete_credential_route = warp::path!("api" / "credentials" / Uuid)
+        .and(warp::delete())
+        .and(auth::authenticated(state.clone()))
+        .and(with_state(state.clone()))
+        .and_then(vault::handle_delete);
+
+    let audit_route = warp::path!("api" / "audit")
+        .and(warp::get())
+        .and(auth::authenticated(state.clone()))
+        .and(warp::query::<audit::AuditQuery>())
+        .and(with_state(state.clone()))
+        .and_then(audit::handle_query);
+
+    let sessions_route = warp::path!("api" / "sessions")
+        .and(warp::get())
+        .and(auth::authorized(state.clone(), TokenScope::ReadSessions))
+        .and(with_state(state.clone()))
+        .map(list_sessions);
+
     let ai_route = warp::path!("api" / "ai" / "chat")
         .and(warp::post())
+        .and(auth::authorized(state.clone(), TokenScope::Ai))
         .and(warp::body::json())
-        .and(with_sessions(sessions.clone()))
+        .and(tls::remote())
+        .and(with_state(state.clone()))
         .and_then(handle_ai_chat);
 
+    // Prometheus metrics端点，会话和账户数量只对 admin 可见
+    let metrics_route = warp::path!("metrics")
+        .and(warp::get())
+        .and(auth::authorized(state.clone(), TokenScope::Metrics))
+        .map(move |identity: Option<auth::Identity>| {
+            if identity.is_some_and(|i| i.role != UserRole::Admin) {
+                return warp::reply::with_status("仅管理员可以查看监控指标".to_string(), warp::http::StatusCode::FORBIDDEN).into_response();
+            }
+            metrics.render().into_response()
+        });
+
     let routes = ws_route
+        .or(login_route)
+        .or(second_factor_route)
+        .or(totp_status_route)
+        .or(totp_enroll_route)
+        .or(totp_activate_route)
+        .or(totp_recovery_route)
+        .or(totp_disable_route)
+        .or(logout_route)
+        .or(ws_token_route)
+        .or(methods_route)
+        .or(oidc_login_route)
+        .or(oidc_callback_route)
+        .or(session_route)
+        .or(tokens_route)
+        .or(create_token_route)
+        .or(revoke_token_route)
+        .or(credentials_route)
+        .or(create_credential_route)
+        .or(delete_credential_route)
+        .or(sessions_route)
+        .or(audit_route)
         .or(ai_route)
-        .or(static_files);
+        .or(metrics_route)
+        .or(static_files)
+        .recover(handle_rejection);
 
     let addr: SocketAddr = format!("{}:{}", address, port).parse().unwrap();
-    info!("Server starting on {}", addr);
 
-    warp::serve(routes)
-        .run(addr)
-        .await;
+    match tls_acceptor {
+        Some(acceptor) => {
+            if let Some(http_port) = state.config.server.tls.redirect_http_port {
+                let redirect_addr: SocketAddr = format!("{}:{}", address, http_port).parse().unwrap();
+                info!("Redirecting HTTP on {} to HTTPS", redirect_addr);
+                tokio::spawn(warp::serve(tls::redirect(port, address.clone())).run(redirect_addr));
+            }
+            acceptor.spawn_reloader();
+            info!("Server starting on https://{}", addr);
+            if let Err(e) = tls::serve(routes, addr, acceptor).await {
+                error!("Failed to start HTTPS server: {}", e);
+            }
+        }
+        None => {
+            info!("Server starting on {}", addr);
+            warp::serve(routes)
+                .run(addr)
+                .await;
+        }
+    }
 }
 
-fn with_sessions(sessions: Sessions) -> impl Filter<Extract = (Sessions,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || sessions.clone())
+fn init_metrics() {
+    metrics::describe_counter!("websocket_connections_total", "Total WebSocket connections");
+    metrics::describe_gauge!("websocket_connections_active", "Active WebSocket connections");
+    metrics::describe_gauge!("sessions_active", "Open terminal sessions");
+    metrics::describe_gauge!("sessions_detached", "Sessions waiting for their owner to resume");
+    metrics::describe_counter!("sessions_detached_total", "Sessions kept open after their owner disconnected");
+    metrics::describe_counter!("sessions_resumed_total", "Detached sessions resumed by their owner");
+    metrics::describe_counter!("sessions_closed_total", "Closed terminal sessions by reason");
+    metrics::describe_counter!("session_participants_dropped_total", "Participants removed for falling behind session output");
+    metrics::describe_counter!("limits_rejected_total", "Requests rejected by rate limits and session quotas");
+    metrics::describe_counter!("tls_handshake_failures_total", "TLS handshakes that failed or timed out");
+    metrics::describe_counter!("ws_upgrades_rejected_total", "WebSocket upgrades rejected by the Origin or CSRF token check");
+    metrics::describe_counter!("tls_reloads_total", "TLS certificate reloads after the files changed");
+}
+
+fn with_state(state: AppState) -> impl Filter<Extract = (AppState,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || state.clone())
+}
+
+/// 登录失败、未登录和被拒绝的 WebSocket 升级返回 JSON 错误，其余拒绝交还 warp 默认处理
+async fn handle_rejection(rejection: warp::Rejection) -> Result<warp::reply::Response, warp::Rejection> {
+    if let Some(e) = rejection.find::<auth::AuthError>() {
+        return Ok(auth::rejection_reply(e));
+    }
+    match rejection.find::<csrf::UpgradeError>() {
+        Some(e) => Ok(e.reply()),
+        None => Err(rejection),
+    }
+}
+
+/// 当前账户拥有的会话，admin 可以看到全部
+fn list_sessions(identity: Option<auth::Identity>, state: AppState) -> impl warp::Reply {
+    let visible = |session: &SharedSession| match &identity {
+        Some(identity) if identity.role != UserRole::Admin => session.owner_user.as_ref() == Some(&identity.user),
+        _ => true,
+    };
+    let sessions: Vec<_> = state
+        .sessions
+        .iter()
+        .filter(|s| visible(s.value()))
+        .map(|s| s.summary())
+        .collect();
+    warp::reply::json(&sessions)
 }
 
 async fn handle_ai_chat(
-    request: AIRequest,
-    sessions: Sessions,
-) -> Result<impl warp::Reply, warp::Rejection> {
-    match ai::process_ai_request(request, sessions).await {
-        Ok(response) => Ok(warp::reply::json(&response)),
+    identity: Option<auth::Identity>,
+    mut request: AIRequest,
+    remote: Option<SocketAddr>,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let user = identity.as_ref().map(|i| i.user.as_str());
+    // 许可在请求处理完之前一直占用并发名额
+    let _permit = match state.limits.check_ai_chat(user, remote.map(|addr| addr.ip())) {
+        Ok(permit) => permit,
+        Err(e) => return Ok(e.reply()),
+    };
+    // 只有以 writer 或 owner 身份参与会话的用户才能针对该会话提问
+    let session = match request.session_id {
+        Some(id) => match state.sessions.get(&id).map(|s| s.clone()) {
+            Some(session) if identity.as_ref().is_none_or(|i| session.user_can_write(&i.user)) => Some(session),
+            _ => {
+                let body = warp::reply::json(&serde_json::json!({ "error": "无权在该会话中使用AI助手" }));
+                return Ok(warp::reply::with_status(body, warp::http::StatusCode::FORBIDDEN).into_response());
+            }
+        },
+        None => None,
+    };
+    if let Some(id) = request.ai_config.credential_id {
+        let revealed = match &state.vault {
+            Some(vault) => vault.reveal(identity.as_ref(), id, &[vault::CredentialKind::AiApiKey]),
+            None => Err(vault::VaultError::Disabled),
+        };
+        // API Key 只发往保存时绑定的接口地址
+        let revealed = revealed.and_then(|(info, secret)| match (info.endpoint, &request.ai_config.endpoint) {
+            (Some(bound), Some(requested)) if !requested.is_empty() && bound != *requested => {
+                Err(vault::VaultError::Invalid("该 API Key 只能用于保存时的接口地址".to_string()))
+            }
+            (Some(bound), _) => Ok((bound, secret)),
+            (None, _) => Err(vault::VaultError::Invalid("该 API Key 未绑定接口地址，请重新保存".to_string())),
+        });
+        match revealed {
+            Ok((endpoint, secret)) => {
+                request.ai_config.endpoint = Some(endpoint);
+                request.ai_config.api_key = secret.value;
+            }
+            Err(e) => return Ok(vault::error_reply(&e)),
+        }
+    }
+    let host = session.as_ref().and_then(|s| s.host.clone());
+    let auto_execute = session.is_some_and(|s| {
+        rbac::can_auto_execute(&state.config.auth, identity.as_ref(), s.host.as_deref())
+    });
+    let session_id = request.session_id;
+    // 审计日志中同样只保存遮盖后的提示词
+    let redactions = ai::redact_request(&mut request, &state.redactor);
+    audit::record(user, host.as_deref(), audit::AuditEvent::AiPrompt { session_id, prompt: request.message.clone() });
+    match ai::process_ai_request(request, state.sessions.clone()).await {
+        Ok(mut response) => {
+            response.redactions = redactions;
+            response.auto_execute = auto_execute;
+            response.risk = response.command.as_deref().map(risk::assess);
+            if response.risk.as_ref().is_some_and(|r| r.level > risk::RiskLevel::Low) {
+                response.auto_execute = false;
+            }
+            // 策略禁止的命令不返回给前端，需要确认或审批的命令不能自动执行
+            if let Some(command) = &response.command {
+                let verdict = state.policy.evaluate_script(identity.as_ref(), host.as_deref(), command);
+                let verdict = state.approvals.escalate(verdict, command);
+                if verdict.action != PolicyAction::Allow {
+                    audit::record(user, host.as_deref(), audit::AuditEvent::PolicyViolation {
+                        session_id,
+                        command: command.clone(),
+                        action: verdict.action,
+                        rule: verdict.rule,
+                    });
+                    if verdict.action == PolicyAction::Deny {
+                        response.command = None;
+                    }
+                    response.auto_execute = false;
+                    response.policy = Some(verdict);
+                }
+            }
+            audit::record(user, host.as_deref(), audit::AuditEvent::AiSuggestion {
+                session_id,
+                command: response.command.clone(),
+                auto_execute: response.auto_execute,
+            });
+            Ok(warp::reply::json(&response).into_response())
+        }
         Err(e) => {
             error!("AI request failed: {}", e);
             Ok(warp::reply::json(&serde_json::json!({
                 "error": e.to_string()
-            })))
+            })).into_response())
         }
     }
 }
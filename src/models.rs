@@ -1,38 +1,342 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::session::AccessError;
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(tag = "type")]
 pub enum WebSocketMessage {
     #[serde(rename = "connect")]
     Connect {
+        #[serde(default)]
+        protocol: Protocol,
+        #[serde(default)]
         host: String,
+        /// 为0时使用协议默认端口
+        #[serde(default)]
         port: u16,
+        #[serde(default)]
         username: String,
+        #[serde(default)]
         password: String,
+        /// 凭据库中保存的凭据，由服务端填入主机、用户名和密码或私钥
+        #[serde(default)]
+        credential_id: Option<Uuid>,
+        /// 覆盖配置中的 ssh.auto_reconnect
+        #[serde(default)]
+        auto_reconnect: Option<bool>,
+        #[serde(default)]
+        cols: Option<u16>,
+        #[serde(default)]
+        rows: Option<u16>,
     },
     #[serde(rename = "data")]
     Data {
         session_id: Uuid,
         data: String,
+        /// 前端自动执行AI建议的命令时置为 true
+        #[serde(default)]
+        auto_execute: bool,
     },
     #[serde(rename = "disconnect")]
     Disconnect {
         session_id: Uuid,
     },
+    #[serde(rename = "resize")]
+    Resize {
+        session_id: Uuid,
+        cols: u16,
+        rows: u16,
+    },
+    /// 在已有SSH连接上打开新的Shell通道，免去重复握手和认证
+    #[serde(rename = "open_channel")]
+    OpenChannel {
+        connection_id: Uuid,
+    },
+    /// 重新附加到自己(同一登录用户)的会话，他人的会话需通过分享链接加入
+    #[serde(rename = "attach")]
+    Attach {
+        session_id: Uuid,
+    },
+    /// WebSocket断开后在 session.resume_grace 内重新接管自己的会话
+    #[serde(rename = "resume")]
+    Resume {
+        session_id: Uuid,
+        #[serde(default)]
+        resume_token: Option<String>,
+    },
+    #[serde(rename = "detach")]
+    Detach {
+        session_id: Uuid,
+    },
+    /// 仅 owner 可用，授予或收回参与者的输入权限
+    #[serde(rename = "grant_control")]
+    GrantControl {
+        session_id: Uuid,
+        client_id: Uuid,
+    },
+    #[serde(rename = "revoke_control")]
+    RevokeControl {
+        session_id: Uuid,
+        client_id: Uuid,
+    },
+    #[serde(rename = "kick")]
+    Kick {
+        session_id: Uuid,
+        client_id: Uuid,
+    },
+    /// 生成限时分享链接，role 只能是 viewer 或 writer
+    #[serde(rename = "create_share_link")]
+    CreateShareLink {
+        session_id: Uuid,
+        #[serde(default)]
+        role: Option<Role>,
+        /// 有效期 (秒)，缺省使用 share.default_ttl
+        #[serde(default)]
+        ttl: Option<u64>,
+    },
+    #[serde(rename = "revoke_share_link")]
+    RevokeShareLink {
+        session_id: Uuid,
+        link_id: Uuid,
+    },
+    #[serde(rename = "list_share_links")]
+    ListShareLinks {
+        session_id: Uuid,
+    },
+    /// 把自己拥有的会话加入广播组，之后向其中任一会话输入都会写入组内所有会话；
+    /// group_id 为空时新建组
+    #[serde(rename = "broadcast_join")]
+    BroadcastJoin {
+        #[serde(default)]
+        group_id: Option<Uuid>,
+        session_ids: Vec<Uuid>,
+    },
+    #[serde(rename = "broadcast_leave")]
+    BroadcastLeave {
+        group_id: Uuid,
+        session_ids: Vec<Uuid>,
+    },
+    /// 回复 command_confirm，approve 为 false 时清掉该行
+    #[serde(rename = "confirm_command")]
+    ConfirmCommand {
+        session_id: Uuid,
+        confirm_id: Uuid,
+        approve: bool,
+    },
+    /// 审批人批准或拒绝另一位账户的命令；申请人对自己的申请发送 false 表示撤回
+    #[serde(rename = "approve_command")]
+    ApproveCommand {
+        approval_id: Uuid,
+        approve: bool,
+    },
+}
+
+/// 终端会话类型
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Ssh,
+    Telnet,
+    /// 网关本机Shell
+    Local,
+}
+
+impl Protocol {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Protocol::Ssh => 22,
+            Protocol::Telnet => 23,
+            Protocol::Local => 0,
+        }
+    }
+}
+
+/// 会话参与者的角色
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Owner,
+    Writer,
+    Viewer,
+}
+
+impl Role {
+    pub fn can_write(&self) -> bool {
+        matches!(self, Role::Owner | Role::Writer)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ParticipantInfo {
+    pub client_id: Uuid,
+    pub role: Role,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub via_link: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareLinkInfo {
+    pub link_id: Uuid,
+    pub role: Role,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub viewers: usize,
+}
+
+/// `/api/sessions` 返回的会话概要
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub session_id: Uuid,
+    pub host: Option<String>,
+    pub owner: Option<String>,
+    /// owner 已断线、等待恢复
+    pub detached: bool,
+    pub participants: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BroadcastFailure {
+    pub session_id: Uuid,
+    pub message: String,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
 pub enum WebSocketResponse {
+    /// 连接建立后发送，告知客户端自己的 client_id
+    #[serde(rename = "hello")]
+    Hello { client_id: Uuid },
     #[serde(rename = "connected")]
-    Connected { session_id: Uuid },
+    Connected {
+        session_id: Uuid,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        connection_id: Option<Uuid>,
+        /// 配置了 session.resume_grace 时返回，断线后用于 resume
+        #[serde(skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+    },
     #[serde(rename = "data")]
-    Data { data: String },
+    Data { session_id: Uuid, data: String },
     #[serde(rename = "error")]
     Error { message: String },
+    /// 对会话的操作未通过权限校验
+    #[serde(rename = "access_denied")]
+    AccessDenied {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session_id: Option<Uuid>,
+        code: AccessError,
+        message: String,
+    },
     #[serde(rename = "disconnected")]
-    Disconnected,
+    Disconnected { session_id: Uuid },
+    #[serde(rename = "status")]
+    Status {
+        session_id: Uuid,
+        connected: bool,
+        latency_ms: Option<u64>,
+    },
+    #[serde(rename = "attached")]
+    Attached {
+        session_id: Uuid,
+        role: Role,
+        participants: Vec<ParticipantInfo>,
+    },
+    #[serde(rename = "participant_joined")]
+    ParticipantJoined {
+        session_id: Uuid,
+        client_id: Uuid,
+        role: Role,
+    },
+    #[serde(rename = "participant_left")]
+    ParticipantLeft {
+        session_id: Uuid,
+        client_id: Uuid,
+    },
+    #[serde(rename = "role_changed")]
+    RoleChanged {
+        session_id: Uuid,
+        client_id: Uuid,
+        role: Role,
+    },
+    #[serde(rename = "share_link_created")]
+    ShareLinkCreated {
+        session_id: Uuid,
+        link: ShareLinkInfo,
+        token: String,
+        url: String,
+    },
+    #[serde(rename = "share_links")]
+    ShareLinks {
+        session_id: Uuid,
+        links: Vec<ShareLinkInfo>,
+    },
+    /// 广播组当前成员，组被清空时 session_ids 为空
+    #[serde(rename = "broadcast_group")]
+    BroadcastGroup {
+        group_id: Uuid,
+        session_ids: Vec<Uuid>,
+    },
+    /// 广播输入中写入失败的会话，其余会话不受影响
+    #[serde(rename = "broadcast_failed")]
+    BroadcastFailed {
+        group_id: Uuid,
+        failures: Vec<BroadcastFailure>,
+    },
+    /// 命令被服务端策略拦截，终端中的该行已被清除
+    #[serde(rename = "command_blocked")]
+    CommandBlocked {
+        session_id: Uuid,
+        command: String,
+        message: String,
+    },
+    /// 命令需要确认，回车暂不发送，等待 confirm_command
+    #[serde(rename = "command_confirm")]
+    CommandConfirm {
+        session_id: Uuid,
+        confirm_id: Uuid,
+        command: String,
+        message: String,
+    },
+    /// 命令需要另一位账户审批，已从终端中清除，批准后由服务端写入
+    #[serde(rename = "approval_pending")]
+    ApprovalPending {
+        session_id: Uuid,
+        approval_id: Uuid,
+        command: String,
+        message: String,
+        /// 收到通知的在线审批人数
+        approvers: usize,
+        expires_at: chrono::DateTime<chrono::Utc>,
+    },
+    /// 发给有权审批的账户，用 approve_command 回复
+    #[serde(rename = "approval_request")]
+    ApprovalRequest {
+        approval_id: Uuid,
+        session_id: Uuid,
+        requester: String,
+        host: Option<String>,
+        command: String,
+        message: String,
+        expires_at: chrono::DateTime<chrono::Utc>,
+    },
+    /// 打开会话被限流或超过会话数上限
+    #[serde(rename = "limit_exceeded")]
+    LimitExceeded {
+        code: crate::ratelimit::LimitCode,
+        message: String,
+        /// 建议的重试等待 (秒)
+        retry_after: Option<u64>,
+    },
+    /// 审批结束，发给申请人和所有收到过申请的审批人
+    #[serde(rename = "approval_resolved")]
+    ApprovalResolved {
+        approval_id: Uuid,
+        session_id: Uuid,
+        command: String,
+        outcome: crate::approval::ApprovalOutcome,
+        approver: Option<String>,
+    },
 }
 
 #[derive(Debug, Deserialize)]
@@ -45,8 +349,11 @@ pub struct AIRequest {
 #[derive(Debug, Deserialize)]
 pub struct AIConfig {
     pub provider: Option<String>,
-    #[serde(rename = "apiKey")]
+    #[serde(rename = "apiKey", default)]
     pub api_key: String,
+    /// 凭据库中保存的 API Key，设置后忽略 apiKey
+    #[serde(rename = "credentialId", default)]
+    pub credential_id: Option<Uuid>,
     pub model: String,
     pub endpoint: Option<String>,
     #[serde(rename = "systemPrompt")]
@@ -60,4 +367,15 @@ pub struct AIConfig {
 pub struct AIResponse {
     pub response: String,
     pub command: Option<String>,
+    /// 当前账户能否在该会话上自动执行 command
+    pub auto_execute: bool,
+    /// command 命中命令策略时的判定，被禁止的命令不会返回
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<crate::policy::Verdict>,
+    /// command 的风险评估，高于 low 时不能自动执行
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub risk: Option<crate::risk::Assessment>,
+    /// 发送给AI之前被遮盖的内容
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub redactions: Vec<crate::redact::Redaction>,
 }
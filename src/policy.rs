@@ -0,0 +1,430 @@
+use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::auth::Identity;
+use crate::config::{matches_pattern, PolicyAction, PolicyConfig, PolicyRule};
+use crate::rbac::host_matches;
+
+/// 命令前面可以出现的包装程序及其带参数的选项，匹配时跳过它们看真正执行的程序
+///
+/// 不在表中的选项都按不带参数处理，如 `sudo -n`、`time -p`。
+const WRAPPERS: &[(&str, &[&str])] = &[
+    ("sudo", &["-u", "-g", "-C", "-h", "-p", "-U", "-r", "-t", "-T", "--user", "--group", "--close-from", "--host", "--prompt", "--other-user", "--role", "--type", "--command-timeout"]),
+    ("doas", &["-u", "-C"]),
+    ("env", &["-u", "-C", "-S", "--unset", "--chdir", "--split-string"]),
+    ("command", &[]),
+    ("exec", &["-a"]),
+    ("nohup", &[]),
+    ("time", &["-f", "-o", "--format", "--output"]),
+    ("nice", &["-n", "--adjustment"]),
+    ("stdbuf", &["-i", "-o", "-e", "--input", "--output", "--error"]),
+];
+/// 用 `-c` 执行参数中脚本的 shell
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "ksh", "dash", "ash", "fish"];
+/// `sh -c`、`eval` 嵌套展开的层数上限
+const MAX_INLINE_DEPTH: usize = 4;
+
+/// 策略判定结果
+#[derive(Debug, Clone, Serialize)]
+pub struct Verdict {
+    pub action: PolicyAction,
+    /// 命中的规则在 `policy.rules` 中的下标，没有命中时为空
+    pub rule: Option<usize>,
+    pub message: String,
+}
+
+impl Verdict {
+    fn allow() -> Self {
+        Verdict { action: PolicyAction::Allow, rule: None, message: String::new() }
+    }
+}
+
+struct CommandPattern {
+    program: String,
+    args: Vec<String>,
+}
+
+struct CompiledRule {
+    rule: PolicyRule,
+    commands: Vec<CommandPattern>,
+    pattern: Option<Regex>,
+}
+
+/// 服务端命令策略 (`policy.rules`)，对用户提交的每一行和AI建议的命令生效
+pub struct Policy {
+    rules: Vec<CompiledRule>,
+    host_groups: BTreeMap<String, Vec<String>>,
+}
+
+impl Policy {
+    /// host_groups 与 `auth.host_groups` 相同，规则中以 `@组名` 引用
+    pub fn new(config: &PolicyConfig, host_groups: &BTreeMap<String, Vec<String>>) -> anyhow::Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| {
+                let pattern = rule
+                    .pattern
+                    .as_deref()
+                    .map(Regex::new)
+                    .transpose()
+                    .map_err(|e| anyhow::anyhow!("policy.rules[{}] 的正则无效: {}", index, e))?;
+                let commands = rule
+                    .commands
+                    .iter()
+                    .filter_map(|command| {
+                        let mut words = command.split_whitespace().map(str::to_string);
+                        Some(CommandPattern { program: words.next()?, args: words.collect() })
+                    })
+                    .collect();
+                Ok(CompiledRule { rule: rule.clone(), commands, pattern })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Policy { rules, host_groups: host_groups.clone() })
+    }
+
+    /// 判定一行命令，host 为空表示本地终端
+    pub fn evaluate(&self, identity: Option<&Identity>, host: Option<&str>, line: &str) -> Verdict {
+        let line = line.trim();
+        if line.is_empty() {
+            return Verdict::allow();
+        }
+        let commands = expand_commands(line);
+        for (index, compiled) in self.rules.iter().enumerate() {
+            let rule = &compiled.rule;
+            let role_ok = rule.roles.is_empty() || identity.is_some_and(|i| rule.roles.contains(&i.role));
+            let host_ok = rule.hosts.is_empty()
+                || host.is_some_and(|host| host_matches(&self.host_groups, &rule.hosts, host));
+            if !role_ok || !host_ok {
+                continue;
+            }
+            let command_ok = compiled.commands.is_empty()
+                || commands.iter().any(|words| compiled.commands.iter().any(|p| p.matches(words)));
+            let pattern_ok = compiled.pattern.as_ref().is_none_or(|re| re.is_match(line));
+            if command_ok && pattern_ok {
+                let message = rule.message.clone().unwrap_or_else(|| match rule.action {
+                    PolicyAction::Allow => String::new(),
+                    PolicyAction::Confirm => "该命令需要确认后才能执行".to_string(),
+                    PolicyAction::Approve => "该命令需要另一位账户审批后才能执行".to_string(),
+                    PolicyAction::Deny => "该命令被服务端策略禁止".to_string(),
+                });
+                return Verdict { action: rule.action, rule: Some(index), message };
+            }
+        }
+        Verdict::allow()
+    }
+
+    /// 判定多行脚本 (如AI建议的命令)，取最严格的结果
+    pub fn evaluate_script(&self, identity: Option<&Identity>, host: Option<&str>, script: &str) -> Verdict {
+        script
+            .lines()
+            .map(|line| self.evaluate(identity, host, line))
+            .max_by_key(|verdict| verdict.action)
+            .unwrap_or_else(Verdict::allow)
+    }
+}
+
+impl CommandPattern {
+    fn matches(&self, words: &[String]) -> bool {
+        let Some((program, args)) = program_and_args(words) else {
+            return false;
+        };
+        let program = program.rsplit('/').next().unwrap_or(program);
+        if !matches_pattern(&self.program, program) {
+            return false;
+        }
+        // 短选项可以合并或拆开书写，`-rf` 也匹配 `-r -f` 和 `-fr`
+        let short_flags: String = args
+            .iter()
+            .filter(|a| a.starts_with('-') && !a.starts_with("--"))
+            .flat_map(|a| a.chars().skip(1))
+            .collect();
+        // 路径按字面归一后比较，`rm -rf /` 同样匹配 `rm -rf //` 和 `rm -rf /*`；含通配符的参数按通配符匹配
+        let targets: Vec<String> = args.iter().flat_map(|a| path_targets(a)).collect();
+        self.args.iter().all(|expected| {
+            if expected.starts_with('-') && !expected.starts_with("--") && expected.len() > 1 {
+                expected.chars().skip(1).all(|c| short_flags.contains(c))
+            } else if expected.contains(['*', '?']) {
+                targets.iter().any(|t| matches_pattern(expected, t))
+            } else {
+                targets.contains(&normalize_path(expected))
+            }
+        })
+    }
+}
+
+/// 合并重复的 `/`，去掉 `.` 并解析 `..`，末尾的 `/` 也去掉；不含 `/` 的参数原样返回
+fn normalize_path(arg: &str) -> String {
+    if !arg.contains('/') {
+        return arg.to_string();
+    }
+    let absolute = arg.starts_with('/');
+    let mut parts: Vec<&str> = Vec::new();
+    for part in arg.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if parts.last().is_some_and(|p| *p != "..") => {
+                parts.pop();
+            }
+            // 根目录的上级仍是根目录
+            ".." if absolute => {}
+            _ => parts.push(part),
+        }
+    }
+    match (absolute, parts.is_empty()) {
+        (true, _) => format!("/{}", parts.join("/")),
+        (false, true) => ".".to_string(),
+        (false, false) => parts.join("/"),
+    }
+}
+
+/// 参数实际作用的路径：`dir/*` 展开后是目录下的全部内容，等同于目录本身
+fn path_targets(arg: &str) -> Vec<String> {
+    let normalized = normalize_path(arg);
+    let parent = normalized
+        .strip_suffix("/*")
+        .map(|dir| if dir.is_empty() { "/".to_string() } else { dir.to_string() });
+    std::iter::once(normalized).chain(parent).collect()
+}
+
+/// 跳过环境变量赋值和 sudo 等包装程序，返回实际执行的程序及其参数
+pub(crate) fn program_and_args(words: &[String]) -> Option<(&str, &[String])> {
+    let mut index = 0;
+    while index < words.len() {
+        let word = words[index].as_str();
+        if is_assignment(word) {
+            index += 1;
+        } else if let Some(options) = wrapper_options(word) {
+            index += 1;
+            while index < words.len() && (words[index].starts_with('-') || is_assignment(&words[index])) {
+                let option = words[index].as_str();
+                index += 1;
+                if option == "--" {
+                    break;
+                }
+                if options.contains(&option) {
+                    index += 1;
+                }
+            }
+        } else {
+            return Some((word, &words[index + 1..]));
+        }
+    }
+    None
+}
+
+/// 包装程序带参数的选项，不是包装程序时为空
+fn wrapper_options(word: &str) -> Option<&'static [&'static str]> {
+    let name = word.rsplit('/').next().unwrap_or(word);
+    WRAPPERS.iter().find(|(wrapper, _)| *wrapper == name).map(|(_, options)| *options)
+}
+
+/// 词列表是否以包装程序开头 (跳过环境变量赋值)
+pub(crate) fn starts_with_wrapper(words: &[String]) -> bool {
+    words.iter().find(|w| !is_assignment(w)).is_some_and(|w| wrapper_options(w).is_some())
+}
+
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// 拆分一行命令，`sh -c '…'` 和 `eval …` 中的脚本同样拆开加入结果
+fn expand_commands(line: &str) -> Vec<Vec<String>> {
+    let mut commands = Vec::new();
+    let mut scripts = vec![(line.to_string(), 0)];
+    while let Some((script, depth)) = scripts.pop() {
+        for words in split_commands(&script) {
+            if let Some(inner) = inline_script(&words).filter(|_| depth < MAX_INLINE_DEPTH) {
+                scripts.push((inner, depth + 1));
+            }
+            commands.push(words);
+        }
+    }
+    commands
+}
+
+/// `sh -c` 的脚本参数或 `eval` 拼接后的参数，其他命令为空
+pub(crate) fn inline_script(words: &[String]) -> Option<String> {
+    let (program, args) = program_and_args(words)?;
+    let program = program.rsplit('/').next().unwrap_or(program);
+    if program == "eval" {
+        return Some(args.join(" "));
+    }
+    if !SHELLS.contains(&program) {
+        return None;
+    }
+    let mut inline = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => return args.next().filter(|_| inline).cloned(),
+            "-o" | "+o" | "-O" | "+O" => {
+                args.next();
+            }
+            _ if arg.starts_with("--") || arg.starts_with('+') => {}
+            _ if arg.starts_with('-') => inline |= arg.contains('c'),
+            _ => return inline.then(|| arg.clone()),
+        }
+    }
+    None
+}
+
+/// 把一行 shell 命令拆成若干简单命令的词列表
+///
+/// 处理引号和反斜杠转义，按 `;`、`&`、`|`、换行、括号、`$(` 和反引号分隔。
+pub fn split_commands(line: &str) -> Vec<Vec<String>> {
+    let mut commands = Vec::new();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars().peekable();
+
+    fn end_word(word: &mut String, in_word: &mut bool, words: &mut Vec<String>) {
+        if *in_word {
+            words.push(std::mem::take(word));
+            *in_word = false;
+        }
+    }
+
+    while let Some(c) = chars.next() {
+        if let Some(q) = quote {
+            match c {
+                _ if c == q => quote = None,
+                '\\' if q == '"' => word.extend(chars.next()),
+                _ => word.push(c),
+            }
+            continue;
+        }
+        match c {
+            '\'' | '"' => {
+                quote = Some(c);
+                in_word = true;
+            }
+            '\\' => {
+                word.extend(chars.next());
+                in_word = true;
+            }
+            ' ' | '\t' => end_word(&mut word, &mut in_word, &mut words),
+            ';' | '&' | '|' | '\n' | '(' | ')' | '`' | '{' | '}' => {
+                end_word(&mut word, &mut in_word, &mut words);
+                if !words.is_empty() {
+                    commands.push(std::mem::take(&mut words));
+                }
+            }
+            '$' if chars.peek() == Some(&'(') => {
+                chars.next();
+                end_word(&mut word, &mut in_word, &mut words);
+                if !words.is_empty() {
+                    commands.push(std::mem::take(&mut words));
+                }
+            }
+            _ => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    end_word(&mut word, &mut in_word, &mut words);
+    if !words.is_empty() {
+        commands.push(words);
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserRole;
+
+    fn policy(rules: Vec<PolicyRule>) -> Policy {
+        let host_groups = BTreeMap::from([("prod".to_string(), vec!["*.prod".to_string()])]);
+        Policy::new(&PolicyConfig { rules, ..Default::default() }, &host_groups).unwrap()
+    }
+
+    fn rule(commands: &[&str], action: PolicyAction) -> PolicyRule {
+        PolicyRule {
+            commands: commands.iter().map(|c| c.to_string()).collect(),
+            action,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parsed_command_matching() {
+        let policy = policy(vec![rule(&["rm -rf /", "mkfs*"], PolicyAction::Deny), rule(&["shutdown"], PolicyAction::Confirm)]);
+        for line in ["rm -rf /", "sudo rm -fr /", "cd /tmp && sudo -u root rm -r -f /", "echo $(mkfs.ext4 /dev/sdb)", "/sbin/mkfs -t xfs /dev/sdc"] {
+            assert_eq!(policy.evaluate(None, None, line).action, PolicyAction::Deny, "{}", line);
+        }
+        for line in ["rm -rf /tmp/build", "echo 'rm -rf /'", "ls -la", ""] {
+            assert_eq!(policy.evaluate(None, None, line).action, PolicyAction::Allow, "{}", line);
+        }
+        assert_eq!(policy.evaluate(None, None, "LANG=C nohup shutdown -h now").action, PolicyAction::Confirm);
+        assert_eq!(policy.evaluate_script(None, None, "uptime\nshutdown -r\nrm -rf /").action, PolicyAction::Deny);
+    }
+
+    #[test]
+    fn wrapper_flags_do_not_hide_the_program() {
+        let policy = policy(vec![rule(&["rm -rf /"], PolicyAction::Deny)]);
+        for line in [
+            "sudo -n rm -rf /",
+            "sudo -E -u root -n rm -rf /",
+            "time -p rm -rf /",
+            "nice -n 5 rm -rf /",
+            "env VAR=x rm -rf /",
+            "env -u HOME VAR=x sudo -- rm -rf /",
+            "/usr/bin/sudo -i rm -rf /",
+        ] {
+            assert_eq!(policy.evaluate(None, None, line).action, PolicyAction::Deny, "{}", line);
+        }
+    }
+
+    #[test]
+    fn inline_scripts_are_split() {
+        let policy = policy(vec![rule(&["rm -rf /"], PolicyAction::Deny)]);
+        for line in [
+            "bash -c 'rm -rf /'",
+            "sh -ec \"cd /tmp; rm -rf /\"",
+            "sudo bash -o pipefail -lc 'uptime && rm -rf /'",
+            "eval rm -rf /",
+            "eval 'ls; rm -rf /'",
+            "sh -c \"bash -c 'eval rm -rf /'\"",
+        ] {
+            assert_eq!(policy.evaluate(None, None, line).action, PolicyAction::Deny, "{}", line);
+        }
+        for line in ["bash -c 'rm -rf /tmp/x'", "bash script.sh 'rm -rf /'", "echo eval rm -rf /"] {
+            assert_eq!(policy.evaluate(None, None, line).action, PolicyAction::Allow, "{}", line);
+        }
+    }
+
+    #[test]
+    fn path_arguments_are_normalized() {
+        let policy = policy(vec![rule(&["rm -rf /", "chmod * /etc/*"], PolicyAction::Deny)]);
+        for line in ["rm -rf /*", "rm -rf //", "rm -rf /./", "rm -rf /tmp/..", "rm -rf '/'*", "rm -rf /../..", "chmod 777 /etc//shadow"] {
+            assert_eq!(policy.evaluate(None, None, line).action, PolicyAction::Deny, "{}", line);
+        }
+        for line in ["rm -rf /tmp/*", "rm -rf ./*", "rm -rf tmp/..", "chmod 644 /home/etc"] {
+            assert_eq!(policy.evaluate(None, None, line).action, PolicyAction::Allow, "{}", line);
+        }
+    }
+
+    #[test]
+    fn scoped_by_role_and_host() {
+        let admin = Identity { user: "root".to_string(), role: UserRole::Admin, groups: Vec::new() };
+        let operator = Identity { user: "bob".to_string(), role: UserRole::Operator, groups: Vec::new() };
+        let policy = policy(vec![
+            PolicyRule { roles: vec![UserRole::Admin], ..rule(&["reboot"], PolicyAction::Allow) },
+            PolicyRule { hosts: vec!["@prod".to_string()], ..rule(&["reboot"], PolicyAction::Deny) },
+            PolicyRule { pattern: Some(r"DROP\s+TABLE".to_string()), ..rule(&[], PolicyAction::Confirm) },
+        ]);
+        assert_eq!(policy.evaluate(Some(&admin), Some("db.prod"), "reboot").action, PolicyAction::Allow);
+        let denied = policy.evaluate(Some(&operator), Some("db.prod"), "reboot");
+        assert_eq!((denied.action, denied.rule), (PolicyAction::Deny, Some(1)));
+        assert_eq!(policy.evaluate(Some(&operator), Some("db.dev"), "reboot").action, PolicyAction::Allow);
+        assert_eq!(policy.evaluate(Some(&operator), None, "psql -c 'DROP  TABLE x'").action, PolicyAction::Confirm);
+    }
+}
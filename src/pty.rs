@@ -0,0 +1,125 @@
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
+use std::io::{Read, Write};
+use tokio::sync::mpsc;
+use tracing::{info, error};
+use uuid::Uuid;
+
+use crate::config::LocalShellConfig;
+use crate::terminal::{SessionEvent, TerminalBackend, TerminalError};
+
+/// 在网关本机PTY上运行的Shell，用于管理网关自身或在没有远程主机时联调
+pub struct PtySession {
+    id: Uuid,
+    master: Mutex<Box<dyn MasterPty + Send>>,
+    writer: Mutex<Box<dyn Write + Send>>,
+    child: Mutex<Box<dyn Child + Send + Sync>>,
+}
+
+impl PtySession {
+    pub fn spawn(
+        config: &LocalShellConfig,
+        cols: u16,
+        rows: u16,
+    ) -> Result<(Self, mpsc::Receiver<SessionEvent>), TerminalError> {
+        let pair = native_pty_system()
+            .openpty(PtySize { rows, cols, pixel_width: 0, pixel_height: 0 })
+            .map_err(|e| TerminalError::Pty(format!("打开PTY失败: {}", e)))?;
+
+        let shell = config.shell();
+        let mut cmd = CommandBuilder::new(&shell);
+        cmd.args(&config.args);
+        cmd.env("TERM", "xterm-256color");
+        if let Some(cwd) = &config.cwd {
+            cmd.cwd(cwd);
+        }
+
+        let child = pair.slave.spawn_command(cmd)
+            .map_err(|e| TerminalError::Pty(format!("启动 {} 失败: {}", shell, e)))?;
+        // 子进程已持有从端，这里必须释放，否则子进程退出后读端收不到EOF
+        drop(pair.slave);
+
+        let mut reader = pair.master.try_clone_reader()
+            .map_err(|e| TerminalError::Pty(e.to_string()))?;
+        let writer = pair.master.take_writer()
+            .map_err(|e| TerminalError::Pty(e.to_string()))?;
+
+        let id = Uuid::new_v4();
+        let (tx, rx) = mpsc::channel(100);
+
+        // PTY读取是阻塞的，放到独立线程中
+        std::thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        let data = String::from_utf8_lossy(&buffer[..n]).to_string();
+                        if tx.blocking_send(SessionEvent::Data(data)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        // Linux 上子进程退出后读取主端返回 EIO
+                        if e.raw_os_error() != Some(5) {
+                            error!("本地终端读取错误: {}", e);
+                        }
+                        break;
+                    }
+                }
+            }
+            info!("本地终端 {} 已退出", id);
+            let _ = tx.blocking_send(SessionEvent::Closed);
+        });
+
+        info!("本地终端 {} 已启动: {}", id, shell);
+        Ok((
+            PtySession {
+                id,
+                master: Mutex::new(pair.master),
+                writer: Mutex::new(writer),
+                child: Mutex::new(child),
+            },
+            rx,
+        ))
+    }
+}
+
+#[async_trait]
+impl TerminalBackend for PtySession {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        "local"
+    }
+
+    /// PTY写入在缓冲区满时会阻塞，放到 block_in_place 中执行以免占住异步工作线程
+    async fn write(&self, data: &str) -> Result<(), TerminalError> {
+        tokio::task::block_in_place(|| {
+            let mut writer = self.writer.lock();
+            writer.write_all(data.as_bytes())?;
+            writer.flush()
+        })?;
+        Ok(())
+    }
+
+    async fn resize(&self, cols: u16, rows: u16) -> Result<(), TerminalError> {
+        self.master.lock()
+            .resize(PtySize { rows, cols, pixel_width: 0, pixel_height: 0 })
+            .map_err(|e| TerminalError::Pty(format!("调整终端大小失败: {}", e)))
+    }
+
+    async fn close(&self) {
+        tokio::task::block_in_place(|| {
+            let mut child = self.child.lock();
+            if let Ok(None) = child.try_wait() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        });
+        info!("本地终端 {} 已关闭", self.id);
+    }
+}
@@ -0,0 +1,242 @@
+use dashmap::DashMap;
+use metrics::counter;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use warp::http::{header, StatusCode};
+use warp::Reply;
+
+use crate::config::{PerformanceConfig, SSHConfig};
+use crate::Sessions;
+
+/// 桶的数量超过该值时清理已经补满的桶
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitCode {
+    RateLimited,
+    Busy,
+    UserSessionLimit,
+    GlobalSessionLimit,
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitError {
+    #[error("请求过于频繁，请 {retry_after} 秒后重试")]
+    RateLimited { retry_after: u64 },
+    #[error("同时处理的请求过多，请稍后重试")]
+    Busy,
+    #[error("当前账户已打开 {0} 个SSH会话，达到上限")]
+    UserSessions(usize),
+    #[error("服务器已打开 {0} 个SSH会话，达到上限")]
+    GlobalSessions(usize),
+}
+
+impl LimitError {
+    pub fn code(&self) -> LimitCode {
+        match self {
+            LimitError::RateLimited { .. } => LimitCode::RateLimited,
+            LimitError::Busy => LimitCode::Busy,
+            LimitError::UserSessions(_) => LimitCode::UserSessionLimit,
+            LimitError::GlobalSessions(_) => LimitCode::GlobalSessionLimit,
+        }
+    }
+
+    /// 建议的重试等待 (秒)，会话数上限要等已有会话关闭，无法估计
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            LimitError::RateLimited { retry_after } => Some(*retry_after),
+            LimitError::Busy => Some(1),
+            LimitError::UserSessions(_) | LimitError::GlobalSessions(_) => None,
+        }
+    }
+
+    /// 429 及 `Retry-After`
+    pub fn reply(&self) -> warp::reply::Response {
+        let body = warp::reply::json(&serde_json::json!({
+            "error": self.to_string(),
+            "code": self.code(),
+            "retry_after": self.retry_after(),
+        }));
+        let mut response = warp::reply::with_status(body, StatusCode::TOO_MANY_REQUESTS).into_response();
+        if let Some(retry_after) = self.retry_after() {
+            response.headers_mut().insert(header::RETRY_AFTER, retry_after.into());
+        }
+        response
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// 按键 (账户、IP) 分别计数的令牌桶
+pub struct RateLimiter {
+    capacity: f64,
+    per_second: f64,
+    buckets: DashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    /// 每 window 秒 max_requests 次，允许一次性用完
+    pub fn new(max_requests: u32, window: u64) -> Self {
+        RateLimiter {
+            capacity: max_requests as f64,
+            per_second: max_requests as f64 / window as f64,
+            buckets: DashMap::new(),
+        }
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.capacity);
+        bucket.updated = now;
+    }
+
+    /// 所有键都还有令牌时各扣一个，否则不扣并返回需要等待的时间
+    pub fn check(&self, keys: &[String]) -> Result<(), LimitError> {
+        let now = Instant::now();
+        if self.buckets.len() > PRUNE_THRESHOLD {
+            self.buckets.retain(|_, bucket| {
+                self.refill(bucket, now);
+                bucket.tokens < self.capacity
+            });
+        }
+        let mut wait: f64 = 0.0;
+        for key in keys {
+            let mut bucket = self
+                .buckets
+                .entry(key.clone())
+                .or_insert(Bucket { tokens: self.capacity, updated: now });
+            self.refill(&mut bucket, now);
+            if bucket.tokens < 1.0 {
+                wait = wait.max((1.0 - bucket.tokens) / self.per_second);
+            }
+        }
+        if wait > 0.0 {
+            return Err(LimitError::RateLimited { retry_after: (wait.ceil() as u64).max(1) });
+        }
+        for key in keys {
+            if let Some(mut bucket) = self.buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `performance` 和 `ssh.max_sessions*` 中的限制
+pub struct Limits {
+    ai_chat: Option<RateLimiter>,
+    connect: Option<RateLimiter>,
+    ai_concurrency: Option<Arc<Semaphore>>,
+    max_sessions: usize,
+    max_sessions_per_user: usize,
+}
+
+impl Limits {
+    pub fn new(performance: &PerformanceConfig, ssh: &SSHConfig) -> Self {
+        let rate = &performance.rate_limit;
+        let limiter = |max_requests: Option<u32>| {
+            rate.enabled
+                .then(|| RateLimiter::new(max_requests.unwrap_or(rate.max_requests), rate.window))
+        };
+        Limits {
+            ai_chat: limiter(rate.ai_chat),
+            connect: limiter(rate.connect),
+            ai_concurrency: (performance.max_concurrent_requests > 0)
+                .then(|| Arc::new(Semaphore::new(performance.max_concurrent_requests))),
+            max_sessions: ssh.max_sessions,
+            max_sessions_per_user: ssh.max_sessions_per_user,
+        }
+    }
+
+    /// `/api/ai/chat`：限流并占用一个并发名额，处理完请求前不要释放返回的许可
+    pub fn check_ai_chat(&self, user: Option<&str>, ip: Option<IpAddr>) -> Result<Option<OwnedSemaphorePermit>, LimitError> {
+        if let Some(limiter) = &self.ai_chat {
+            limiter.check(&keys(user, ip)).inspect_err(|e| rejected(e, "ai_chat"))?;
+        }
+        self.ai_concurrency
+            .as_ref()
+            .map(|semaphore| semaphore.clone().try_acquire_owned().map_err(|_| LimitError::Busy))
+            .transpose()
+            .inspect_err(|e| rejected(e, "ai_chat"))
+    }
+
+    /// WebSocket 打开会话的尝试
+    pub fn check_connect(&self, user: Option<&str>, ip: Option<IpAddr>) -> Result<(), LimitError> {
+        match &self.connect {
+            Some(limiter) => limiter.check(&keys(user, ip)).inspect_err(|e| rejected(e, "connect")),
+            None => Ok(()),
+        }
+    }
+
+    /// 再打开一个SSH会话是否会超过总数或账户的上限
+    pub fn check_ssh_sessions(&self, sessions: &Sessions, user: Option<&str>) -> Result<(), LimitError> {
+        let (mut total, mut owned) = (0, 0);
+        for session in sessions.iter().filter(|s| s.backend.kind() == "ssh") {
+            total += 1;
+            if user.is_some() && session.owner_user.as_deref() == user {
+                owned += 1;
+            }
+        }
+        let result = if self.max_sessions > 0 && total >= self.max_sessions {
+            Err(LimitError::GlobalSessions(total))
+        } else if user.is_some() && self.max_sessions_per_user > 0 && owned >= self.max_sessions_per_user {
+            Err(LimitError::UserSessions(owned))
+        } else {
+            Ok(())
+        };
+        result.inspect_err(|e| rejected(e, "ssh_sessions"))
+    }
+}
+
+fn keys(user: Option<&str>, ip: Option<IpAddr>) -> Vec<String> {
+    user.map(|user| format!("user:{}", user))
+        .into_iter()
+        .chain(ip.map(|ip| format!("ip:{}", ip)))
+        .collect()
+}
+
+fn rejected(error: &LimitError, limit: &'static str) {
+    let code = match error.code() {
+        LimitCode::RateLimited => "rate_limited",
+        LimitCode::Busy => "busy",
+        LimitCode::UserSessionLimit => "user_session_limit",
+        LimitCode::GlobalSessionLimit => "global_session_limit",
+    };
+    counter!("limits_rejected_total", 1, "limit" => limit, "code" => code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket_per_key() {
+        let limiter = RateLimiter::new(2, 60);
+        let alice = vec!["user:alice".to_string(), "ip:10.0.0.1".to_string()];
+        let bob = vec!["user:bob".to_string(), "ip:10.0.0.1".to_string()];
+        limiter.check(&alice).unwrap();
+        limiter.check(&alice).unwrap();
+        // 每 30 秒补充一个令牌
+        assert_eq!(limiter.check(&alice), Err(LimitError::RateLimited { retry_after: 30 }));
+        // 同一IP的其他账户也受IP桶限制，被拒绝的请求不扣令牌
+        assert!(limiter.check(&bob).is_err());
+        limiter.check(&["user:bob".to_string()]).unwrap();
+
+        let mut bucket = limiter.buckets.get_mut("user:alice").unwrap();
+        bucket.updated -= std::time::Duration::from_secs(45);
+        drop(bucket);
+        let mut bucket = limiter.buckets.get_mut("ip:10.0.0.1").unwrap();
+        bucket.updated -= std::time::Duration::from_secs(45);
+        drop(bucket);
+        limiter.check(&alice).unwrap();
+        assert!(limiter.check(&alice).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use crate::auth::Identity;
+use crate::config::{matches_pattern, AccessRule, AuthConfig, UserRole};
+use crate::session::AccessError;
+
+/// 打开新终端前校验账户权限，host 为空表示网关本地终端；未启用登录时不做限制
+pub fn check_connect(config: &AuthConfig, identity: Option<&Identity>, host: Option<&str>) -> Result<(), AccessError> {
+    let Some(identity) = identity else {
+        return Ok(());
+    };
+    match identity.role {
+        UserRole::Admin => Ok(()),
+        UserRole::Viewer => Err(AccessError::ReadOnlyAccount),
+        UserRole::Operator => {
+            let host = host.ok_or(AccessError::HostNotAllowed)?;
+            if rules_for(config, identity).any(|rule| covers(config, rule, host)) {
+                Ok(())
+            } else {
+                Err(AccessError::HostNotAllowed)
+            }
+        }
+    }
+}
+
+/// 是否允许在该主机上自动执行AI建议的命令
+pub fn can_auto_execute(config: &AuthConfig, identity: Option<&Identity>, host: Option<&str>) -> bool {
+    let Some(identity) = identity else {
+        return true;
+    };
+    match (identity.role, host) {
+        (UserRole::Admin, _) => true,
+        (UserRole::Operator, Some(host)) => rules_for(config, identity)
+            .any(|rule| rule.ai_auto_execute && covers(config, rule, host)),
+        _ => false,
+    }
+}
+
+/// 账户写入会话前的检查，viewer 账户即使被授予输入权限也不能输入
+pub fn check_write(identity: Option<&Identity>) -> Result<(), AccessError> {
+    match identity {
+        Some(identity) if identity.role == UserRole::Viewer => Err(AccessError::ReadOnlyAccount),
+        _ => Ok(()),
+    }
+}
+
+fn rules_for<'a>(config: &'a AuthConfig, identity: &'a Identity) -> impl Iterator<Item = &'a AccessRule> {
+    config.rules.iter().filter(move |rule| {
+        rule.users.contains(&identity.user)
+            || rule.groups.iter().any(|g| identity.groups.contains(g))
+    })
+}
+
+fn covers(config: &AuthConfig, rule: &AccessRule, host: &str) -> bool {
+    host_matches(&config.host_groups, &rule.hosts, host)
+}
+
+/// 主机是否命中通配符或 `@主机组` 列表
+pub fn host_matches(host_groups: &BTreeMap<String, Vec<String>>, patterns: &[String], host: &str) -> bool {
+    patterns.iter().any(|pattern| match pattern.strip_prefix('@') {
+        Some(group) => host_groups
+            .get(group)
+            .is_some_and(|patterns| patterns.iter().any(|p| matches_pattern(p, host))),
+        None => matches_pattern(pattern, host),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AuthConfig {
+        let mut config = AuthConfig::default();
+        config.host_groups.insert("web".to_string(), vec!["web-*.prod".to_string()]);
+        config.rules = vec![
+            AccessRule {
+                groups: vec!["ops".to_string()],
+                hosts: vec!["@web".to_string()],
+                ai_auto_execute: true,
+                ..Default::default()
+            },
+            AccessRule {
+                users: vec!["bob".to_string()],
+                hosts: vec!["db-1".to_string()],
+                ..Default::default()
+            },
+        ];
+        config
+    }
+
+    fn identity(user: &str, role: UserRole, groups: &[&str]) -> Identity {
+        Identity {
+            user: user.to_string(),
+            role,
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn operator_limited_to_granted_hosts() {
+        let config = config();
+        let bob = identity("bob", UserRole::Operator, &["ops"]);
+        assert!(check_connect(&config, Some(&bob), Some("web-3.prod")).is_ok());
+        assert!(check_connect(&config, Some(&bob), Some("db-1")).is_ok());
+        assert_eq!(check_connect(&config, Some(&bob), Some("db-2")), Err(AccessError::HostNotAllowed));
+        assert_eq!(check_connect(&config, Some(&bob), None), Err(AccessError::HostNotAllowed));
+
+        assert!(can_auto_execute(&config, Some(&bob), Some("web-1.prod")));
+        assert!(!can_auto_execute(&config, Some(&bob), Some("db-1")));
+    }
+
+    #[test]
+    fn admin_and_viewer() {
+        let config = config();
+        let admin = identity("root", UserRole::Admin, &[]);
+        let viewer = identity("eve", UserRole::Viewer, &["ops"]);
+        assert!(check_connect(&config, Some(&admin), None).is_ok());
+        assert_eq!(check_connect(&config, Some(&viewer), Some("web-1.prod")), Err(AccessError::ReadOnlyAccount));
+        assert!(check_write(Some(&viewer)).is_err());
+        assert!(check_connect(&config, None, None).is_ok());
+    }
+}
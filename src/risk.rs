@@ -0,0 +1,500 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::matches_pattern;
+use crate::policy::{inline_script, program_and_args, starts_with_wrapper};
+
+/// 风险等级，从低到高排序
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskLevel {
+    #[default]
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl std::fmt::Display for RiskLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RiskLevel::Low => "low",
+            RiskLevel::Medium => "medium",
+            RiskLevel::High => "high",
+            RiskLevel::Critical => "critical",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RiskReason {
+    pub level: RiskLevel,
+    pub reason: String,
+}
+
+/// 对一条命令的风险评估，level 为所有原因中最高的等级
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Assessment {
+    pub level: RiskLevel,
+    pub reasons: Vec<RiskReason>,
+}
+
+impl Assessment {
+    fn flag(&mut self, level: RiskLevel, reason: String) {
+        if !self.reasons.iter().any(|r| r.reason == reason) {
+            self.level = self.level.max(level);
+            self.reasons.push(RiskReason { level, reason });
+        }
+    }
+}
+
+/// 破坏性命令：程序名 (可用通配符)、触发的参数 (为空表示任何调用) 及等级
+const DESTRUCTIVE: &[(&str, &[&str], RiskLevel)] = &[
+    ("rm", &[], RiskLevel::High),
+    ("shred", &[], RiskLevel::High),
+    ("wipefs", &[], RiskLevel::High),
+    ("mkfs*", &[], RiskLevel::High),
+    ("mkswap", &[], RiskLevel::High),
+    ("fdisk", &[], RiskLevel::High),
+    ("sfdisk", &[], RiskLevel::High),
+    ("parted", &[], RiskLevel::High),
+    ("dd", &[], RiskLevel::High),
+    ("truncate", &[], RiskLevel::High),
+    ("shutdown", &[], RiskLevel::High),
+    ("reboot", &[], RiskLevel::High),
+    ("halt", &[], RiskLevel::High),
+    ("poweroff", &[], RiskLevel::High),
+    ("init", &["0", "6"], RiskLevel::High),
+    ("userdel", &[], RiskLevel::High),
+    ("groupdel", &[], RiskLevel::High),
+    ("crontab", &["-r"], RiskLevel::High),
+    ("rmdir", &[], RiskLevel::Medium),
+    ("mv", &[], RiskLevel::Medium),
+    ("chmod", &[], RiskLevel::Medium),
+    ("chown", &[], RiskLevel::Medium),
+    ("chgrp", &[], RiskLevel::Medium),
+    ("kill", &[], RiskLevel::Medium),
+    ("killall", &[], RiskLevel::Medium),
+    ("pkill", &[], RiskLevel::Medium),
+    ("iptables", &["-F", "--flush", "-X", "--delete-chain"], RiskLevel::Medium),
+    ("systemctl", &["stop", "restart", "disable", "mask", "kill", "poweroff", "reboot", "halt"], RiskLevel::Medium),
+    ("service", &["stop", "restart"], RiskLevel::Medium),
+    ("docker", &["rm", "rmi", "kill", "prune"], RiskLevel::Medium),
+    ("podman", &["rm", "rmi", "kill", "prune"], RiskLevel::Medium),
+    ("kubectl", &["delete", "drain"], RiskLevel::Medium),
+    ("git", &["clean", "--hard"], RiskLevel::Medium),
+];
+
+/// `-f` 表示强制的命令
+const FORCE_SHORT_FLAG: &[&str] = &["rm", "cp", "mv", "ln", "git", "docker", "podman", "kubectl", "umount", "userdel"];
+/// 下载远程内容的程序
+const DOWNLOADERS: &[&str] = &["curl", "wget", "fetch", "aria2c"];
+/// 能执行标准输入或参数中脚本的解释器
+const INTERPRETERS: &[&str] = &["sh", "bash", "zsh", "ksh", "dash", "fish", "python*", "perl", "ruby", "node", "php"];
+/// 系统目录，通配符作用于这些目录下时需要提醒
+const SYSTEM_DIRS: &[&str] = &[
+    "/bin", "/boot", "/dev", "/etc", "/home", "/lib", "/lib64", "/opt", "/proc", "/root", "/sbin", "/srv", "/sys",
+    "/usr", "/var",
+];
+/// 可以安全写入的设备
+const SAFE_DEVICES: &[&str] = &["/dev/null", "/dev/stdout", "/dev/stderr", "/dev/tty", "/dev/fd/*"];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Word {
+    text: String,
+    /// 含有未加引号的 `*`、`?` 或 `[`
+    glob: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(Word),
+    /// `|` 或 `|&`
+    Pipe,
+    /// `;`、`&`、`&&`、`||` 和换行
+    Separator,
+    /// `>`、`>>`、`2>`、`&>`、`<` 等，目标是下一个词
+    Redirect(String),
+    /// 子shell、`$(`、`<(`、`>(` 或反引号开始
+    Open,
+    Close,
+}
+
+/// 把一行 shell 命令切成词和操作符
+///
+/// 处理单双引号和反斜杠转义；双引号中的 `$(...)` 同样会被展开，按子shell处理。
+fn tokenize(line: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word = Word { text: String::new(), glob: false };
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    // 每层子shell结束后要恢复的引号状态
+    let mut stack: Vec<Option<char>> = Vec::new();
+    let mut in_backtick = false;
+    let mut chars = line.chars().peekable();
+
+    fn end_word(tokens: &mut Vec<Token>, word: &mut Word, in_word: &mut bool) {
+        if *in_word {
+            tokens.push(Token::Word(std::mem::replace(word, Word { text: String::new(), glob: false })));
+            *in_word = false;
+        }
+    }
+
+    while let Some(c) = chars.next() {
+        if quote == Some('\'') {
+            if c == '\'' {
+                quote = None;
+            } else {
+                word.text.push(c);
+            }
+            continue;
+        }
+        if quote == Some('"') {
+            match c {
+                '"' => quote = None,
+                '\\' => word.text.extend(chars.next()),
+                '$' if chars.peek() == Some(&'(') => {
+                    chars.next();
+                    end_word(&mut tokens, &mut word, &mut in_word);
+                    stack.push(quote.take());
+                    tokens.push(Token::Open);
+                }
+                _ => word.text.push(c),
+            }
+            continue;
+        }
+        match c {
+            '\'' | '"' => {
+                quote = Some(c);
+                in_word = true;
+            }
+            '\\' => {
+                word.text.extend(chars.next());
+                in_word = true;
+            }
+            ' ' | '\t' => end_word(&mut tokens, &mut word, &mut in_word),
+            '\n' | ';' => {
+                end_word(&mut tokens, &mut word, &mut in_word);
+                tokens.push(Token::Separator);
+            }
+            '|' => {
+                end_word(&mut tokens, &mut word, &mut in_word);
+                match chars.peek() {
+                    Some('|') => {
+                        chars.next();
+                        tokens.push(Token::Separator);
+                    }
+                    Some('&') => {
+                        chars.next();
+                        tokens.push(Token::Pipe);
+                    }
+                    _ => tokens.push(Token::Pipe),
+                }
+            }
+            '&' => {
+                end_word(&mut tokens, &mut word, &mut in_word);
+                if chars.peek() == Some(&'>') {
+                    chars.next();
+                    let mut op = "&>".to_string();
+                    if chars.peek() == Some(&'>') {
+                        chars.next();
+                        op.push('>');
+                    }
+                    tokens.push(Token::Redirect(op));
+                } else {
+                    if chars.peek() == Some(&'&') {
+                        chars.next();
+                    }
+                    tokens.push(Token::Separator);
+                }
+            }
+            '>' | '<' => {
+                if chars.peek() == Some(&'(') {
+                    chars.next();
+                    end_word(&mut tokens, &mut word, &mut in_word);
+                    stack.push(None);
+                    tokens.push(Token::Open);
+                    continue;
+                }
+                // 紧挨着的数字是文件描述符，如 `2>`
+                let mut op = String::new();
+                if in_word && !word.text.is_empty() && word.text.chars().all(|c| c.is_ascii_digit()) {
+                    op = std::mem::take(&mut word.text);
+                    in_word = false;
+                } else {
+                    end_word(&mut tokens, &mut word, &mut in_word);
+                }
+                op.push(c);
+                while let Some(&next) = chars.peek() {
+                    if next == c || next == '&' || next == '|' {
+                        op.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Redirect(op));
+            }
+            '$' if chars.peek() == Some(&'(') => {
+                chars.next();
+                end_word(&mut tokens, &mut word, &mut in_word);
+                stack.push(None);
+                tokens.push(Token::Open);
+            }
+            '(' => {
+                end_word(&mut tokens, &mut word, &mut in_word);
+                stack.push(None);
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                end_word(&mut tokens, &mut word, &mut in_word);
+                tokens.push(Token::Close);
+                if let Some(restore) = stack.pop() {
+                    quote = restore;
+                    in_word = quote.is_some();
+                }
+            }
+            '`' => {
+                end_word(&mut tokens, &mut word, &mut in_word);
+                tokens.push(if in_backtick { Token::Close } else { Token::Open });
+                in_backtick = !in_backtick;
+            }
+            '*' | '?' | '[' => {
+                word.text.push(c);
+                word.glob = true;
+                in_word = true;
+            }
+            _ => {
+                word.text.push(c);
+                in_word = true;
+            }
+        }
+    }
+    end_word(&mut tokens, &mut word, &mut in_word);
+    tokens
+}
+
+/// 一个简单命令及其上下文
+#[derive(Debug, Default)]
+struct SimpleCommand {
+    words: Vec<Word>,
+    /// (操作符, 目标)
+    redirects: Vec<(String, String)>,
+    /// 嵌套的子shell层数
+    depth: usize,
+    /// 标准输入来自管道
+    piped: bool,
+}
+
+fn parse(tokens: Vec<Token>) -> Vec<SimpleCommand> {
+    let mut commands = Vec::new();
+    let mut current = SimpleCommand::default();
+    let mut depth = 0;
+    let mut tokens = tokens.into_iter();
+
+    fn finish(commands: &mut Vec<SimpleCommand>, current: &mut SimpleCommand, depth: usize, piped: bool) {
+        let done = std::mem::replace(current, SimpleCommand { depth, piped, ..Default::default() });
+        if !done.words.is_empty() || !done.redirects.is_empty() {
+            commands.push(done);
+        }
+    }
+
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word(word) => current.words.push(word),
+            Token::Redirect(op) => {
+                if let Some(Token::Word(target)) = tokens.next() {
+                    current.redirects.push((op, target.text));
+                }
+            }
+            Token::Pipe => finish(&mut commands, &mut current, depth, true),
+            Token::Separator => finish(&mut commands, &mut current, depth, false),
+            Token::Open => {
+                depth += 1;
+                finish(&mut commands, &mut current, depth, false);
+            }
+            Token::Close => {
+                depth = depth.saturating_sub(1);
+                finish(&mut commands, &mut current, depth, false);
+            }
+        }
+    }
+    finish(&mut commands, &mut current, depth, false);
+    commands
+}
+
+/// 分析一条 (可能多行的) shell 命令的风险
+pub fn assess(script: &str) -> Assessment {
+    let mut assessment = Assessment::default();
+    let commands = parse(tokenize(script));
+
+    for command in &commands {
+        let texts: Vec<String> = command.words.iter().map(|w| w.text.clone()).collect();
+        for (op, target) in &command.redirects {
+            if op.contains('>') {
+                flag_write_target(&mut assessment, target);
+            }
+        }
+        // `sh -c` 和 `eval` 执行的脚本按同样的规则评估
+        if let Some(inner) = inline_script(&texts) {
+            for reason in assess(&inner).reasons {
+                assessment.flag(reason.level, reason.reason);
+            }
+        }
+        let Some((program, args)) = program_and_args(&texts) else {
+            // 包装程序后面找不到要执行的程序，无法判断实际执行什么，按高风险处理
+            if starts_with_wrapper(&texts) {
+                assessment.flag(RiskLevel::High, format!("无法解析 {} 实际执行的命令", texts.join(" ")));
+            }
+            continue;
+        };
+        let program = program.rsplit('/').next().unwrap_or(program);
+        let arg_words = &command.words[texts.len() - args.len()..];
+
+        if texts[..texts.len() - args.len() - 1].iter().any(|w| w == "sudo" || w == "doas") {
+            assessment.flag(RiskLevel::Medium, "以管理员权限执行".to_string());
+        }
+        if program == "eval" {
+            assessment.flag(RiskLevel::Medium, "eval 执行动态生成的命令".to_string());
+        }
+
+        let destructive = DESTRUCTIVE
+            .iter()
+            .filter(|(pattern, triggers, _)| {
+                matches_pattern(pattern, program) && (triggers.is_empty() || args.iter().any(|a| triggers.contains(&a.as_str())))
+            })
+            .map(|(_, _, level)| *level)
+            .max();
+        if let Some(level) = destructive {
+            assessment.flag(level, format!("破坏性命令 {}", program));
+        }
+
+        for (arg, word) in args.iter().zip(arg_words) {
+            if arg == "--no-preserve-root" {
+                assessment.flag(RiskLevel::Critical, "关闭了根目录保护 (--no-preserve-root)".to_string());
+            } else if arg.starts_with("--force") {
+                assessment.flag(RiskLevel::Medium, format!("强制选项 {}", arg));
+            } else if arg.starts_with('-') && !arg.starts_with("--") {
+                if FORCE_SHORT_FLAG.contains(&program) && arg[1..].contains('f') {
+                    assessment.flag(RiskLevel::Medium, format!("强制选项 {}", arg));
+                }
+                if matches!(program, "kill" | "killall" | "pkill") && matches!(arg.as_str(), "-9" | "-KILL" | "-SIGKILL") {
+                    assessment.flag(RiskLevel::Medium, format!("强制结束进程 {}", arg));
+                }
+            }
+            if let Some(device) = arg.strip_prefix("of=").filter(|_| program == "dd") {
+                flag_write_target(&mut assessment, device);
+            }
+            if destructive.is_some() && is_system_root(arg) {
+                assessment.flag(RiskLevel::Critical, format!("{} 作用于系统目录 {}", program, arg));
+            }
+            if word.glob && is_system_path(arg) {
+                let level = if destructive.is_some() { RiskLevel::High } else { RiskLevel::Medium };
+                assessment.flag(level, format!("通配符作用于系统目录 {}", arg));
+            }
+        }
+    }
+
+    // 下载的内容直接交给解释器执行：`curl ... | sh`、`sh -c "$(curl ...)"`、`bash <(curl ...)`
+    let programs: Vec<String> = commands.iter().map(program_name).collect();
+    let is = |names: &[&str], program: &str| names.iter().any(|pattern| matches_pattern(pattern, program));
+    for (i, downloader) in programs.iter().enumerate().filter(|(_, p)| is(DOWNLOADERS, p)) {
+        let executed = programs.iter().enumerate().any(|(j, interpreter)| {
+            is(INTERPRETERS, interpreter)
+                && ((j > i && commands[j].piped) || (j < i && commands[i].depth > commands[j].depth))
+        });
+        if executed {
+            assessment.flag(RiskLevel::Critical, format!("把 {} 下载的远程脚本直接交给解释器执行", downloader));
+        }
+    }
+    assessment
+}
+
+/// 实际执行的程序名，没有时为空串
+fn program_name(command: &SimpleCommand) -> String {
+    let texts: Vec<String> = command.words.iter().map(|w| w.text.clone()).collect();
+    program_and_args(&texts)
+        .map(|(program, _)| program.rsplit('/').next().unwrap_or(program).to_string())
+        .unwrap_or_default()
+}
+
+/// 重定向或 `dd of=` 的写入目标
+fn flag_write_target(assessment: &mut Assessment, target: &str) {
+    if SAFE_DEVICES.iter().any(|device| matches_pattern(device, target)) {
+        return;
+    }
+    if target.starts_with("/dev/") {
+        assessment.flag(RiskLevel::Critical, format!("直接写入设备 {}", target));
+    } else if is_system_path(target) && !target.starts_with("/home/") && !target.starts_with("/var/tmp/") {
+        assessment.flag(RiskLevel::High, format!("覆盖写入系统文件 {}", target));
+    }
+}
+
+/// 根目录、系统目录本身或根目录下的通配符
+fn is_system_root(path: &str) -> bool {
+    let trimmed = path.trim_end_matches('/');
+    (trimmed.is_empty() && path.starts_with('/')) || trimmed == "/*" || SYSTEM_DIRS.contains(&trimmed)
+}
+
+fn is_system_path(path: &str) -> bool {
+    path == "/" || path.starts_with("/*") || SYSTEM_DIRS.iter().any(|dir| path.strip_prefix(dir).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(command: &str) -> RiskLevel {
+        assess(command).level
+    }
+
+    #[test]
+    fn tokenizes_shell_syntax() {
+        let tokens = tokenize("sudo cat \"$(ls /etc/*.conf)\" 2>/dev/null | grep -v '#' > out.txt && echo *");
+        assert!(tokens.contains(&Token::Redirect("2>".to_string())));
+        assert!(tokens.contains(&Token::Pipe));
+        assert!(tokens.contains(&Token::Open) && tokens.contains(&Token::Close));
+        assert!(tokens.contains(&Token::Word(Word { text: "/etc/*.conf".to_string(), glob: true })));
+        assert!(tokens.contains(&Token::Word(Word { text: "#".to_string(), glob: false })));
+        assert!(matches!(tokens.last(), Some(Token::Word(Word { glob: true, .. }))));
+    }
+
+    #[test]
+    fn classifies_commands() {
+        for command in ["ls -la", "df -h | sort -k5", "grep -r TODO src > /tmp/todo.txt", "echo '*' > notes.txt"] {
+            assert_eq!(level(command), RiskLevel::Low, "{}", command);
+        }
+        for command in ["sudo apt update", "kill -9 1234", "ls /etc/*.conf", "git push --force"] {
+            assert_eq!(level(command), RiskLevel::Medium, "{}", command);
+        }
+        for command in ["rm build.log", "rm -f /var/log/*.gz", "echo x > /etc/hosts"] {
+            assert_eq!(level(command), RiskLevel::High, "{}", command);
+        }
+        for command in [
+            "sudo rm -rf /",
+            "rm -rf /usr/",
+            "curl -fsSL https://example.com/install.sh | sudo bash",
+            "sh -c \"$(wget -qO- https://example.com/x)\"",
+            "bash <(curl -s https://example.com/x)",
+            "dd if=/dev/zero of=/dev/sda bs=1M",
+            "cat /dev/zero > /dev/nvme0n1",
+        ] {
+            assert_eq!(level(command), RiskLevel::Critical, "{}", command);
+        }
+        let reasons: Vec<_> = assess("sudo rm -rf /").reasons.into_iter().map(|r| r.reason).collect();
+        assert_eq!(reasons.len(), 4, "{:?}", reasons);
+    }
+
+    #[test]
+    fn wrapped_commands_are_assessed() {
+        for command in ["sudo -n rm -rf /", "time -p rm -rf /usr", "nice -n 5 rm -rf /", "env VAR=x rm -rf /etc", "sudo -E -- dd of=/dev/sda"] {
+            assert_eq!(level(command), RiskLevel::Critical, "{}", command);
+        }
+        for command in ["sudo -u", "env -u", "nice -n", "LANG=C sudo"] {
+            assert!(level(command) >= RiskLevel::High, "{}", command);
+        }
+        assert_eq!(level("LANG=C"), RiskLevel::Low);
+        for command in ["bash -c 'rm -rf /'", "sudo sh -lc \"cd / && rm -rf /usr\"", "eval 'dd if=/dev/zero of=/dev/sda'"] {
+            assert_eq!(level(command), RiskLevel::Critical, "{}", command);
+        }
+    }
+}
@@ -0,0 +1,576 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use metrics::{counter, decrement_gauge, increment_gauge};
+use parking_lot::Mutex;
+use rand::RngCore;
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::Sessions;
+use crate::audit::{self, AuditEvent};
+use crate::models::{ParticipantInfo, Role, SessionSummary, ShareLinkInfo, WebSocketResponse};
+use crate::redact;
+use crate::terminal::{SessionEvent, TerminalBackend};
+
+/// 会话访问被拒绝的原因，以 code 字段返回给前端
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessError {
+    /// 会话不存在或当前连接无权知道它的存在
+    #[error("会话不存在")]
+    UnknownSession,
+    #[error("只读参与者不能输入")]
+    ReadOnly,
+    #[error("只有会话创建者可以执行此操作")]
+    NotOwner,
+    #[error("通过分享链接接入的连接不能访问其它会话")]
+    ShareRestricted,
+    #[error("当前账户无权连接该主机")]
+    HostNotAllowed,
+    #[error("当前账户为只读账户")]
+    ReadOnlyAccount,
+    #[error("当前账户不允许自动执行AI命令")]
+    AutoExecuteNotAllowed,
+    #[error("风险等级高于 low 的命令不能自动执行")]
+    AutoExecuteRisky,
+    #[error("目标地址不在允许连接的范围内")]
+    DestinationNotAllowed,
+}
+
+/// 判断密码提示只需要最后一行输出的末尾
+const OUTPUT_TAIL_LIMIT: usize = 256;
+
+struct Participant {
+    role: Role,
+    tx: mpsc::Sender<String>,
+    /// 连接登录的用户，未启用登录时为空
+    user: Option<String>,
+    /// 通过分享链接加入时记录链接ID，撤销链接时一并移出
+    via_link: Option<Uuid>,
+}
+
+struct ShareLink {
+    role: Role,
+    expires_at: DateTime<Utc>,
+}
+
+/// 可被多个WebSocket客户端同时附加的终端会话
+///
+/// 后端输出由单独的转发任务扇出给所有参与者；参与者以WebSocket连接的
+/// client_id 区分，创建者为 owner，其余客户端默认以 viewer 身份加入。
+pub struct SharedSession {
+    pub id: Uuid,
+    pub backend: Arc<dyn TerminalBackend>,
+    /// 远程主机，本地终端为空
+    pub host: Option<String>,
+    /// 创建者登录的用户，未启用登录时为空
+    pub owner_user: Option<String>,
+    /// owner 断线后凭此令牌恢复会话
+    pub resume_token: String,
+    /// owner 所在的WebSocket连接，断线等待恢复期间为空
+    owner: Mutex<Option<Uuid>>,
+    /// 每次 owner 断线递增，用于让过期的关闭定时器失效
+    detach_epoch: AtomicU64,
+    participants: DashMap<Uuid, Participant>,
+    links: DashMap<Uuid, ShareLink>,
+    /// 最后一行输出，用于判断远端是否在等待输入密码
+    output_tail: Mutex<String>,
+    closed: AtomicBool,
+}
+
+impl SharedSession {
+    /// 登记新打开的终端，调用 `forward` 后才开始转发输出
+    pub fn register(
+        backend: Arc<dyn TerminalBackend>,
+        host: Option<String>,
+        owner: Uuid,
+        owner_user: Option<String>,
+        owner_tx: mpsc::Sender<String>,
+        sessions: &Sessions,
+    ) -> Arc<Self> {
+        let mut token = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut token);
+        let session = Arc::new(SharedSession {
+            id: backend.id(),
+            backend,
+            host,
+            owner_user,
+            resume_token: URL_SAFE_NO_PAD.encode(token),
+            owner: Mutex::new(Some(owner)),
+            detach_epoch: AtomicU64::new(0),
+            participants: DashMap::new(),
+            links: DashMap::new(),
+            output_tail: Mutex::new(String::new()),
+            closed: AtomicBool::new(false),
+        });
+        let owner_participant = Participant { role: Role::Owner, tx: owner_tx, user: session.owner_user.clone(), via_link: None };
+        session.participants.insert(owner, owner_participant);
+        sessions.insert(session.id, session.clone());
+        increment_gauge!("sessions_active", 1.0);
+        session
+    }
+
+    /// 启动输出转发任务，后端结束时移除会话
+    pub fn forward(self: &Arc<Self>, mut rx: mpsc::Receiver<SessionEvent>, sessions: &Sessions) {
+        let pump = self.clone();
+        let sessions = sessions.clone();
+        tokio::spawn(async move {
+            let session_id = pump.id;
+            while let Some(event) = rx.recv().await {
+                let response = match event {
+                    SessionEvent::Data(data) => {
+                        pump.note_output(&data);
+                        WebSocketResponse::Data { session_id, data }
+                    }
+                    SessionEvent::Status { connected, latency_ms } => {
+                        WebSocketResponse::Status { session_id, connected, latency_ms }
+                    }
+                    SessionEvent::Closed => {
+                        // 后端已结束或对端失效且未能重连，移除残留的会话
+                        pump.terminate(&sessions, "backend_closed").await;
+                        break;
+                    }
+                };
+                pump.broadcast(&response).await;
+            }
+        });
+    }
+
+    fn note_output(&self, data: &str) {
+        let mut tail = self.output_tail.lock();
+        match data.rfind(['\r', '\n']) {
+            Some(end) => {
+                tail.clear();
+                tail.push_str(&data[end + 1..]);
+            }
+            None => tail.push_str(data),
+        }
+        if tail.len() > OUTPUT_TAIL_LIMIT {
+            let cut = (tail.len() - OUTPUT_TAIL_LIMIT..tail.len()).find(|&i| tail.is_char_boundary(i)).unwrap_or(0);
+            tail.drain(..cut);
+        }
+    }
+
+    /// 最后一行输出是密码提示，此时输入的内容不应出现在审计日志中
+    pub fn awaiting_secret(&self) -> bool {
+        redact::secret_prompt(&self.output_tail.lock())
+    }
+
+    pub fn role_of(&self, client_id: Uuid) -> Option<Role> {
+        self.participants.get(&client_id).map(|p| p.role)
+    }
+
+    pub fn owner(&self) -> Option<Uuid> {
+        *self.owner.lock()
+    }
+
+    /// owner 的连接断开后保留会话，grace 内未恢复则关闭
+    pub async fn detach_owner(self: &Arc<Self>, grace: Duration, sessions: &Sessions) {
+        let Some(owner) = self.owner.lock().take() else {
+            return;
+        };
+        let epoch = self.detach_epoch.fetch_add(1, Ordering::SeqCst) + 1;
+        self.leave(owner).await;
+        counter!("sessions_detached_total", 1);
+        increment_gauge!("sessions_detached", 1.0);
+        info!("会话 {} 的 owner 已断开，保留 {} 秒等待恢复", self.id, grace.as_secs());
+
+        let session = self.clone();
+        let sessions = sessions.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(grace).await;
+            if session.detach_epoch.load(Ordering::SeqCst) == epoch && session.owner().is_none() {
+                info!("会话 {} 未在 {} 秒内恢复", session.id, grace.as_secs());
+                session.close(&sessions, "resume_expired").await;
+            }
+        });
+    }
+
+    /// 断线期间由同一登录用户或持有恢复令牌的连接重新成为 owner
+    pub async fn resume(
+        &self,
+        client_id: Uuid,
+        tx: mpsc::Sender<String>,
+        user: Option<&str>,
+        token: Option<&str>,
+    ) -> Result<Vec<ParticipantInfo>, AccessError> {
+        {
+            let mut owner = self.owner.lock();
+            let same_user = matches!((self.owner_user.as_deref(), user), (Some(a), Some(b)) if a == b);
+            let token_ok = token.is_some_and(|t| bool::from(t.as_bytes().ct_eq(self.resume_token.as_bytes())));
+            if owner.is_some() || !(same_user || token_ok) {
+                return Err(AccessError::UnknownSession);
+            }
+            *owner = Some(client_id);
+        }
+        counter!("sessions_resumed_total", 1);
+        decrement_gauge!("sessions_detached", 1.0);
+        Ok(self.join(client_id, tx, user, Role::Owner, None).await)
+    }
+
+    /// 校验参与者是否具备 required 所需的权限，返回其当前角色
+    ///
+    /// 非参与者一律视为会话不存在，避免泄露他人会话。
+    pub fn authorize(&self, client_id: Uuid, required: Role) -> Result<Role, AccessError> {
+        let role = self.role_of(client_id).ok_or(AccessError::UnknownSession)?;
+        match required {
+            Role::Owner if role != Role::Owner => Err(AccessError::NotOwner),
+            Role::Writer if !role.can_write() => Err(AccessError::ReadOnly),
+            _ => Ok(role),
+        }
+    }
+
+    /// 不经分享链接附加时使用的角色：已参与的连接保留原角色，
+    /// 同一登录用户的其它连接以 writer 身份加入，其余连接拒绝
+    pub fn attach_role(&self, client_id: Uuid, user: Option<&str>) -> Result<Role, AccessError> {
+        if let Some(role) = self.role_of(client_id) {
+            return Ok(role);
+        }
+        match (self.owner_user.as_deref(), user) {
+            (Some(owner), Some(user)) if owner == user => Ok(Role::Writer),
+            _ => Err(AccessError::UnknownSession),
+        }
+    }
+
+    /// 登录用户是否有连接以 writer 或 owner 身份参与会话
+    ///
+    /// HTTP 接口 (如AI对话) 没有 client_id，按用户判断。
+    pub fn user_can_write(&self, user: &str) -> bool {
+        self.participants.iter().any(|p| p.role.can_write() && p.user.as_deref() == Some(user))
+    }
+
+    pub fn participants(&self) -> Vec<ParticipantInfo> {
+        self.participants
+            .iter()
+            .map(|p| ParticipantInfo { client_id: *p.key(), role: p.role, via_link: p.via_link })
+            .collect()
+    }
+
+    pub fn summary(&self) -> SessionSummary {
+        SessionSummary {
+            session_id: self.id,
+            host: self.host.clone(),
+            owner: self.owner_user.clone(),
+            detached: self.owner().is_none(),
+            participants: self.participants.len(),
+        }
+    }
+
+    /// 加入会话并通知其他参与者，返回加入后的参与者列表
+    pub async fn join(
+        &self,
+        client_id: Uuid,
+        tx: mpsc::Sender<String>,
+        user: Option<&str>,
+        role: Role,
+        via_link: Option<Uuid>,
+    ) -> Vec<ParticipantInfo> {
+        self.participants.insert(client_id, Participant { role, tx, user: user.map(str::to_string), via_link });
+        info!("客户端 {} 以 {:?} 身份加入会话 {}", client_id, role, self.id);
+        self.broadcast_except(client_id, &WebSocketResponse::ParticipantJoined {
+            session_id: self.id,
+            client_id,
+            role,
+        }).await;
+        self.participants()
+    }
+
+    pub async fn leave(&self, client_id: Uuid) {
+        if self.participants.remove(&client_id).is_some() {
+            info!("客户端 {} 离开会话 {}", client_id, self.id);
+            self.broadcast(&WebSocketResponse::ParticipantLeft {
+                session_id: self.id,
+                client_id,
+            }).await;
+        }
+    }
+
+    /// 将非 owner 参与者移出会话
+    pub async fn kick(&self, client_id: Uuid) -> bool {
+        let Some((_, p)) = self.participants.remove_if(&client_id, |_, p| p.role != Role::Owner) else {
+            return false;
+        };
+        let message = serde_json::to_string(&WebSocketResponse::Disconnected { session_id: self.id }).unwrap();
+        let _ = p.tx.send(message).await;
+        info!("客户端 {} 被移出会话 {}", client_id, self.id);
+        self.broadcast(&WebSocketResponse::ParticipantLeft {
+            session_id: self.id,
+            client_id,
+        }).await;
+        true
+    }
+
+    /// 创建分享链接，到期后自动撤销并移出通过它加入的参与者
+    pub fn create_link(self: &Arc<Self>, role: Role, ttl: std::time::Duration) -> ShareLinkInfo {
+        let link_id = Uuid::new_v4();
+        let expires_at = Utc::now() + chrono::Duration::from_std(ttl).unwrap_or_else(|_| chrono::Duration::hours(1));
+        self.links.insert(link_id, ShareLink { role, expires_at });
+
+        let session = Arc::downgrade(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(ttl).await;
+            if let Some(session) = session.upgrade() {
+                session.revoke_link(link_id).await;
+            }
+        });
+
+        ShareLinkInfo { link_id, role, expires_at, viewers: 0 }
+    }
+
+    /// 链接仍然有效时返回它授予的角色
+    pub fn link_role(&self, link_id: Uuid) -> Option<Role> {
+        self.links
+            .get(&link_id)
+            .filter(|l| l.expires_at > Utc::now())
+            .map(|l| l.role)
+    }
+
+    pub fn links(&self) -> Vec<ShareLinkInfo> {
+        self.links
+            .iter()
+            .map(|l| ShareLinkInfo {
+                link_id: *l.key(),
+                role: l.role,
+                expires_at: l.expires_at,
+                viewers: self.participants.iter().filter(|p| p.via_link == Some(*l.key())).count(),
+            })
+            .collect()
+    }
+
+    pub async fn revoke_link(&self, link_id: Uuid) -> bool {
+        if self.links.remove(&link_id).is_none() {
+            return false;
+        }
+        let viewers: Vec<Uuid> = self.participants
+            .iter()
+            .filter(|p| p.via_link == Some(link_id))
+            .map(|p| *p.key())
+            .collect();
+        for client_id in viewers {
+            self.kick(client_id).await;
+        }
+        info!("会话 {} 的分享链接 {} 已撤销", self.id, link_id);
+        true
+    }
+
+    /// 调整非 owner 参与者的角色，成功时通知所有参与者
+    pub async fn set_role(&self, client_id: Uuid, role: Role) -> bool {
+        let changed = match self.participants.get_mut(&client_id) {
+            Some(mut p) if p.role != Role::Owner && role != Role::Owner => {
+                p.role = role;
+                true
+            }
+            _ => false,
+        };
+        if changed {
+            self.broadcast(&WebSocketResponse::RoleChanged {
+                session_id: self.id,
+                client_id,
+                role,
+            }).await;
+        }
+        changed
+    }
+
+    /// 关闭后端连接并结束会话
+    pub async fn close(&self, sessions: &Sessions, reason: &'static str) {
+        self.backend.close().await;
+        self.terminate(sessions, reason).await;
+    }
+
+    /// 从会话表中移除并通知所有参与者，重复调用只生效一次
+    pub async fn terminate(&self, sessions: &Sessions, reason: &'static str) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        sessions.remove(&self.id);
+        decrement_gauge!("sessions_active", 1.0);
+        if self.owner().is_none() {
+            decrement_gauge!("sessions_detached", 1.0);
+        }
+        counter!("sessions_closed_total", 1, "reason" => reason);
+        self.broadcast(&WebSocketResponse::Disconnected { session_id: self.id }).await;
+        self.participants.clear();
+        self.links.clear();
+        info!("会话 {} 已结束 ({})", self.id, reason);
+        audit::record(self.owner_user.as_deref(), self.host.as_deref(), AuditEvent::Disconnect {
+            session_id: self.id,
+            reason: reason.to_string(),
+        });
+    }
+
+    pub async fn broadcast(&self, response: &WebSocketResponse) {
+        self.broadcast_except(Uuid::nil(), response).await;
+    }
+
+    /// 扇出不等待单个参与者：非 owner 的发送队列已满说明其连接跟不上输出，直接移出会话，
+    /// 避免一个慢速的观看者拖慢 owner 和其他参与者；owner 的输出不能丢，在其余参与者之后等待
+    async fn broadcast_except(&self, except: Uuid, response: &WebSocketResponse) {
+        let mut backlog = Vec::new();
+        let mut lagging = self.fan_out(except, serde_json::to_string(response).unwrap(), &mut backlog);
+        while let Some(client_id) = lagging.pop() {
+            let Some((_, p)) = self.participants.remove(&client_id) else {
+                continue;
+            };
+            warn!("客户端 {} 跟不上会话 {} 的输出，已移出", client_id, self.id);
+            counter!("session_participants_dropped_total", 1);
+            // 队列腾出空间后再告知对方，不阻塞扇出
+            let message = serde_json::to_string(&WebSocketResponse::Disconnected { session_id: self.id }).unwrap();
+            tokio::spawn(async move {
+                let _ = p.tx.send(message).await;
+            });
+            let left = WebSocketResponse::ParticipantLeft { session_id: self.id, client_id };
+            lagging.extend(self.fan_out(Uuid::nil(), serde_json::to_string(&left).unwrap(), &mut backlog));
+        }
+        for (client_id, tx, message) in backlog {
+            if tx.send(message).await.is_err() {
+                self.participants.remove(&client_id);
+            }
+        }
+    }
+
+    /// 用 try_send 发给每个参与者，返回队列已满的非 owner 参与者；owner 的消息放入 backlog
+    fn fan_out(&self, except: Uuid, message: String, backlog: &mut Vec<(Uuid, mpsc::Sender<String>, String)>) -> Vec<Uuid> {
+        let targets: Vec<_> = self.participants
+            .iter()
+            .filter(|p| *p.key() != except)
+            .map(|p| (*p.key(), p.role, p.tx.clone()))
+            .collect();
+
+        let mut lagging = Vec::new();
+        for (client_id, role, tx) in targets {
+            match tx.try_send(message.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Closed(_)) => {
+                    // 对应的WebSocket已断开
+                    self.participants.remove(&client_id);
+                }
+                Err(TrySendError::Full(message)) if role == Role::Owner => backlog.push((client_id, tx, message)),
+                Err(TrySendError::Full(_)) => lagging.push(client_id),
+            }
+        }
+        lagging
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use crate::terminal::TerminalError;
+
+    struct NullBackend(Uuid);
+
+    #[async_trait]
+    impl TerminalBackend for NullBackend {
+        fn id(&self) -> Uuid {
+            self.0
+        }
+
+        fn kind(&self) -> &'static str {
+            "null"
+        }
+
+        async fn write(&self, _data: &str) -> Result<(), TerminalError> {
+            Ok(())
+        }
+
+        async fn resize(&self, _cols: u16, _rows: u16) -> Result<(), TerminalError> {
+            Ok(())
+        }
+
+        async fn close(&self) {}
+    }
+
+    #[tokio::test]
+    async fn slow_viewer_is_dropped_without_stalling_others() {
+        let sessions = Sessions::default();
+        let (owner_tx, mut owner_rx) = mpsc::channel(100);
+        let owner = Uuid::new_v4();
+        let session = SharedSession::register(Arc::new(NullBackend(Uuid::new_v4())), None, owner, None, owner_tx, &sessions);
+
+        let (fast_tx, mut fast_rx) = mpsc::channel(100);
+        let fast = Uuid::new_v4();
+        session.join(fast, fast_tx, None, Role::Viewer, None).await;
+        let (slow_tx, _slow_rx) = mpsc::channel(1);
+        let slow = Uuid::new_v4();
+        session.join(slow, slow_tx, None, Role::Viewer, None).await;
+        while owner_rx.try_recv().is_ok() {}
+        while fast_rx.try_recv().is_ok() {}
+
+        let output = WebSocketResponse::Disconnected { session_id: session.id };
+        for _ in 0..3 {
+            tokio::time::timeout(Duration::from_secs(1), session.broadcast(&output)).await.unwrap();
+        }
+
+        assert_eq!(session.role_of(slow), None);
+        assert_eq!(session.role_of(fast), Some(Role::Viewer));
+        // 3 条输出 + 慢速观看者离开的通知
+        let mut owner_got = 0;
+        while owner_rx.try_recv().is_ok() {
+            owner_got += 1;
+        }
+        let mut fast_got = 0;
+        while fast_rx.try_recv().is_ok() {
+            fast_got += 1;
+        }
+        assert_eq!(owner_got, 4);
+        assert_eq!(fast_got, 4);
+    }
+
+    #[tokio::test]
+    async fn revoked_and_expired_links_stop_admitting() {
+        let sessions = Sessions::default();
+        let (owner_tx, _owner_rx) = mpsc::channel(100);
+        let session = SharedSession::register(Arc::new(NullBackend(Uuid::new_v4())), None, Uuid::new_v4(), None, owner_tx, &sessions);
+
+        let link = session.create_link(Role::Viewer, Duration::from_secs(60));
+        assert_eq!(session.link_role(link.link_id), Some(Role::Viewer));
+        let expired = session.create_link(Role::Writer, Duration::ZERO);
+        assert_eq!(session.link_role(expired.link_id), None);
+
+        let (guest_tx, mut guest_rx) = mpsc::channel(100);
+        let guest = Uuid::new_v4();
+        session.join(guest, guest_tx, None, Role::Viewer, Some(link.link_id)).await;
+        assert_eq!(session.links().iter().find(|l| l.link_id == link.link_id).map(|l| l.viewers), Some(1));
+
+        // 撤销后链接失效，经由它加入的参与者被移出
+        assert!(session.revoke_link(link.link_id).await);
+        assert_eq!(session.link_role(link.link_id), None);
+        assert_eq!(session.role_of(guest), None);
+        let mut disconnected = false;
+        while let Ok(message) = guest_rx.try_recv() {
+            disconnected |= message.contains("\"disconnected\"");
+        }
+        assert!(disconnected);
+        assert!(!session.revoke_link(link.link_id).await);
+    }
+
+    #[tokio::test]
+    async fn only_writing_participants_can_use_the_session() {
+        let sessions = Sessions::default();
+        let (tx, _rx) = mpsc::channel(100);
+        let owner = Uuid::new_v4();
+        let session = SharedSession::register(Arc::new(NullBackend(Uuid::new_v4())), None, owner, Some("alice".to_string()), tx.clone(), &sessions);
+        let viewer = Uuid::new_v4();
+        session.join(viewer, tx.clone(), Some("bob"), Role::Viewer, None).await;
+        assert!(session.user_can_write("alice"));
+        assert!(!session.user_can_write("bob"));
+        assert!(!session.user_can_write("mallory"));
+
+        assert!(session.set_role(viewer, Role::Writer).await);
+        assert!(session.user_can_write("bob"));
+        session.leave(owner).await;
+        assert!(!session.user_can_write("alice"));
+    }
+}
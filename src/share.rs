@@ -0,0 +1,124 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::models::Role;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Error, Debug)]
+pub enum ShareError {
+    #[error("分享链接格式无效")]
+    Malformed,
+    #[error("分享链接签名无效")]
+    BadSignature,
+    #[error("分享链接已过期")]
+    Expired,
+    #[error("分享链接已被撤销")]
+    Revoked,
+}
+
+/// 分享链接中携带的声明，签名后编码进URL
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareClaims {
+    pub link_id: Uuid,
+    pub session_id: Uuid,
+    pub role: Role,
+    /// Unix 时间戳 (秒)
+    pub expires_at: i64,
+}
+
+/// 用 HMAC-SHA256 签发和校验分享链接
+pub struct ShareSigner {
+    key: Vec<u8>,
+}
+
+impl ShareSigner {
+    /// 未配置密钥时每次启动随机生成，重启后旧链接自然失效
+    pub fn new(secret: Option<&str>) -> Self {
+        let key = match secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => {
+                let mut key = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut key);
+                key
+            }
+        };
+        ShareSigner { key }
+    }
+
+    pub fn sign(&self, claims: &ShareClaims) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap());
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(payload.as_bytes()).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    /// 校验签名和有效期，是否已撤销由会话自己判断
+    pub fn verify(&self, token: &str) -> Result<ShareClaims, ShareError> {
+        let (payload, signature) = token.split_once('.').ok_or(ShareError::Malformed)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| ShareError::Malformed)?;
+        self.mac(payload.as_bytes())
+            .verify_slice(&signature)
+            .map_err(|_| ShareError::BadSignature)?;
+
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| ShareError::Malformed)?;
+        let claims: ShareClaims = serde_json::from_slice(&payload).map_err(|_| ShareError::Malformed)?;
+        if claims.expires_at <= chrono::Utc::now().timestamp() {
+            return Err(ShareError::Expired);
+        }
+        Ok(claims)
+    }
+
+    fn mac(&self, data: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(data);
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(expires_in: i64) -> ShareClaims {
+        ShareClaims {
+            link_id: Uuid::new_v4(),
+            session_id: Uuid::new_v4(),
+            role: Role::Writer,
+            expires_at: chrono::Utc::now().timestamp() + expires_in,
+        }
+    }
+
+    #[test]
+    fn signed_tokens_verify_until_expiry() {
+        let signer = ShareSigner::new(Some("share-secret"));
+        let issued = claims(60);
+        let verified = signer.verify(&signer.sign(&issued)).unwrap();
+        assert_eq!((verified.link_id, verified.session_id, verified.role), (issued.link_id, issued.session_id, Role::Writer));
+
+        // 同一密钥重启后仍然有效，随机密钥则不认旧令牌
+        assert!(ShareSigner::new(Some("share-secret")).verify(&signer.sign(&issued)).is_ok());
+        assert!(matches!(ShareSigner::new(None).verify(&signer.sign(&issued)), Err(ShareError::BadSignature)));
+
+        assert!(matches!(signer.verify(&signer.sign(&claims(-1))), Err(ShareError::Expired)));
+        assert!(matches!(signer.verify("no-dot"), Err(ShareError::Malformed)));
+    }
+
+    #[test]
+    fn tampered_claims_are_rejected() {
+        let signer = ShareSigner::new(Some("share-secret"));
+        let token = signer.sign(&claims(60));
+        let (_, signature) = token.split_once('.').unwrap();
+
+        // 把角色改成 owner 并沿用原签名
+        let mut forged = claims(60);
+        forged.role = Role::Owner;
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+        assert!(matches!(signer.verify(&format!("{}.{}", payload, signature)), Err(ShareError::BadSignature)));
+    }
+}
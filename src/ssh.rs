@@ -1,10 +1,18 @@
-use ssh2::Session;
+use async_trait::async_trait;
+use ssh2::{ErrorCode, MethodType, Session};
 use std::io::{Read, Write};
+use std::net::SocketAddr;
 use tokio::sync::mpsc;
 use tracing::{info, error, warn};
 use thiserror::Error;
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::Connections;
+use crate::config::{SSHAlgorithms, SSHOptions};
+use crate::terminal::{SessionEvent, TerminalBackend, TerminalError};
 
 #[derive(Error, Debug)]
 pub enum SSHError {
@@ -18,150 +26,709 @@ pub enum SSHError {
     NetworkTimeout { host: String, port: u16 },
     #[error("握手失败: SSH协议握手失败")]
     HandshakeFailed,
+    #[error("算法配置无效: {0}")]
+    InvalidAlgorithm(String),
+    #[error("通道已关闭")]
+    ChannelClosed,
     #[error("IO错误: {0}")]
     IoError(#[from] std::io::Error),
 }
 
-pub struct SSHSession {
-    #[allow(dead_code)] // 企业级项目中可能需要直接访问session
+/// SSH 登录方式
+#[derive(Clone)]
+pub enum SSHAuth {
+    Password(String),
+    /// PEM 或 OpenSSH 格式的私钥，公钥由私钥推导
+    PrivateKey { key: String, passphrase: Option<String> },
+}
+
+/// 重连时需要复用的连接参数
+#[derive(Clone)]
+struct ConnectParams {
+    host: String,
+    port: u16,
+    /// 通过 destinations 检查的地址，重连时也只连接这些地址
+    addrs: Vec<SocketAddr>,
+    username: String,
+    auth: SSHAuth,
+    options: SSHOptions,
+}
+
+/// 关闭通道和断开传输时等待对端的最长时间
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 单次输入写入的最长时间，对端窗口一直不打开时放弃
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 探测请求使用的环境变量名，服务器接受或拒绝都说明对端存活
+const PROBE_ENV: &str = "SSH_AI_TERMINAL_PING";
+
+enum ProbeError {
+    Timeout,
+    Fatal(ssh2::Error),
+}
+
+/// 一条已认证的SSH传输连接，可承载多个Shell通道
+///
+/// 所有通道共用同一个 libssh2 会话，读写、探测和重连都在 `inner` 锁内串行进行，
+/// 锁内只做非阻塞或限时的操作；最后一个通道关闭时断开传输并从 `Connections` 中移除。
+pub struct SSHConnection {
+    pub id: Uuid,
+    params: ConnectParams,
+    inner: tokio::sync::Mutex<ConnectionInner>,
+}
+
+struct ConnectionInner {
     session: Session,
+    channels: ChannelTable<ChannelEntry>,
+}
+
+/// 连接上的通道表；最后一个通道移除后连接即关闭，之后不能再加入新通道
+struct ChannelTable<T> {
+    entries: HashMap<Uuid, T>,
+    closed: bool,
+}
+
+impl<T> ChannelTable<T> {
+    fn new() -> Self {
+        ChannelTable { entries: HashMap::new(), closed: false }
+    }
+
+    fn insert(&mut self, id: Uuid, entry: T) -> Result<(), SSHError> {
+        if self.closed {
+            return Err(SSHError::ChannelClosed);
+        }
+        self.entries.insert(id, entry);
+        Ok(())
+    }
+
+    /// 移除通道，返回被移除的通道以及是否因为它是最后一个而需要断开传输
+    fn remove(&mut self, id: Uuid) -> (Option<T>, bool) {
+        let entry = self.entries.remove(&id);
+        let last = self.entries.is_empty() && !self.closed;
+        if last {
+            self.closed = true;
+        }
+        (entry, last)
+    }
+
+    /// 连接失效且无法重连时丢弃全部通道
+    fn close_all(&mut self) {
+        self.entries.clear();
+        self.closed = true;
+    }
+}
+
+struct ChannelEntry {
     channel: ssh2::Channel,
+    tx: mpsc::Sender<SessionEvent>,
+}
+
+/// 连接上的单个Shell通道，`id` 即对外的 session_id
+pub struct SSHSession {
+    pub id: Uuid,
+    connection: Arc<SSHConnection>,
+    /// 保证同一通道上的多次输入不会交错写入
+    writing: tokio::sync::Mutex<()>,
 }
 
 impl SSHSession {
+    /// 建立新的SSH连接并打开第一个Shell通道
     pub async fn new(
         host: &str,
         port: u16,
+        addrs: Vec<SocketAddr>,
         username: &str,
-        password: &str,
-    ) -> Result<(Arc<tokio::sync::Mutex<Self>>, mpsc::Receiver<String>), SSHError> {
-        info!("尝试连接到 SSH 服务器: {}:{}", host, port);
-        
-        // 尝试TCP连接，设置超时
-        let tcp = match std::net::TcpStream::connect_timeout(
-            &format!("{}:{}", host, port).parse().unwrap(),
-            Duration::from_secs(10)
-        ) {
-            Ok(stream) => {
-                info!("TCP连接成功建立到 {}:{}", host, port);
-                stream
-            }
-            Err(e) => {
-                error!("TCP连接失败到 {}:{} - {}", host, port, e);
-                return Err(SSHError::NetworkTimeout { 
-                    host: host.to_string(), 
-                    port 
-                });
-            }
+        auth: SSHAuth,
+        options: &SSHOptions,
+        connections: &Connections,
+    ) -> Result<(Self, mpsc::Receiver<SessionEvent>), SSHError> {
+        let params = ConnectParams {
+            host: host.to_string(),
+            port,
+            addrs,
+            username: username.to_string(),
+            auth,
+            options: options.clone(),
         };
-        
-        // 设置TCP流为非阻塞模式
-        tcp.set_nonblocking(false)
-            .map_err(|e| SSHError::ConnectionFailed(format!("设置TCP流失败: {}", e)))?;
-        
-        let mut session = Session::new()
-            .map_err(|e| SSHError::ConnectionFailed(format!("创建SSH会话失败: {}", e)))?;
-        
-        session.set_tcp_stream(tcp);
-        
-        info!("开始SSH握手...");
-        session.handshake()
-            .map_err(|e| {
-                error!("SSH握手失败: {}", e);
-                SSHError::HandshakeFailed
-            })?;
-        
-        info!("SSH握手成功，开始认证用户: {}", username);
-        session.userauth_password(username, password)
-            .map_err(|e| {
-                error!("SSH认证失败: {}", e);
-                SSHError::AuthenticationFailed
-            })?;
-        
-        if !session.authenticated() {
-            warn!("SSH认证检查失败");
-            return Err(SSHError::AuthenticationFailed);
-        }
-        
-        info!("SSH认证成功");
-        let mut channel = session.channel_session()
-            .map_err(|e| {
-                error!("创建SSH通道失败: {}", e);
-                SSHError::ChannelCreationFailed
-            })?;
-        
-        channel.request_pty("xterm", None, None)
-            .map_err(|e| {
-                error!("请求PTY失败: {}", e);
-                SSHError::ChannelCreationFailed
-            })?;
-        
-        channel.shell()
-            .map_err(|e| {
-                error!("启动Shell失败: {}", e);
-                SSHError::ChannelCreationFailed
-            })?;
-        
+
+        let session = {
+            let params = params.clone();
+            tokio::task::spawn_blocking(move || authenticate(&params))
+                .await
+                .map_err(|e| SSHError::ConnectionFailed(e.to_string()))??
+        };
+        session.set_blocking(false);
+
+        let connection = Arc::new(SSHConnection {
+            id: Uuid::new_v4(),
+            params,
+            inner: tokio::sync::Mutex::new(ConnectionInner {
+                session,
+                channels: ChannelTable::new(),
+            }),
+        });
+
+        let opened = connection.open_channel().await?;
+        connections.insert(connection.id, connection.clone());
+        tokio::spawn(connection.clone().run(connections.clone()));
+
+        info!("SSH会话创建完成");
+        Ok(opened)
+    }
+
+}
+
+#[async_trait]
+impl TerminalBackend for SSHSession {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        "ssh"
+    }
+
+    fn connection_id(&self) -> Option<Uuid> {
+        Some(self.connection.id)
+    }
+
+    /// 非阻塞写入，对端窗口已满时先释放连接锁再重试，卡住的通道不会阻塞同一连接上的其它通道
+    async fn write(&self, data: &str) -> Result<(), TerminalError> {
+        let _writing = self.writing.lock().await;
+        let deadline = Instant::now() + WRITE_TIMEOUT;
+        let mut remaining = data.as_bytes();
+        while !remaining.is_empty() {
+            let written = {
+                let mut inner = self.connection.inner.lock().await;
+                let entry = inner.channels.entries.get_mut(&self.id).ok_or(SSHError::ChannelClosed)?;
+                match entry.channel.write(remaining) {
+                    Ok(n) => n,
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => 0,
+                    Err(e) => return Err(SSHError::IoError(e).into()),
+                }
+            };
+            remaining = &remaining[written..];
+            if written == 0 {
+                if Instant::now() >= deadline {
+                    let e = std::io::Error::new(std::io::ErrorKind::TimedOut, "写入超时，对端没有读取输入");
+                    return Err(SSHError::IoError(e).into());
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn resize(&self, cols: u16, rows: u16) -> Result<(), TerminalError> {
+        let mut inner = self.connection.inner.lock().await;
+        let ConnectionInner { session, channels } = &mut *inner;
+        let entry = channels.entries.get_mut(&self.id).ok_or(SSHError::ChannelClosed)?;
+
+        session.set_blocking(true);
+        session.set_timeout(WRITE_TIMEOUT.as_millis() as u32);
+        let result = tokio::task::block_in_place(|| {
+            entry.channel.request_pty_size(cols as u32, rows as u32, None, None)
+        });
+        session.set_timeout(0);
         session.set_blocking(false);
-        
+        result.map_err(|e| SSHError::ConnectionFailed(format!("调整终端大小失败: {}", e)))?;
+        Ok(())
+    }
+
+    /// 关闭本通道，若为连接上的最后一个通道则同时断开传输
+    async fn close(&self) {
+        self.connection.close_channel(self.id).await;
+    }
+}
+
+impl SSHConnection {
+    /// 在已认证的连接上打开新的Shell通道，无需重新握手和认证
+    ///
+    /// 与写入一样使用非阻塞模式并在 `WRITE_TIMEOUT` 后放弃，每一步只在尝试时持有连接锁，
+    /// 对端迟迟不应答时不会卡住同一连接上的其它通道。
+    pub async fn open_channel(self: &Arc<Self>) -> Result<(SSHSession, mpsc::Receiver<SessionEvent>), SSHError> {
+        let deadline = Instant::now() + WRITE_TIMEOUT;
+        let mut channel = self.retry_nonblocking(deadline, "创建SSH通道", |session| session.channel_session()).await?;
+        self.retry_nonblocking(deadline, "请求PTY", |_| channel.request_pty("xterm", None, None)).await?;
+        self.retry_nonblocking(deadline, "启动Shell", |_| channel.shell()).await?;
+
+        let mut inner = self.inner.lock().await;
+        if inner.channels.closed {
+            return Err(SSHError::ChannelClosed);
+        }
+        let id = Uuid::new_v4();
         let (tx, rx) = mpsc::channel(100);
-        
-        let ssh_session = Arc::new(tokio::sync::Mutex::new(SSHSession {
-            session,
-            channel,
-        }));
-        
-        let ssh_clone = ssh_session.clone();
-        tokio::spawn(async move {
-            let mut buffer = [0u8; 4096];
-            loop {
-                let data = {
-                    let mut ssh = ssh_clone.lock().await;
-                    match ssh.channel.read(&mut buffer) {
-                        Ok(0) => {
-                            info!("SSH通道已关闭");
-                            break;
+        inner.channels.insert(id, ChannelEntry { channel, tx })?;
+        info!("SSH连接 {} 上打开通道 {} (共{}个)", self.id, id, inner.channels.entries.len());
+
+        Ok((SSHSession { id, connection: self.clone(), writing: tokio::sync::Mutex::new(()) }, rx))
+    }
+
+    /// 反复尝试一步非阻塞操作直到不再返回 EAGAIN，两次尝试之间释放连接锁
+    async fn retry_nonblocking<T>(
+        &self,
+        deadline: Instant,
+        step: &str,
+        mut attempt: impl FnMut(&Session) -> Result<T, ssh2::Error>,
+    ) -> Result<T, SSHError> {
+        loop {
+            {
+                let inner = self.inner.lock().await;
+                if inner.channels.closed {
+                    return Err(SSHError::ChannelClosed);
+                }
+                match attempt(&inner.session) {
+                    Ok(value) => return Ok(value),
+                    Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => {}
+                    Err(e) => {
+                        error!("{}失败: {}", step, e);
+                        return Err(SSHError::ChannelCreationFailed);
+                    }
+                }
+            }
+            if Instant::now() >= deadline {
+                error!("{}超时", step);
+                return Err(SSHError::ChannelCreationFailed);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn close_channel(&self, id: Uuid) {
+        let mut inner = self.inner.lock().await;
+        let (entry, last) = inner.channels.remove(id);
+
+        // 非阻塞模式下 EOF/CLOSE 可能因 EAGAIN 未发出，限时阻塞发送；对端已失效时不会卡住
+        inner.session.set_blocking(true);
+        inner.session.set_timeout(CLOSE_TIMEOUT.as_millis() as u32);
+        tokio::task::block_in_place(|| {
+            if let Some(mut entry) = entry {
+                let _ = entry.channel.send_eof();
+                let _ = entry.channel.close();
+                let _ = entry.channel.wait_close();
+            }
+            if last {
+                let _ = inner.session.disconnect(None, "all channels closed", None);
+            }
+        });
+        inner.session.set_timeout(0);
+        inner.session.set_blocking(false);
+
+        info!("SSH连接 {} 上的通道 {} 已关闭 (剩余{}个)", self.id, id, inner.channels.entries.len());
+        if last {
+            info!("SSH连接 {} 已无通道，断开传输", self.id);
+        }
+    }
+
+    /// 读取所有通道的输出、发送保活并探测对端，连接失效后按配置自动重连
+    async fn run(self: Arc<Self>, connections: Connections) {
+        let options = &self.params.options;
+        let probe_interval = Duration::from_secs(options.keep_alive as u64);
+        let probe_timeout = Duration::from_secs((options.keep_alive as u64).clamp(1, 10));
+        let mut last_probe = Instant::now();
+        let mut missed_probes = 0;
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            let mut events = Vec::new();
+            let mut finished = Vec::new();
+            let mut alive = true;
+            {
+                let mut inner = self.inner.lock().await;
+                if inner.channels.closed {
+                    break;
+                }
+                for (id, entry) in inner.channels.entries.iter_mut() {
+                    if entry.tx.is_closed() {
+                        // 接收端已经不存在，没有人再关心这个通道
+                        finished.push(*id);
+                        continue;
+                    }
+                    let result = entry.channel.read(&mut buffer);
+                    match classify_read(&result, entry.channel.eof()) {
+                        ReadOutcome::RemoteClosed => {
+                            info!("SSH通道 {} 已被远端关闭", id);
+                            finished.push(*id);
                         }
-                        Ok(n) => Some(String::from_utf8_lossy(&buffer[..n]).to_string()),
-                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => None,
-                        Err(e) => {
-                            error!("SSH读取错误: {}", e);
+                        ReadOutcome::Idle => {}
+                        ReadOutcome::Data(n) => events.push((
+                            entry.tx.clone(),
+                            SessionEvent::Data(String::from_utf8_lossy(&buffer[..n]).to_string()),
+                        )),
+                        ReadOutcome::TransportFailed => {
+                            if let Err(e) = result {
+                                error!("SSH读取错误: {}", e);
+                            }
+                            alive = false;
                             break;
                         }
                     }
+                }
+            }
+
+            let idle = events.is_empty();
+            for (tx, event) in events {
+                let _ = tx.send(event).await;
+            }
+            for id in finished {
+                self.notify(id, SessionEvent::Closed).await;
+                self.close_channel(id).await;
+            }
+
+            if alive && options.keep_alive > 0 && last_probe.elapsed() >= probe_interval {
+                last_probe = Instant::now();
+                let result = {
+                    let mut inner = self.inner.lock().await;
+                    tokio::task::block_in_place(|| inner.probe(probe_timeout))
                 };
-                
-                if let Some(data) = data {
-                    if tx.send(data).await.is_err() {
-                        break;
+                match result {
+                    Ok(Some(latency)) => {
+                        missed_probes = 0;
+                        self.broadcast(|| SessionEvent::Status {
+                            connected: true,
+                            latency_ms: Some(latency.as_millis() as u64),
+                        }).await;
+                    }
+                    Ok(None) => {}
+                    Err(ProbeError::Timeout) => {
+                        missed_probes += 1;
+                        warn!("SSH保活探测超时 ({}/{}): {}:{}",
+                            missed_probes, options.keep_alive_count_max, self.params.host, self.params.port);
+                        alive = missed_probes < options.keep_alive_count_max;
+                    }
+                    Err(ProbeError::Fatal(e)) => {
+                        error!("SSH保活探测失败: {}", e);
+                        alive = false;
                     }
                 }
-                
+            }
+
+            if !alive {
+                self.broadcast(|| SessionEvent::Status { connected: false, latency_ms: None }).await;
+                if !options.auto_reconnect || !self.reconnect().await {
+                    self.broadcast(|| SessionEvent::Closed).await;
+                    let mut inner = self.inner.lock().await;
+                    inner.channels.close_all();
+                    break;
+                }
+                missed_probes = 0;
+                last_probe = Instant::now();
+                self.broadcast(|| SessionEvent::Status { connected: true, latency_ms: None }).await;
+                continue;
+            }
+
+            if idle {
                 tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
             }
-        });
-        
-        info!("SSH会话创建完成");
-        Ok((ssh_session, rx))
+        }
+
+        connections.remove(&self.id);
+        info!("SSH连接 {} 的读取任务已退出", self.id);
     }
 
-    pub fn write(&mut self, data: &str) -> Result<(), SSHError> {
-        self.channel.write_all(data.as_bytes())?;
-        self.channel.flush()?;
-        Ok(())
+    async fn notify(&self, id: Uuid, event: SessionEvent) {
+        let tx = self.inner.lock().await.channels.entries.get(&id).map(|e| e.tx.clone());
+        if let Some(tx) = tx {
+            let _ = tx.send(event).await;
+        }
+    }
+
+    async fn broadcast(&self, event: impl Fn() -> SessionEvent) {
+        let senders: Vec<_> = self.inner.lock().await.channels.entries.values().map(|e| e.tx.clone()).collect();
+        for tx in senders {
+            let _ = tx.send(event()).await;
+        }
+    }
+
+    /// 使用相同的凭据重新认证，并为每个现有通道打开新的Shell
+    async fn reconnect(&self) -> bool {
+        let params = &self.params;
+        for attempt in 1..=params.options.reconnect_attempts {
+            tokio::time::sleep(Duration::from_secs(params.options.reconnect_delay)).await;
+            info!("尝试重新连接 {}:{} (第{}次)", params.host, params.port, attempt);
+
+            let cloned = params.clone();
+            let session = match tokio::task::spawn_blocking(move || authenticate(&cloned)).await {
+                Ok(Ok(session)) => session,
+                Ok(Err(e)) => {
+                    warn!("重新连接失败: {}", e);
+                    continue;
+                }
+                Err(e) => {
+                    error!("重新连接任务异常: {}", e);
+                    continue;
+                }
+            };
+
+            let mut inner = self.inner.lock().await;
+            session.set_timeout(WRITE_TIMEOUT.as_millis() as u32);
+            let reopened = tokio::task::block_in_place(|| {
+                inner.channels.entries.values_mut().try_for_each(|entry| {
+                    entry.channel = open_shell(&session)?;
+                    Ok::<_, SSHError>(())
+                })
+            });
+            session.set_timeout(0);
+            match reopened {
+                Ok(()) => {
+                    session.set_blocking(false);
+                    let _ = inner.session.disconnect(None, "reconnecting", None);
+                    inner.session = session;
+                    info!("SSH连接 {} 已重新连接", self.id);
+                    return true;
+                }
+                Err(e) => warn!("重新打开Shell失败: {}", e),
+            }
+        }
+        false
     }
 
     #[allow(dead_code)] // 企业级项目中的命令执行功能
-    pub fn execute_command(&mut self, command: &str) -> Result<String, SSHError> {
-        let mut channel = self.session.channel_session()
-            .map_err(|_| SSHError::ChannelCreationFailed)?;
-        
-        channel.exec(command)
-            .map_err(|_| SSHError::ChannelCreationFailed)?;
-        
-        let mut output = String::new();
-        channel.read_to_string(&mut output)?;
-        
-        Ok(output)
+    pub async fn execute_command(&self, command: &str) -> Result<String, SSHError> {
+        let inner = self.inner.lock().await;
+        inner.session.set_blocking(true);
+        let result = tokio::task::block_in_place(|| {
+            let mut channel = inner.session.channel_session()
+                .map_err(|_| SSHError::ChannelCreationFailed)?;
+            
+            channel.exec(command)
+                .map_err(|_| SSHError::ChannelCreationFailed)?;
+            
+            let mut output = String::new();
+            channel.read_to_string(&mut output)?;
+            Ok(output)
+        });
+        inner.session.set_blocking(false);
+        result
+    }
+}
+
+impl ConnectionInner {
+    /// 在任一通道上发送需要应答的请求来测量往返时延
+    fn probe(&mut self, timeout: Duration) -> Result<Option<Duration>, ProbeError> {
+        if let Err(e) = self.session.keepalive_send() {
+            return Err(ProbeError::Fatal(e));
+        }
+        let Some(entry) = self.channels.entries.values_mut().next() else {
+            return Ok(None);
+        };
+
+        self.session.set_blocking(true);
+        self.session.set_timeout(timeout.as_millis() as u32);
+        let started = Instant::now();
+        let result = entry.channel.setenv(PROBE_ENV, "1");
+        self.session.set_timeout(0);
+        self.session.set_blocking(false);
+
+        match result {
+            Ok(()) => Ok(Some(started.elapsed())),
+            Err(e) => match e.code() {
+                ErrorCode::Session(LIBSSH2_ERROR_CHANNEL_REQUEST_DENIED) => Ok(Some(started.elapsed())),
+                ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT) => Err(ProbeError::Timeout),
+                _ => Err(ProbeError::Fatal(e)),
+            },
+        }
+    }
+}
+
+/// 一次通道读取的结果
+#[derive(Debug, PartialEq, Eq)]
+enum ReadOutcome {
+    Data(usize),
+    Idle,
+    /// 远端发送了 EOF，例如用户输入 `exit`；只结束该通道，不触发重连
+    RemoteClosed,
+    /// 传输层故障，判定连接失效并按配置重连
+    TransportFailed,
+}
+
+fn classify_read(result: &std::io::Result<usize>, eof: bool) -> ReadOutcome {
+    match result {
+        Ok(0) if eof => ReadOutcome::RemoteClosed,
+        Ok(0) => ReadOutcome::Idle,
+        Ok(n) => ReadOutcome::Data(*n),
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => ReadOutcome::Idle,
+        Err(_) => ReadOutcome::TransportFailed,
+    }
+}
+
+/// 建立TCP连接、握手并认证，返回阻塞模式的会话
+fn authenticate(params: &ConnectParams) -> Result<Session, SSHError> {
+    let ConnectParams { host, port, addrs, username, auth, options } = params;
+    let port = *port;
+    info!("尝试连接到 SSH 服务器: {}:{}", host, port);
+
+    // 依次尝试解析出的地址，设置超时
+    let tcp = addrs.iter().find_map(|addr| match std::net::TcpStream::connect_timeout(addr, Duration::from_secs(10)) {
+        Ok(stream) => Some(stream),
+        Err(e) => {
+            warn!("TCP连接失败到 {} - {}", addr, e);
+            None
+        }
+    });
+    let tcp = match tcp {
+        Some(stream) => {
+            info!("TCP连接成功建立到 {}:{}", host, port);
+            stream
+        }
+        None => {
+            error!("TCP连接失败到 {}:{}", host, port);
+            return Err(SSHError::NetworkTimeout { 
+                host: host.to_string(), 
+                port 
+            });
+        }
+    };
+    
+    // 设置TCP流为非阻塞模式
+    tcp.set_nonblocking(false)
+        .map_err(|e| SSHError::ConnectionFailed(format!("设置TCP流失败: {}", e)))?;
+    
+    let mut session = Session::new()
+        .map_err(|e| SSHError::ConnectionFailed(format!("创建SSH会话失败: {}", e)))?;
+    
+    session.set_tcp_stream(tcp);
+    apply_options(&session, options)?;
+    
+    info!("开始SSH握手...");
+    session.handshake()
+        .map_err(|e| {
+            error!("SSH握手失败: {}", e);
+            SSHError::HandshakeFailed
+        })?;
+    
+    info!("SSH握手成功，开始认证用户: {}", username);
+    let result = match auth {
+        SSHAuth::Password(password) => session.userauth_password(username, password),
+        SSHAuth::PrivateKey { key, passphrase } => {
+            session.userauth_pubkey_memory(username, None, key, passphrase.as_deref())
+        }
+    };
+    result
+        .map_err(|e| {
+            error!("SSH认证失败: {}", e);
+            SSHError::AuthenticationFailed
+        })?;
+    
+    if !session.authenticated() {
+        warn!("SSH认证检查失败");
+        return Err(SSHError::AuthenticationFailed);
+    }
+    
+    info!("SSH认证成功");
+    Ok(session)
+}
+
+/// 打开带PTY的交互式Shell通道，调用时会话须处于阻塞模式
+fn open_shell(session: &Session) -> Result<ssh2::Channel, SSHError> {
+    let mut channel = session.channel_session()
+        .map_err(|e| {
+            error!("创建SSH通道失败: {}", e);
+            SSHError::ChannelCreationFailed
+        })?;
+    
+    channel.request_pty("xterm", None, None)
+        .map_err(|e| {
+            error!("请求PTY失败: {}", e);
+            SSHError::ChannelCreationFailed
+        })?;
+    
+    channel.shell()
+        .map_err(|e| {
+            error!("启动Shell失败: {}", e);
+            SSHError::ChannelCreationFailed
+        })?;
+    
+    Ok(channel)
+}
+
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+const LIBSSH2_ERROR_EAGAIN: i32 = -37;
+const LIBSSH2_ERROR_CHANNEL_REQUEST_DENIED: i32 = -22;
+
+/// 算法偏好和压缩必须在握手之前设置
+fn apply_options(session: &Session, options: &SSHOptions) -> Result<(), SSHError> {
+    let SSHAlgorithms { cipher, key_exchange, mac, host_key } = &options.algorithms;
+    let prefs = [
+        (MethodType::Kex, key_exchange),
+        (MethodType::HostKey, host_key),
+        (MethodType::CryptCs, cipher),
+        (MethodType::CryptSc, cipher),
+        (MethodType::MacCs, mac),
+        (MethodType::MacSc, mac),
+    ];
+    for (method, value) in prefs {
+        if let Some(value) = value {
+            session.method_pref(method, value)
+                .map_err(|e| SSHError::InvalidAlgorithm(format!("{}: {}", value, e)))?;
+        }
+    }
+
+    session.set_compress(options.compression);
+    if options.keep_alive > 0 {
+        session.set_keepalive(true, options.keep_alive);
+    }
+    Ok(())
+}
+
+/// 检查逗号分隔的算法列表是否全部被当前 libssh2 支持
+pub fn validate_algorithms(method: MethodType, prefs: &str) -> Result<(), SSHError> {
+    let session = Session::new()
+        .map_err(|e| SSHError::ConnectionFailed(format!("创建SSH会话失败: {}", e)))?;
+    let supported = session.supported_algs(method)
+        .map_err(|e| SSHError::InvalidAlgorithm(e.to_string()))?;
+
+    let names: Vec<&str> = prefs.split(',').map(str::trim).collect();
+    if names.iter().any(|n| n.is_empty()) {
+        return Err(SSHError::InvalidAlgorithm(format!("列表格式错误: \"{}\"", prefs)));
+    }
+    if let Some(unknown) = names.iter().find(|n| !supported.contains(n)) {
+        return Err(SSHError::InvalidAlgorithm(format!(
+            "未知算法 \"{}\"，可选: {}",
+            unknown,
+            supported.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Error, ErrorKind};
+
+    #[test]
+    fn remote_exit_is_not_a_transport_failure() {
+        assert_eq!(classify_read(&Ok(0), true), ReadOutcome::RemoteClosed);
+        assert_eq!(classify_read(&Ok(0), false), ReadOutcome::Idle);
+        assert_eq!(classify_read(&Ok(12), false), ReadOutcome::Data(12));
+        assert_eq!(classify_read(&Err(Error::from(ErrorKind::WouldBlock)), false), ReadOutcome::Idle);
+        assert_eq!(classify_read(&Err(Error::from(ErrorKind::ConnectionReset)), false), ReadOutcome::TransportFailed);
+    }
+
+    #[test]
+    fn closing_the_last_channel_closes_the_connection() {
+        let mut channels = ChannelTable::new();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        channels.insert(first, "first").unwrap();
+        channels.insert(second, "second").unwrap();
+
+        assert_eq!(channels.remove(first), (Some("first"), false));
+        // 已移除的通道再次关闭不影响其它通道
+        assert_eq!(channels.remove(first), (None, false));
+        assert!(!channels.closed);
+
+        assert_eq!(channels.remove(second), (Some("second"), true));
+        assert!(channels.closed);
+        assert!(matches!(channels.insert(Uuid::new_v4(), "late"), Err(SSHError::ChannelClosed)));
+        // 断开只发生一次
+        assert_eq!(channels.remove(second), (None, false));
+
+        let mut failed = ChannelTable::new();
+        failed.insert(first, "first").unwrap();
+        failed.close_all();
+        assert!(failed.entries.is_empty() && failed.closed);
+        assert_eq!(failed.remove(first), (None, false));
     }
 }
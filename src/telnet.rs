@@ -0,0 +1,383 @@
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{info, error, debug};
+use uuid::Uuid;
+
+use crate::terminal::{SessionEvent, TerminalBackend, TerminalError};
+
+// RFC 854 命令字节
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+// 选项
+const OPT_ECHO: u8 = 1;
+const OPT_SGA: u8 = 3;
+const OPT_TTYPE: u8 = 24;
+const OPT_NAWS: u8 = 31;
+
+const TTYPE_IS: u8 = 0;
+const TTYPE_SEND: u8 = 1;
+
+const TERMINAL_TYPE: &str = "XTERM";
+
+/// 子协商内容的上限，对端一直不发送 IAC SE 时缓冲不会无限增长
+const MAX_SUBNEGOTIATION: usize = 4096;
+
+/// 从服务器数据流中分离出的内容
+#[derive(Debug, PartialEq, Eq)]
+enum TelnetEvent {
+    Data(Vec<u8>),
+    Negotiate(u8, u8),
+    Subnegotiate(u8, Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ParserState {
+    Data,
+    Iac,
+    Negotiate(u8),
+    Sub,
+    SubIac,
+}
+
+/// 逐字节解析 IAC 序列，状态跨越多次读取保留
+struct TelnetParser {
+    state: ParserState,
+    sub: Vec<u8>,
+}
+
+impl TelnetParser {
+    fn new() -> Self {
+        TelnetParser { state: ParserState::Data, sub: Vec::new() }
+    }
+
+    fn feed(&mut self, input: &[u8]) -> Vec<TelnetEvent> {
+        let mut events = Vec::new();
+        let mut data = Vec::new();
+
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (ParserState::Data, IAC) => ParserState::Iac,
+                (ParserState::Data, b) => {
+                    data.push(b);
+                    ParserState::Data
+                }
+                (ParserState::Iac, IAC) => {
+                    data.push(IAC);
+                    ParserState::Data
+                }
+                (ParserState::Iac, cmd @ (DO | DONT | WILL | WONT)) => ParserState::Negotiate(cmd),
+                (ParserState::Iac, SB) => {
+                    self.sub.clear();
+                    ParserState::Sub
+                }
+                // NOP、GA 等其它单字节命令直接忽略
+                (ParserState::Iac, _) => ParserState::Data,
+                (ParserState::Negotiate(cmd), option) => {
+                    if !data.is_empty() {
+                        events.push(TelnetEvent::Data(std::mem::take(&mut data)));
+                    }
+                    events.push(TelnetEvent::Negotiate(cmd, option));
+                    ParserState::Data
+                }
+                (ParserState::Sub, IAC) => ParserState::SubIac,
+                (ParserState::SubIac, SE) => {
+                    if let Some((&option, payload)) = self.sub.split_first() {
+                        if !data.is_empty() {
+                            events.push(TelnetEvent::Data(std::mem::take(&mut data)));
+                        }
+                        events.push(TelnetEvent::Subnegotiate(option, payload.to_vec()));
+                    }
+                    ParserState::Data
+                }
+                // 超过上限仍未结束的子协商整段丢弃，回到数据状态
+                (ParserState::Sub | ParserState::SubIac, _) if self.sub.len() >= MAX_SUBNEGOTIATION => {
+                    self.sub = Vec::new();
+                    ParserState::Data
+                }
+                (ParserState::Sub | ParserState::SubIac, b) => {
+                    self.sub.push(b);
+                    ParserState::Sub
+                }
+            };
+        }
+
+        if !data.is_empty() {
+            events.push(TelnetEvent::Data(data));
+        }
+        events
+    }
+}
+
+/// 双方已协商的选项
+struct Negotiation {
+    naws: bool,
+    /// 已主动发送过 WILL NAWS，收到的 DO 只是确认
+    naws_offered: bool,
+    cols: u16,
+    rows: u16,
+}
+
+impl Negotiation {
+    /// 对服务器的协商请求给出应答，只在状态变化时回复以避免循环
+    fn respond(&mut self, cmd: u8, option: u8) -> Vec<u8> {
+        match (cmd, option) {
+            (DO, OPT_NAWS) => {
+                let mut reply = Vec::new();
+                if !self.naws_offered {
+                    reply.extend_from_slice(&[IAC, WILL, OPT_NAWS]);
+                }
+                self.naws = true;
+                self.naws_offered = true;
+                reply.extend(naws(self.cols, self.rows));
+                reply
+            }
+            (DONT, OPT_NAWS) => {
+                self.naws = false;
+                self.naws_offered = false;
+                Vec::new()
+            }
+            // 主动发送过 WILL TTYPE，这里不再重复
+            (DO, OPT_TTYPE) => Vec::new(),
+            // 由服务器回显、抑制GA，均为全双工终端所需
+            (WILL, OPT_ECHO) | (WILL, OPT_SGA) => Vec::new(),
+            (DO, OPT_SGA) => vec![IAC, WILL, OPT_SGA],
+            (DO, _) => vec![IAC, WONT, option],
+            (WILL, _) => vec![IAC, DONT, option],
+            _ => Vec::new(),
+        }
+    }
+
+    fn subnegotiate(&self, option: u8, payload: &[u8]) -> Vec<u8> {
+        if option == OPT_TTYPE && payload.first() == Some(&TTYPE_SEND) {
+            let mut reply = vec![IAC, SB, OPT_TTYPE, TTYPE_IS];
+            reply.extend_from_slice(TERMINAL_TYPE.as_bytes());
+            reply.extend_from_slice(&[IAC, SE]);
+            reply
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// NAWS 子协商，宽高中的 255 需要转义
+fn naws(cols: u16, rows: u16) -> Vec<u8> {
+    let mut out = vec![IAC, SB, OPT_NAWS];
+    for byte in cols.to_be_bytes().into_iter().chain(rows.to_be_bytes()) {
+        out.push(byte);
+        if byte == IAC {
+            out.push(IAC);
+        }
+    }
+    out.extend_from_slice(&[IAC, SE]);
+    out
+}
+
+/// 转义用户输入：IAC 加倍，单独的 CR 按 NVT 规则补 NUL
+fn escape_input(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (i, &byte) in data.iter().enumerate() {
+        out.push(byte);
+        match byte {
+            IAC => out.push(IAC),
+            b'\r' if data.get(i + 1) != Some(&b'\n') => out.push(0),
+            _ => {}
+        }
+    }
+    out
+}
+
+/// 面向只支持telnet的交换机、终端服务器等老旧设备
+pub struct TelnetSession {
+    id: Uuid,
+    writer: Mutex<OwnedWriteHalf>,
+    negotiation: Mutex<Negotiation>,
+}
+
+impl TelnetSession {
+    /// 只连接通过 destinations 检查的地址
+    pub async fn connect(
+        host: &str,
+        port: u16,
+        addrs: &[SocketAddr],
+        cols: u16,
+        rows: u16,
+    ) -> Result<(std::sync::Arc<Self>, mpsc::Receiver<SessionEvent>), TerminalError> {
+        info!("尝试连接到 Telnet 服务器: {}:{}", host, port);
+        let stream = tokio::time::timeout(Duration::from_secs(10), TcpStream::connect(addrs))
+            .await
+            .map_err(|_| TerminalError::IoError(std::io::ErrorKind::TimedOut.into()))??;
+        let _ = stream.set_nodelay(true);
+        let (mut reader, writer) = stream.into_split();
+
+        let session = std::sync::Arc::new(TelnetSession {
+            id: Uuid::new_v4(),
+            writer: Mutex::new(writer),
+            negotiation: Mutex::new(Negotiation { naws: false, naws_offered: true, cols, rows }),
+        });
+
+        // 主动声明本端能力，大多数设备会随后发送 DO NAWS / DO TTYPE
+        session.send_raw(&[
+            IAC, WILL, OPT_NAWS,
+            IAC, WILL, OPT_TTYPE,
+            IAC, DO, OPT_SGA,
+            IAC, DO, OPT_ECHO,
+        ]).await?;
+
+        let (tx, rx) = mpsc::channel(100);
+        let reader_session = session.clone();
+        tokio::spawn(async move {
+            let mut parser = TelnetParser::new();
+            let mut buffer = [0u8; 4096];
+            loop {
+                let n = match reader.read(&mut buffer).await {
+                    Ok(0) => {
+                        info!("Telnet连接已关闭");
+                        break;
+                    }
+                    Ok(n) => n,
+                    Err(e) => {
+                        error!("Telnet读取错误: {}", e);
+                        break;
+                    }
+                };
+
+                for event in parser.feed(&buffer[..n]) {
+                    let reply = match event {
+                        TelnetEvent::Data(data) => {
+                            let data = String::from_utf8_lossy(&data).to_string();
+                            if tx.send(SessionEvent::Data(data)).await.is_err() {
+                                return;
+                            }
+                            continue;
+                        }
+                        TelnetEvent::Negotiate(cmd, option) => {
+                            debug!("Telnet协商: {} {}", cmd, option);
+                            reader_session.negotiation.lock().await.respond(cmd, option)
+                        }
+                        TelnetEvent::Subnegotiate(option, payload) => {
+                            reader_session.negotiation.lock().await.subnegotiate(option, &payload)
+                        }
+                    };
+                    if !reply.is_empty() && reader_session.send_raw(&reply).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            let _ = tx.send(SessionEvent::Closed).await;
+        });
+
+        info!("Telnet会话创建完成");
+        Ok((session, rx))
+    }
+
+    async fn send_raw(&self, bytes: &[u8]) -> std::io::Result<()> {
+        let mut writer = self.writer.lock().await;
+        writer.write_all(bytes).await?;
+        writer.flush().await
+    }
+}
+
+#[async_trait]
+impl TerminalBackend for TelnetSession {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        "telnet"
+    }
+
+    async fn write(&self, data: &str) -> Result<(), TerminalError> {
+        self.send_raw(&escape_input(data.as_bytes())).await?;
+        Ok(())
+    }
+
+    async fn resize(&self, cols: u16, rows: u16) -> Result<(), TerminalError> {
+        let enabled = {
+            let mut negotiation = self.negotiation.lock().await;
+            negotiation.cols = cols;
+            negotiation.rows = rows;
+            negotiation.naws
+        };
+        if enabled {
+            self.send_raw(&naws(cols, rows)).await?;
+        }
+        Ok(())
+    }
+
+    async fn close(&self) {
+        let _ = self.writer.lock().await.shutdown().await;
+        info!("Telnet会话 {} 已关闭", self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parser_separates_commands_from_data() {
+        let mut parser = TelnetParser::new();
+        let events = parser.feed(&[b'a', IAC, DO, OPT_NAWS, b'b', IAC, IAC, b'c']);
+        assert_eq!(events, vec![
+            TelnetEvent::Data(b"a".to_vec()),
+            TelnetEvent::Negotiate(DO, OPT_NAWS),
+            TelnetEvent::Data(vec![b'b', IAC, b'c']),
+        ]);
+    }
+
+    #[test]
+    fn parser_keeps_state_across_reads() {
+        let mut parser = TelnetParser::new();
+        assert!(parser.feed(&[IAC, SB, OPT_TTYPE]).is_empty());
+        let events = parser.feed(&[TTYPE_SEND, IAC, SE, b'x']);
+        assert_eq!(events, vec![
+            TelnetEvent::Subnegotiate(OPT_TTYPE, vec![TTYPE_SEND]),
+            TelnetEvent::Data(b"x".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn oversized_subnegotiation_is_dropped() {
+        let mut parser = TelnetParser::new();
+        let mut input = vec![IAC, SB, OPT_TTYPE];
+        input.resize(MAX_SUBNEGOTIATION + 3, b'x');
+        assert!(parser.feed(&input).is_empty());
+        let events = parser.feed(b"ok");
+        assert_eq!(events, vec![TelnetEvent::Data(b"ok".to_vec())]);
+        assert_eq!(parser.sub.capacity(), 0);
+        // 之后的子协商照常解析
+        let events = parser.feed(&[IAC, SB, OPT_TTYPE, TTYPE_SEND, IAC, SE]);
+        assert_eq!(events, vec![TelnetEvent::Subnegotiate(OPT_TTYPE, vec![TTYPE_SEND])]);
+    }
+
+    #[test]
+    fn negotiation_answers_once() {
+        let mut negotiation = Negotiation { naws: false, naws_offered: false, cols: 80, rows: 24 };
+        let first = negotiation.respond(DO, OPT_NAWS);
+        assert_eq!(&first[..3], &[IAC, WILL, OPT_NAWS]);
+        assert_eq!(&first[3..], &naws(80, 24)[..]);
+        assert!(negotiation.naws);
+        assert_eq!(negotiation.respond(DO, OPT_NAWS), naws(80, 24));
+        assert_eq!(negotiation.respond(DO, 39), vec![IAC, WONT, 39]);
+        assert!(negotiation.respond(WILL, OPT_ECHO).is_empty());
+    }
+
+    #[test]
+    fn input_escaping() {
+        assert_eq!(escape_input(b"ls\r"), b"ls\r\0".to_vec());
+        assert_eq!(escape_input(b"ls\r\n"), b"ls\r\n".to_vec());
+        assert_eq!(escape_input(&[IAC]), vec![IAC, IAC]);
+    }
+}
@@ -0,0 +1,106 @@
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{Connections, config::Config, pty::PtySession, ssh::{SSHAuth, SSHError, SSHSession}, telnet::TelnetSession};
+
+#[derive(Error, Debug)]
+pub enum TerminalError {
+    #[error(transparent)]
+    Ssh(#[from] SSHError),
+    #[error("本地终端错误: {0}")]
+    Pty(String),
+    #[error("不支持的终端类型: {0}")]
+    Unsupported(String),
+    #[error("IO错误: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+/// 后端推送给上层的会话事件
+#[derive(Debug)]
+pub enum SessionEvent {
+    Data(String),
+    Status { connected: bool, latency_ms: Option<u64> },
+    Closed,
+}
+
+/// 打开后的终端及其输出流
+pub type OpenedTerminal = (Arc<dyn TerminalBackend>, mpsc::Receiver<SessionEvent>);
+
+/// 终端会话后端，SSH、本地PTY等都通过它接入WebSocket和AI层
+#[async_trait]
+pub trait TerminalBackend: Send + Sync {
+    /// 对外的 session_id
+    fn id(&self) -> Uuid;
+
+    fn kind(&self) -> &'static str;
+
+    /// 可复用的底层连接，只有支持多通道的后端才有
+    fn connection_id(&self) -> Option<Uuid> {
+        None
+    }
+
+    async fn write(&self, data: &str) -> Result<(), TerminalError>;
+
+    async fn resize(&self, cols: u16, rows: u16) -> Result<(), TerminalError>;
+
+    async fn close(&self);
+}
+
+/// Connect 消息描述的打开目标
+pub enum TerminalTarget {
+    Ssh {
+        host: String,
+        port: u16,
+        /// 通过 destinations 检查的地址
+        addrs: Vec<SocketAddr>,
+        username: String,
+        auth: SSHAuth,
+        auto_reconnect: Option<bool>,
+    },
+    Telnet {
+        host: String,
+        port: u16,
+        addrs: Vec<SocketAddr>,
+    },
+    Local,
+}
+
+pub async fn open(
+    target: TerminalTarget,
+    cols: u16,
+    rows: u16,
+    config: &Config,
+    connections: &Connections,
+) -> Result<OpenedTerminal, TerminalError> {
+    match target {
+        TerminalTarget::Ssh { host, port, addrs, username, auth, auto_reconnect } => {
+            let mut options = config.ssh.options_for(&host);
+            if let Some(auto_reconnect) = auto_reconnect {
+                options.auto_reconnect = auto_reconnect;
+            }
+            let (session, rx) = SSHSession::new(&host, port, addrs, &username, auth, &options, connections).await?;
+            if (cols, rows) != (DEFAULT_COLS, DEFAULT_ROWS) {
+                session.resize(cols, rows).await?;
+            }
+            Ok((Arc::new(session), rx))
+        }
+        TerminalTarget::Telnet { host, port, addrs } => {
+            let (session, rx) = TelnetSession::connect(&host, port, &addrs, cols, rows).await?;
+            Ok((session, rx))
+        }
+        TerminalTarget::Local => {
+            if !config.local_shell.enabled {
+                return Err(TerminalError::Unsupported("local (未在 local_shell.enabled 中启用)".to_string()));
+            }
+            let (session, rx) = PtySession::spawn(&config.local_shell, cols, rows)?;
+            Ok((Arc::new(session), rx))
+        }
+    }
+}
+
+pub const DEFAULT_COLS: u16 = 80;
+pub const DEFAULT_ROWS: u16 = 24;
@@ -0,0 +1,330 @@
+use anyhow::Context as _;
+use futures_util::TryFutureExt;
+use metrics::counter;
+use openssl::ssl::{ErrorCode, ShutdownResult, Ssl, SslAcceptor, SslFiletype, SslMethod, SslStream, SslVerifyMode};
+use openssl::x509::X509Name;
+use parking_lot::{Mutex, RwLock};
+use std::convert::Infallible;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpListener;
+use tracing::{debug, error, info, warn};
+use warp::filters::path::FullPath;
+use warp::http::{header, HeaderValue, StatusCode};
+use warp::hyper::server::conn::Http;
+use warp::hyper::service::{service_fn, Service};
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::TlsConfig;
+
+/// 握手超过该时间未完成则断开
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 由 [`serve`] 放入请求扩展的客户端地址
+#[derive(Debug, Clone, Copy)]
+struct PeerAddr(SocketAddr);
+
+/// 客户端地址，HTTPS 连接由 [`serve`] 提供，HTTP 连接由 warp 提供
+pub fn remote() -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone {
+    warp::ext::optional::<PeerAddr>()
+        .and(warp::addr::remote())
+        .map(|peer: Option<PeerAddr>, remote: Option<SocketAddr>| peer.map(|p| p.0).or(remote))
+}
+
+/// 当前使用的证书，文件变化后在下一次检查时替换，已建立的连接不受影响
+pub struct TlsAcceptor {
+    config: TlsConfig,
+    current: RwLock<Arc<SslAcceptor>>,
+    modified: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl TlsAcceptor {
+    pub fn new(config: &TlsConfig) -> anyhow::Result<Arc<Self>> {
+        let acceptor = TlsAcceptor {
+            current: RwLock::new(Arc::new(build(config)?)),
+            modified: Mutex::new(Vec::new()),
+            config: config.clone(),
+        };
+        *acceptor.modified.lock() = acceptor.modified_times();
+        Ok(Arc::new(acceptor))
+    }
+
+    fn modified_times(&self) -> Vec<Option<SystemTime>> {
+        [Some(&self.config.cert_path), Some(&self.config.key_path), self.config.client_ca_path.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    /// 证书、私钥或 CA 文件的修改时间变化时重新加载，加载失败则继续使用旧证书
+    pub fn reload_if_changed(&self) -> bool {
+        let modified = self.modified_times();
+        {
+            let mut last = self.modified.lock();
+            if *last == modified {
+                return false;
+            }
+            *last = modified;
+        }
+        match build(&self.config) {
+            Ok(acceptor) => {
+                *self.current.write() = Arc::new(acceptor);
+                counter!("tls_reloads_total", 1);
+                info!("Reloaded TLS certificate from {}", self.config.cert_path);
+                true
+            }
+            Err(e) => {
+                // 证书和私钥可能还没有全部写完，下次文件变化时再试
+                error!("Failed to reload TLS certificate, keeping the previous one: {:#}", e);
+                false
+            }
+        }
+    }
+
+    /// 按 `reload_interval` 定期检查证书文件
+    pub fn spawn_reloader(self: &Arc<Self>) {
+        if self.config.reload_interval == 0 {
+            return;
+        }
+        let acceptor = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(acceptor.config.reload_interval));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                acceptor.reload_if_changed();
+            }
+        });
+    }
+
+    /// 完成 TLS 握手
+    async fn accept<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: S) -> io::Result<TlsStream<S>> {
+        let acceptor = self.current.read().clone();
+        let ssl = Ssl::new(acceptor.context()).map_err(io::Error::other)?;
+        let mut stream = TlsStream {
+            inner: SslStream::new(ssl, StreamWrapper { stream, waker: None }).map_err(io::Error::other)?,
+        };
+        std::future::poll_fn(|cx| {
+            stream.with_context(cx, |s| match s.accept() {
+                Ok(()) => Poll::Ready(Ok(())),
+                Err(e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::WANT_WRITE => Poll::Pending,
+                Err(e) => Poll::Ready(Err(io::Error::other(e))),
+            })
+        })
+        .await?;
+        Ok(stream)
+    }
+}
+
+fn build(config: &TlsConfig) -> anyhow::Result<SslAcceptor> {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+    builder
+        .set_certificate_chain_file(&config.cert_path)
+        .with_context(|| format!("无法读取证书 {}", config.cert_path))?;
+    builder
+        .set_private_key_file(&config.key_path, SslFiletype::PEM)
+        .with_context(|| format!("无法读取私钥 {}", config.key_path))?;
+    builder.check_private_key().context("私钥与证书不匹配")?;
+    if let Some(ca) = &config.client_ca_path {
+        builder.set_ca_file(ca).with_context(|| format!("无法读取客户端 CA {}", ca))?;
+        builder.set_client_ca_list(X509Name::load_client_ca_file(ca)?);
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+    Ok(builder.build())
+}
+
+/// 以 HTTPS 提供 filter，每个连接的客户端地址通过 [`remote`] 取得
+///
+/// 包括 warp 默认处理的拒绝在内，所有响应都带上 HSTS。
+pub async fn serve<F, R>(filter: F, addr: SocketAddr, acceptor: Arc<TlsAcceptor>) -> io::Result<()>
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let hsts = hsts_header(&acceptor.config).map(|value| HeaderValue::from_str(&value).expect("valid HSTS header"));
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // 文件描述符耗尽等情况，稍后再接受新连接
+                warn!("Failed to accept connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let service = warp::service(filter.clone());
+        let hsts = hsts.clone();
+        tokio::spawn(async move {
+            let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    counter!("tls_handshake_failures_total", 1);
+                    debug!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+                Err(_) => {
+                    counter!("tls_handshake_failures_total", 1);
+                    debug!("TLS handshake with {} timed out", peer);
+                    return;
+                }
+            };
+            let service = service_fn(move |mut request| {
+                request.extensions_mut().insert(PeerAddr(peer));
+                let hsts = hsts.clone();
+                service.clone().call(request).map_ok(move |mut response| {
+                    if let Some(hsts) = hsts {
+                        response.headers_mut().insert(header::STRICT_TRANSPORT_SECURITY, hsts);
+                    }
+                    response
+                })
+            });
+            if let Err(e) = Http::new().serve_connection(stream, service).with_upgrades().await {
+                debug!("HTTPS connection from {} ended with error: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// 把 HTTP 请求跳转到同一路径的 HTTPS 地址
+pub fn redirect(https_port: u16, fallback_host: String) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("host")
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(move |host: Option<String>, path: FullPath, query: String| {
+            let location = https_location(host.as_deref().unwrap_or(&fallback_host), https_port, path.as_str(), &query);
+            warp::reply::with_header(
+                warp::reply::with_status(warp::reply(), StatusCode::PERMANENT_REDIRECT),
+                header::LOCATION,
+                location,
+            )
+        })
+}
+
+fn https_location(host: &str, port: u16, path: &str, query: &str) -> String {
+    // 去掉 Host 中的端口，IPv6 地址保留方括号
+    let host = match host.strip_prefix('[') {
+        Some(rest) => rest.split_once(']').map_or(host, |(ip, _)| &host[..ip.len() + 2]),
+        None => host.split_once(':').map_or(host, |(name, _)| name),
+    };
+    let port = if port == 443 { String::new() } else { format!(":{}", port) };
+    let query = if query.is_empty() { String::new() } else { format!("?{}", query) };
+    format!("https://{}{}{}{}", host, port, path, query)
+}
+
+/// `Strict-Transport-Security` 的取值，max-age 为 0 时不发送
+fn hsts_header(config: &TlsConfig) -> Option<String> {
+    (config.hsts_max_age > 0).then(|| {
+        let subdomains = if config.hsts_include_subdomains { "; includeSubDomains" } else { "" };
+        format!("max-age={}{}", config.hsts_max_age, subdomains)
+    })
+}
+
+/// 让同步的 openssl 读写底层异步流，没有数据时返回 WouldBlock
+struct StreamWrapper<S> {
+    stream: S,
+    waker: Option<Waker>,
+}
+
+impl<S> StreamWrapper<S> {
+    fn poll<T>(&mut self, f: impl FnOnce(Pin<&mut S>, &mut Context<'_>) -> Poll<io::Result<T>>) -> io::Result<T>
+    where
+        S: Unpin,
+    {
+        let waker = self.waker.clone().expect("TLS stream polled outside of a task");
+        match f(Pin::new(&mut self.stream), &mut Context::from_waker(&waker)) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> Read for StreamWrapper<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.poll(|stream, cx| {
+            let mut buf = ReadBuf::new(buf);
+            stream.poll_read(cx, &mut buf).map_ok(|()| buf.filled().len())
+        })
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Write for StreamWrapper<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.poll(|stream, cx| stream.poll_write(cx, buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.poll(|stream, cx| stream.poll_flush(cx))
+    }
+}
+
+struct TlsStream<S> {
+    inner: SslStream<StreamWrapper<S>>,
+}
+
+impl<S> TlsStream<S> {
+    fn with_context<T>(&mut self, cx: &mut Context<'_>, f: impl FnOnce(&mut SslStream<StreamWrapper<S>>) -> T) -> T {
+        self.inner.get_mut().waker = Some(cx.waker().clone());
+        let result = f(&mut self.inner);
+        self.inner.get_mut().waker = None;
+        result
+    }
+}
+
+fn would_block<T>(result: io::Result<T>) -> Poll<io::Result<T>> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+        result => Poll::Ready(result),
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().with_context(cx, |s| {
+            would_block(s.read(buf.initialize_unfilled())).map_ok(|n| buf.advance(n))
+        })
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.get_mut().with_context(cx, |s| would_block(s.write(buf)))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().with_context(cx, |s| would_block(s.flush()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let sent = this.with_context(cx, |s| match s.shutdown() {
+            Ok(ShutdownResult::Sent | ShutdownResult::Received) => Poll::Ready(Ok(())),
+            Err(e) if e.code() == ErrorCode::ZERO_RETURN => Poll::Ready(Ok(())),
+            Err(e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::WANT_WRITE => Poll::Pending,
+            Err(e) => Poll::Ready(Err(e.into_io_error().unwrap_or_else(io::Error::other))),
+        });
+        match sent {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner.get_mut().stream).poll_shutdown(cx),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirect_location_keeps_path_and_drops_http_port() {
+        assert_eq!(https_location("example.com:8080", 443, "/login.html", ""), "https://example.com/login.html");
+        assert_eq!(https_location("example.com", 8443, "/api/sessions", "a=1&b=2"), "https://example.com:8443/api/sessions?a=1&b=2");
+        assert_eq!(https_location("[::1]:80", 8443, "/", ""), "https://[::1]:8443/");
+    }
+}
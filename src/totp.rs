@@ -0,0 +1,458 @@
+use data_encoding::{BASE32_NOPAD, HEXLOWER};
+use hmac::{Hmac, Mac};
+use parking_lot::Mutex;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+use tracing::error;
+use warp::http::StatusCode;
+use warp::Reply;
+
+use crate::AppState;
+use crate::audit::{self, AuditEvent};
+use crate::auth::Identity;
+use crate::config::TotpConfig;
+
+/// 时间步长 (秒) 和验证码位数，与常见验证器应用的默认值一致
+const STEP: u64 = 30;
+const DIGITS: usize = 6;
+const RECOVERY_CODES: usize = 10;
+
+#[derive(Error, Debug)]
+pub enum TotpError {
+    #[error("验证码错误")]
+    InvalidCode,
+    #[error("尚未启用两步验证")]
+    NotEnrolled,
+    #[error("已经启用两步验证")]
+    AlreadyEnabled,
+    #[error("当前角色必须启用两步验证")]
+    Required,
+    #[error("单点登录账户请在身份提供方配置两步验证")]
+    ExternalAccount,
+    #[error("保存两步验证数据失败: {0}")]
+    Store(#[from] std::io::Error),
+}
+
+/// 新生成的密钥，客户端据此显示二维码
+#[derive(Debug, Clone, Serialize)]
+pub struct Enrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    /// Base32 编码的密钥
+    secret: String,
+    /// 绑定时输入过正确的验证码后才启用
+    enabled: bool,
+    /// 恢复码的 SHA-256，使用后删除
+    recovery_codes: Vec<String>,
+    /// 最近一次通过验证的时间步，同一验证码不能重复使用
+    last_step: u64,
+}
+
+/// 各账户的 TOTP 密钥和恢复码，启用后写入 `auth.totp.store`
+///
+/// 绑定中 (尚未启用) 的密钥只保存在内存中，重启后需要重新绑定。
+pub struct TotpStore {
+    path: PathBuf,
+    issuer: String,
+    skew: u64,
+    records: Mutex<HashMap<String, Record>>,
+}
+
+impl TotpStore {
+    pub fn open(config: &TotpConfig) -> anyhow::Result<Self> {
+        let path = PathBuf::from(&config.store);
+        let records: HashMap<String, Record> = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("无法解析两步验证数据 {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(anyhow::anyhow!("无法读取两步验证数据 {}: {}", path.display(), e)),
+        };
+        Ok(TotpStore {
+            path,
+            issuer: config.issuer.clone(),
+            skew: config.skew,
+            records: Mutex::new(records.into_iter().filter(|(_, r)| r.enabled).collect()),
+        })
+    }
+
+    pub fn enabled(&self, user: &str) -> bool {
+        self.records.lock().get(user).is_some_and(|r| r.enabled)
+    }
+
+    /// 剩余可用的恢复码数量，未启用时为 None
+    pub fn recovery_codes_remaining(&self, user: &str) -> Option<usize> {
+        self.records.lock().get(user).filter(|r| r.enabled).map(|r| r.recovery_codes.len())
+    }
+
+    /// 生成新的密钥，替换之前未完成的绑定
+    pub fn begin(&self, user: &str) -> Result<Enrollment, TotpError> {
+        let mut records = self.records.lock();
+        if records.get(user).is_some_and(|r| r.enabled) {
+            return Err(TotpError::AlreadyEnabled);
+        }
+        let secret = generate_secret();
+        records.insert(user.to_string(), Record {
+            secret: secret.clone(),
+            enabled: false,
+            recovery_codes: Vec::new(),
+            last_step: 0,
+        });
+        Ok(Enrollment {
+            otpauth_uri: otpauth_uri(&self.issuer, user, &secret),
+            secret,
+        })
+    }
+
+    /// 用第一个验证码确认绑定，返回恢复码明文 (只显示这一次)
+    pub fn activate(&self, user: &str, code: &str) -> Result<Vec<String>, TotpError> {
+        let mut records = self.records.lock();
+        let record = records.get_mut(user).ok_or(TotpError::NotEnrolled)?;
+        if record.enabled {
+            return Err(TotpError::AlreadyEnabled);
+        }
+        record.last_step = self.check_code(record, code)?;
+        let codes = generate_recovery_codes();
+        record.recovery_codes = codes.iter().map(|c| hash_recovery_code(c)).collect();
+        record.enabled = true;
+        if let Err(e) = self.save(&records) {
+            records.remove(user);
+            return Err(e.into());
+        }
+        audit::record(Some(user), None, account_event("totp_enabled", None));
+        Ok(codes)
+    }
+
+    /// 校验验证码或恢复码，恢复码使用后失效
+    pub fn verify(&self, user: &str, code: &str) -> Result<(), TotpError> {
+        let mut records = self.records.lock();
+        let record = records.get_mut(user).filter(|r| r.enabled).ok_or(TotpError::NotEnrolled)?;
+        let code = code.trim();
+        if is_totp_code(code) {
+            record.last_step = self.check_code(record, code)?;
+        } else {
+            let hash = hash_recovery_code(code);
+            let index = record
+                .recovery_codes
+                .iter()
+                .position(|h| bool::from(h.as_bytes().ct_eq(hash.as_bytes())))
+                .ok_or(TotpError::InvalidCode)?;
+            record.recovery_codes.remove(index);
+            let remaining = format!("{} remaining", record.recovery_codes.len());
+            audit::record(Some(user), None, account_event("recovery_code_used", Some(remaining)));
+        }
+        self.save(&records)?;
+        Ok(())
+    }
+
+    /// 重新生成恢复码，之前的全部作废
+    pub fn regenerate_recovery_codes(&self, user: &str, code: &str) -> Result<Vec<String>, TotpError> {
+        self.verify(user, code)?;
+        let mut records = self.records.lock();
+        let record = records.get_mut(user).ok_or(TotpError::NotEnrolled)?;
+        let codes = generate_recovery_codes();
+        record.recovery_codes = codes.iter().map(|c| hash_recovery_code(c)).collect();
+        self.save(&records)?;
+        audit::record(Some(user), None, account_event("recovery_codes_regenerated", None));
+        Ok(codes)
+    }
+
+    pub fn disable(&self, user: &str, code: &str) -> Result<(), TotpError> {
+        self.verify(user, code)?;
+        let mut records = self.records.lock();
+        records.remove(user);
+        self.save(&records)?;
+        audit::record(Some(user), None, account_event("totp_disabled", None));
+        Ok(())
+    }
+
+    fn check_code(&self, record: &Record, code: &str) -> Result<u64, TotpError> {
+        let key = BASE32_NOPAD.decode(record.secret.as_bytes()).map_err(|_| TotpError::InvalidCode)?;
+        match_step(&key, code.trim(), unix_now(), self.skew, record.last_step).ok_or(TotpError::InvalidCode)
+    }
+
+    /// 只写入已启用的记录，先写临时文件再替换
+    fn save(&self, records: &HashMap<String, Record>) -> std::io::Result<()> {
+        let enabled: HashMap<_, _> = records.iter().filter(|(_, r)| r.enabled).collect();
+        let content = serde_json::to_vec_pretty(&enabled)?;
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        write_private(&tmp, &content)?;
+        std::fs::rename(&tmp, &self.path)
+    }
+}
+
+fn account_event(action: &str, detail: Option<String>) -> AuditEvent {
+    AuditEvent::Account { action: action.to_string(), detail }
+}
+
+/// 文件中有密钥，只允许属主读写
+fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(content)
+}
+
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE32_NOPAD.encode(&bytes)
+}
+
+/// 验证器应用识别的 URI，客户端把它渲染成二维码
+pub fn otpauth_uri(issuer: &str, user: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode(issuer),
+        encode(user),
+        secret,
+        encode(issuer),
+        DIGITS,
+        STEP
+    )
+}
+
+fn encode(value: &str) -> String {
+    // form 编码把空格写成 `+`，路径中需要 `%20`；原本的 `+` 已被编码为 `%2B`
+    url::form_urlencoded::byte_serialize(value.as_bytes())
+        .collect::<String>()
+        .replace('+', "%20")
+}
+
+/// RFC 4226 HOTP，截取为 6 位数字
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    value % 10u32.pow(DIGITS as u32)
+}
+
+/// 在前后 skew 个时间步内查找匹配的验证码，只接受比 last_step 新的时间步
+fn match_step(key: &[u8], code: &str, now: u64, skew: u64, last_step: u64) -> Option<u64> {
+    if !is_totp_code(code) {
+        return None;
+    }
+    let current = now / STEP;
+    (current.saturating_sub(skew)..=current + skew)
+        .filter(|step| *step > last_step)
+        .find(|step| {
+            let expected = format!("{:0width$}", hotp(key, *step), width = DIGITS);
+            expected.as_bytes().ct_eq(code.as_bytes()).into()
+        })
+}
+
+/// 测试中模拟验证器应用
+#[cfg(test)]
+pub(crate) fn current_code(secret: &str) -> String {
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).unwrap();
+    format!("{:0width$}", hotp(&key, unix_now() / STEP), width = DIGITS)
+}
+
+fn is_totp_code(code: &str) -> bool {
+    code.len() == DIGITS && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let mut bytes = [0u8; 5];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            let code = BASE32_NOPAD.encode(&bytes).to_lowercase();
+            format!("{}-{}", &code[..4], &code[4..])
+        })
+        .collect()
+}
+
+/// 恢复码忽略大小写和分隔符
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    HEXLOWER.encode(&Sha256::digest(normalized.as_bytes()))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CodeRequest {
+    code: String,
+}
+
+/// 已登录账户自行管理两步验证；未启用登录或单点登录账户不适用
+fn local_account(identity: Option<Identity>, state: &AppState) -> Result<Identity, TotpError> {
+    let identity = identity.ok_or(TotpError::NotEnrolled)?;
+    if !state.auth.has_account(&identity.user) {
+        return Err(TotpError::ExternalAccount);
+    }
+    Ok(identity)
+}
+
+pub async fn handle_status(identity: Option<Identity>, state: AppState) -> Result<warp::reply::Response, warp::Rejection> {
+    let identity = match local_account(identity, &state) {
+        Ok(identity) => identity,
+        Err(e) => return Ok(error_reply(&e)),
+    };
+    let remaining = state.auth.totp().recovery_codes_remaining(&identity.user);
+    Ok(warp::reply::json(&serde_json::json!({
+        "enabled": remaining.is_some(),
+        "required": state.config.auth.totp.required_for(identity.role),
+        "recovery_codes_remaining": remaining,
+    }))
+    .into_response())
+}
+
+pub async fn handle_enroll(identity: Option<Identity>, state: AppState) -> Result<warp::reply::Response, warp::Rejection> {
+    let identity = match local_account(identity, &state) {
+        Ok(identity) => identity,
+        Err(e) => return Ok(error_reply(&e)),
+    };
+    Ok(match state.auth.totp().begin(&identity.user) {
+        Ok(enrollment) => warp::reply::json(&enrollment).into_response(),
+        Err(e) => error_reply(&e),
+    })
+}
+
+pub async fn handle_activate(
+    identity: Option<Identity>,
+    request: CodeRequest,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let identity = match local_account(identity, &state) {
+        Ok(identity) => identity,
+        Err(e) => return Ok(error_reply(&e)),
+    };
+    Ok(match state.auth.totp().activate(&identity.user, &request.code) {
+        Ok(codes) => warp::reply::json(&serde_json::json!({ "recovery_codes": codes })).into_response(),
+        Err(e) => error_reply(&e),
+    })
+}
+
+pub async fn handle_recovery_codes(
+    identity: Option<Identity>,
+    request: CodeRequest,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let identity = match local_account(identity, &state) {
+        Ok(identity) => identity,
+        Err(e) => return Ok(error_reply(&e)),
+    };
+    Ok(match state.auth.totp().regenerate_recovery_codes(&identity.user, &request.code) {
+        Ok(codes) => warp::reply::json(&serde_json::json!({ "recovery_codes": codes })).into_response(),
+        Err(e) => error_reply(&e),
+    })
+}
+
+pub async fn handle_disable(
+    identity: Option<Identity>,
+    request: CodeRequest,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let identity = match local_account(identity, &state) {
+        Ok(identity) => identity,
+        Err(e) => return Ok(error_reply(&e)),
+    };
+    if state.config.auth.totp.required_for(identity.role) {
+        return Ok(error_reply(&TotpError::Required));
+    }
+    Ok(match state.auth.totp().disable(&identity.user, &request.code) {
+        Ok(()) => warp::reply::json(&serde_json::json!({ "ok": true })).into_response(),
+        Err(e) => error_reply(&e),
+    })
+}
+
+fn error_reply(error: &TotpError) -> warp::reply::Response {
+    let status = match error {
+        TotpError::InvalidCode => StatusCode::BAD_REQUEST,
+        TotpError::NotEnrolled => StatusCode::NOT_FOUND,
+        TotpError::AlreadyEnabled => StatusCode::CONFLICT,
+        TotpError::Required | TotpError::ExternalAccount => StatusCode::FORBIDDEN,
+        TotpError::Store(e) => {
+            error!("保存两步验证数据失败: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    let body = warp::reply::json(&serde_json::json!({ "error": error.to_string() }));
+    warp::reply::with_status(body, status).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_KEY: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn rfc6238_vectors() {
+        // RFC 6238 附录 B 的 SHA1 向量，取后 6 位
+        for (time, expected) in [(59, 287082), (1111111109, 81804), (1234567890, 5924), (2000000000, 279037)] {
+            assert_eq!(hotp(RFC_KEY, time / STEP), expected);
+        }
+    }
+
+    #[test]
+    fn skew_and_replay() {
+        let now = 1111111109;
+        let previous = format!("{:06}", hotp(RFC_KEY, now / STEP - 1));
+        let step = match_step(RFC_KEY, &previous, now, 1, 0).unwrap();
+        assert_eq!(step, now / STEP - 1);
+        // 已使用的时间步不能再次通过，超出偏差的也不行
+        assert_eq!(match_step(RFC_KEY, &previous, now, 1, step), None);
+        assert_eq!(match_step(RFC_KEY, &previous, now, 0, 0), None);
+        assert_eq!(match_step(RFC_KEY, "12345", now, 1, 0), None);
+    }
+
+    #[test]
+    fn enrollment_and_recovery_codes() {
+        let path = std::env::temp_dir().join(format!("totp-{}.json", uuid::Uuid::new_v4()));
+        let store = TotpStore::open(&TotpConfig {
+            store: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let enrollment = store.begin("alice").unwrap();
+        assert!(enrollment.otpauth_uri.starts_with("otpauth://totp/SSH%20AI%20Terminal:alice?secret="));
+        assert!(!store.enabled("alice"));
+        let key = BASE32_NOPAD.decode(enrollment.secret.as_bytes()).unwrap();
+        let code = format!("{:06}", hotp(&key, unix_now() / STEP));
+        let codes = store.activate("alice", &code).unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+
+        // 恢复码只能用一次，重启后仍然有效
+        let reopened = TotpStore::open(&TotpConfig {
+            store: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(reopened.enabled("alice"));
+        reopened.verify("alice", &codes[0].to_uppercase()).unwrap();
+        assert!(matches!(reopened.verify("alice", &codes[0]), Err(TotpError::InvalidCode)));
+        assert_eq!(reopened.recovery_codes_remaining("alice"), Some(RECOVERY_CODES - 1));
+        assert!(matches!(reopened.begin("alice"), Err(TotpError::AlreadyEnabled)));
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,480 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use parking_lot::Mutex;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use thiserror::Error;
+use tracing::{error, info};
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::Reply;
+
+use crate::AppState;
+use crate::audit::{self, AuditEvent};
+use crate::auth::Identity;
+use crate::config::{UserRole, VaultConfig};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialKind {
+    /// SSH 密码
+    Password,
+    /// PEM 或 OpenSSH 格式的 SSH 私钥
+    PrivateKey,
+    AiApiKey,
+}
+
+#[derive(Error, Debug)]
+pub enum VaultError {
+    #[error("未启用凭据库")]
+    Disabled,
+    #[error("凭据不存在")]
+    NotFound,
+    #[error("该类型的凭据不能用于此处")]
+    WrongKind,
+    #[error("{0}")]
+    Invalid(String),
+    #[error("凭据解密失败，主密钥可能不正确或文件已被修改")]
+    Decrypt,
+    #[error("加密失败: {0}")]
+    Crypto(#[from] openssl::error::ErrorStack),
+    #[error("保存凭据库失败: {0}")]
+    Store(#[from] std::io::Error),
+}
+
+/// 凭据的公开部分，列表接口只返回这些
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialInfo {
+    pub id: Uuid,
+    /// 未启用登录时为空
+    pub owner: Option<String>,
+    pub name: String,
+    pub kind: CredentialKind,
+    /// SSH 凭据只能用于这里的主机和用户
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    /// AI API Key 只会发往这个接口地址
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 解密后的机密，不实现 Debug 以免写进日志
+#[derive(Serialize, Deserialize)]
+pub struct Secret {
+    pub value: String,
+    /// 私钥的口令
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase: Option<String>,
+}
+
+/// AES-256-GCM 密文，tag 附在 ciphertext 末尾
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Sealed {
+    key_id: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    #[serde(flatten)]
+    info: CredentialInfo,
+    sealed: Sealed,
+}
+
+struct MasterKey {
+    /// 密钥哈希的前 8 字节，记录在每条密文上以便轮换时找到对应的密钥
+    id: String,
+    key: [u8; KEY_LEN],
+}
+
+impl MasterKey {
+    fn parse(encoded: &str) -> anyhow::Result<Self> {
+        let key: [u8; KEY_LEN] = STANDARD
+            .decode(encoded.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| anyhow::anyhow!("主密钥必须是 base64 编码的 {} 字节", KEY_LEN))?;
+        Ok(MasterKey { id: HEXLOWER.encode(&Sha256::digest(key)[..8]), key })
+    }
+
+    fn read(path: &str) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("无法读取主密钥 {}: {}", path, e))?;
+        Self::parse(&content).map_err(|e| anyhow::anyhow!("{}: {}", path, e))
+    }
+
+    fn seal(&self, aad: &[u8], secret: &Secret) -> Result<Sealed, VaultError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let plaintext = serde_json::to_vec(secret).expect("secret serializes");
+        let mut tag = [0u8; TAG_LEN];
+        let mut ciphertext = encrypt_aead(Cipher::aes_256_gcm(), &self.key, Some(&nonce), aad, &plaintext, &mut tag)?;
+        ciphertext.extend_from_slice(&tag);
+        Ok(Sealed {
+            key_id: self.id.clone(),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        })
+    }
+
+    fn open(&self, aad: &[u8], sealed: &Sealed) -> Result<Secret, VaultError> {
+        let nonce = STANDARD.decode(&sealed.nonce).map_err(|_| VaultError::Decrypt)?;
+        let data = STANDARD.decode(&sealed.ciphertext).map_err(|_| VaultError::Decrypt)?;
+        if nonce.len() != NONCE_LEN || data.len() < TAG_LEN {
+            return Err(VaultError::Decrypt);
+        }
+        let (ciphertext, tag) = data.split_at(data.len() - TAG_LEN);
+        let plaintext = decrypt_aead(Cipher::aes_256_gcm(), &self.key, Some(&nonce), aad, ciphertext, tag)
+            .map_err(|_| VaultError::Decrypt)?;
+        serde_json::from_slice(&plaintext).map_err(|_| VaultError::Decrypt)
+    }
+}
+
+/// 密文绑定到凭据的归属和用途，改动文件中的这些字段会导致解密失败
+fn aad(info: &CredentialInfo) -> Vec<u8> {
+    let bound = (info.id, &info.owner, info.kind, &info.host, info.port, &info.username);
+    match &info.endpoint {
+        Some(endpoint) => serde_json::to_vec(&(bound, endpoint)),
+        // 未绑定接口地址的旧凭据保持原来的 AAD
+        None => serde_json::to_vec(&bound),
+    }
+    .expect("aad serializes")
+}
+
+/// 生成新的主密钥，供 `ssh-ai-terminal gen-vault-key` 使用
+pub fn generate_key() -> String {
+    let mut key = [0u8; KEY_LEN];
+    rand::thread_rng().fill_bytes(&mut key);
+    STANDARD.encode(key)
+}
+
+/// 加密保存的凭据，写入 `vault.path`
+///
+/// 浏览器只持有凭据 id，密码、私钥和 API Key 在服务端解密后直接用于连接。
+pub struct Vault {
+    path: PathBuf,
+    key: MasterKey,
+    records: Mutex<HashMap<Uuid, Record>>,
+}
+
+impl Vault {
+    pub fn open(config: &VaultConfig) -> anyhow::Result<Self> {
+        let key = match std::env::var(&config.key_env).ok().filter(|v| !v.trim().is_empty()) {
+            Some(encoded) => MasterKey::parse(&encoded).map_err(|e| anyhow::anyhow!("{}: {}", config.key_env, e))?,
+            None => match &config.key_file {
+                Some(path) => MasterKey::read(path)?,
+                None => anyhow::bail!("未设置主密钥: 请设置环境变量 {} 或 vault.key_file", config.key_env),
+            },
+        };
+        let previous = config
+            .previous_key_files
+            .iter()
+            .map(|path| MasterKey::read(path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Self::load(PathBuf::from(&config.path), key, &previous)
+    }
+
+    /// 读取凭据文件，用当前密钥重新加密仍由旧密钥加密的凭据
+    fn load(path: PathBuf, key: MasterKey, previous: &[MasterKey]) -> anyhow::Result<Self> {
+        let mut records: HashMap<Uuid, Record> = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("无法解析凭据库 {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(anyhow::anyhow!("无法读取凭据库 {}: {}", path.display(), e)),
+        };
+        let mut rotated = 0;
+        for record in records.values_mut().filter(|r| r.sealed.key_id != key.id) {
+            let old = previous.iter().find(|k| k.id == record.sealed.key_id).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "凭据 {} 由未知的密钥 {} 加密，当前密钥为 {}，请检查 vault.previous_key_files",
+                    record.info.id,
+                    record.sealed.key_id,
+                    key.id
+                )
+            })?;
+            let aad = aad(&record.info);
+            let secret = old.open(&aad, &record.sealed).map_err(|e| anyhow::anyhow!("凭据 {}: {}", record.info.id, e))?;
+            record.sealed = key.seal(&aad, &secret)?;
+            rotated += 1;
+        }
+        let vault = Vault { path, key, records: Mutex::new(records) };
+        if rotated > 0 {
+            vault.save(&vault.records.lock())?;
+            info!("已用新的主密钥 {} 重新加密 {} 条凭据", vault.key.id, rotated);
+        }
+        Ok(vault)
+    }
+
+    /// 自己保存的凭据，不含机密
+    pub fn list(&self, identity: Option<&Identity>) -> Vec<CredentialInfo> {
+        let owner = identity.map(|i| i.user.as_str());
+        let mut credentials: Vec<_> = self
+            .records
+            .lock()
+            .values()
+            .filter(|r| r.info.owner.as_deref() == owner)
+            .map(|r| r.info.clone())
+            .collect();
+        credentials.sort_by_key(|c| c.created_at);
+        credentials
+    }
+
+    pub fn create(&self, identity: Option<&Identity>, request: CreateCredentialRequest) -> Result<CredentialInfo, VaultError> {
+        let non_empty = |value: Option<String>| value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        let info = CredentialInfo {
+            id: Uuid::new_v4(),
+            owner: identity.map(|i| i.user.clone()),
+            name: request.name.trim().to_string(),
+            kind: request.kind,
+            host: non_empty(request.host),
+            port: request.port.filter(|port| *port != 0),
+            username: non_empty(request.username),
+            endpoint: non_empty(request.endpoint).filter(|_| request.kind == CredentialKind::AiApiKey),
+            created_at: Utc::now(),
+        };
+        if info.name.is_empty() || request.secret.is_empty() {
+            return Err(VaultError::Invalid("名称和机密不能为空".to_string()));
+        }
+        // 不绑定主机的 SSH 凭据可以被发往任意主机
+        if info.kind != CredentialKind::AiApiKey && (info.host.is_none() || info.username.is_none()) {
+            return Err(VaultError::Invalid("SSH 凭据必须指定主机和用户名".to_string()));
+        }
+        // API Key 由服务端发出，接口地址不绑定时任何人都能把它发往自己的服务器
+        if info.kind == CredentialKind::AiApiKey
+            && !info.endpoint.as_deref().is_some_and(|e| e.starts_with("https://") || e.starts_with("http://"))
+        {
+            return Err(VaultError::Invalid("AI API Key 必须指定 http(s) 接口地址".to_string()));
+        }
+        let secret = Secret { value: request.secret, passphrase: request.passphrase.filter(|p| !p.is_empty()) };
+        let sealed = self.key.seal(&aad(&info), &secret)?;
+
+        let mut records = self.records.lock();
+        records.insert(info.id, Record { info: info.clone(), sealed });
+        self.save(&records)?;
+        audit::record(info.owner.as_deref(), info.host.as_deref(), AuditEvent::Account {
+            action: "credential_created".to_string(),
+            detail: Some(format!("credential {} {:?}", info.id, info.kind)),
+        });
+        Ok(info)
+    }
+
+    /// 删除凭据，admin 可以删除任何人的凭据
+    pub fn delete(&self, identity: Option<&Identity>, id: Uuid) -> Result<(), VaultError> {
+        let mut records = self.records.lock();
+        let visible = records.get(&id).is_some_and(|r| match identity {
+            Some(identity) => identity.role == UserRole::Admin || r.info.owner.as_deref() == Some(identity.user.as_str()),
+            None => r.info.owner.is_none(),
+        });
+        if !visible {
+            return Err(VaultError::NotFound);
+        }
+        let removed = records.remove(&id).map(|r| r.info);
+        self.save(&records)?;
+        audit::record(identity.map(|i| i.user.as_str()), None, AuditEvent::Account {
+            action: "credential_deleted".to_string(),
+            detail: removed.map(|info| format!("credential {} owner {:?}", info.id, info.owner)),
+        });
+        Ok(())
+    }
+
+    /// 解密自己的凭据供服务端使用，只有所有者可以使用
+    pub fn reveal(
+        &self,
+        identity: Option<&Identity>,
+        id: Uuid,
+        kinds: &[CredentialKind],
+    ) -> Result<(CredentialInfo, Secret), VaultError> {
+        let record = self
+            .records
+            .lock()
+            .get(&id)
+            .filter(|r| r.info.owner.as_deref() == identity.map(|i| i.user.as_str()))
+            .cloned()
+            .ok_or(VaultError::NotFound)?;
+        if !kinds.contains(&record.info.kind) {
+            return Err(VaultError::WrongKind);
+        }
+        let secret = self.key.open(&aad(&record.info), &record.sealed)?;
+        audit::record(record.info.owner.as_deref(), record.info.host.as_deref(), AuditEvent::Account {
+            action: "credential_used".to_string(),
+            detail: Some(format!("credential {}", id)),
+        });
+        Ok((record.info, secret))
+    }
+
+    fn save(&self, records: &HashMap<Uuid, Record>) -> std::io::Result<()> {
+        let content = serde_json::to_vec_pretty(records)?;
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        write_private(&tmp, &content)?;
+        std::fs::rename(&tmp, &self.path)
+    }
+}
+
+/// 凭据文件只允许服务进程读写
+#[cfg(unix)]
+fn write_private(path: &std::path::Path, content: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = std::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &std::path::Path, content: &[u8]) -> std::io::Result<()> {
+    std::fs::write(path, content)
+}
+
+#[derive(Deserialize)]
+pub struct CreateCredentialRequest {
+    name: String,
+    kind: CredentialKind,
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    port: Option<u16>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    endpoint: Option<String>,
+    /// 密码、私钥或 API Key，保存后不再返回
+    secret: String,
+    #[serde(default)]
+    passphrase: Option<String>,
+}
+
+pub async fn handle_list(identity: Option<Identity>, state: AppState) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(vault) = &state.vault else {
+        return Ok(error_reply(&VaultError::Disabled));
+    };
+    Ok(warp::reply::json(&vault.list(identity.as_ref())).into_response())
+}
+
+pub async fn handle_create(
+    identity: Option<Identity>,
+    request: CreateCredentialRequest,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(vault) = &state.vault else {
+        return Ok(error_reply(&VaultError::Disabled));
+    };
+    Ok(match vault.create(identity.as_ref(), request) {
+        Ok(info) => warp::reply::json(&info).into_response(),
+        Err(e) => error_reply(&e),
+    })
+}
+
+pub async fn handle_delete(
+    id: Uuid,
+    identity: Option<Identity>,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(vault) = &state.vault else {
+        return Ok(error_reply(&VaultError::Disabled));
+    };
+    Ok(match vault.delete(identity.as_ref(), id) {
+        Ok(()) => warp::reply::json(&serde_json::json!({ "ok": true })).into_response(),
+        Err(e) => error_reply(&e),
+    })
+}
+
+pub fn error_reply(error: &VaultError) -> warp::reply::Response {
+    let status = match error {
+        VaultError::Disabled | VaultError::NotFound => StatusCode::NOT_FOUND,
+        VaultError::WrongKind | VaultError::Invalid(_) => StatusCode::BAD_REQUEST,
+        VaultError::Decrypt | VaultError::Crypto(_) | VaultError::Store(_) => {
+            error!("凭据库错误: {}", error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    let body = warp::reply::json(&serde_json::json!({ "error": error.to_string() }));
+    warp::reply::with_status(body, status).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_at_rest_owner_bound_and_rotated() {
+        let path = std::env::temp_dir().join(format!("vault-{}.json", Uuid::new_v4()));
+        let old_key = generate_key();
+        let vault = Vault::load(path.clone(), MasterKey::parse(&old_key).unwrap(), &[]).unwrap();
+        let alice = Identity { user: "alice".to_string(), role: UserRole::Operator, groups: Vec::new() };
+        let request = |kind, host: Option<&str>| CreateCredentialRequest {
+            name: "web-1".to_string(),
+            kind,
+            host: host.map(str::to_string),
+            port: Some(22),
+            username: Some("deploy".to_string()),
+            endpoint: None,
+            secret: "hunter2-secret".to_string(),
+            passphrase: None,
+        };
+
+        assert!(matches!(vault.create(Some(&alice), request(CredentialKind::Password, None)), Err(VaultError::Invalid(_))));
+        let info = vault.create(Some(&alice), request(CredentialKind::Password, Some("web-1.example.com"))).unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("hunter2-secret"));
+
+        let (_, secret) = vault.reveal(Some(&alice), info.id, &[CredentialKind::Password]).unwrap();
+        assert_eq!(secret.value, "hunter2-secret");
+        assert!(matches!(vault.reveal(Some(&alice), info.id, &[CredentialKind::AiApiKey]), Err(VaultError::WrongKind)));
+        let bob = Identity { user: "bob".to_string(), role: UserRole::Operator, groups: Vec::new() };
+        assert!(matches!(vault.reveal(Some(&bob), info.id, &[CredentialKind::Password]), Err(VaultError::NotFound)));
+        assert!(vault.list(Some(&bob)).is_empty());
+
+        // 没有旧密钥时拒绝启动，提供旧密钥后重新加密
+        let new_key = MasterKey::parse(&generate_key()).unwrap();
+        assert!(Vault::load(path.clone(), MasterKey::parse(&generate_key()).unwrap(), &[]).is_err());
+        let rotated = Vault::load(path.clone(), new_key, &[MasterKey::parse(&old_key).unwrap()]).unwrap();
+        let (_, secret) = rotated.reveal(Some(&alice), info.id, &[CredentialKind::Password]).unwrap();
+        assert_eq!(secret.value, "hunter2-secret");
+        assert!(!std::fs::read_to_string(&path).unwrap().contains(&MasterKey::parse(&old_key).unwrap().id));
+
+        rotated.delete(Some(&alice), info.id).unwrap();
+        assert!(rotated.list(Some(&alice)).is_empty());
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn ai_api_key_is_bound_to_its_endpoint() {
+        let path = std::env::temp_dir().join(format!("vault-{}.json", Uuid::new_v4()));
+        let vault = Vault::load(path.clone(), MasterKey::parse(&generate_key()).unwrap(), &[]).unwrap();
+        let request = |endpoint: Option<&str>| CreateCredentialRequest {
+            name: "openai".to_string(),
+            kind: CredentialKind::AiApiKey,
+            host: None,
+            port: None,
+            username: None,
+            endpoint: endpoint.map(str::to_string),
+            secret: "sk-secret".to_string(),
+            passphrase: None,
+        };
+        assert!(matches!(vault.create(None, request(None)), Err(VaultError::Invalid(_))));
+        assert!(matches!(vault.create(None, request(Some("file:///etc/passwd"))), Err(VaultError::Invalid(_))));
+        let info = vault.create(None, request(Some("https://api.openai.com/v1/chat/completions"))).unwrap();
+
+        // 改写文件中的接口地址后无法解密
+        let content = std::fs::read_to_string(&path).unwrap().replace("api.openai.com", "evil.example.com");
+        std::fs::write(&path, content).unwrap();
+        let key = MasterKey { id: vault.key.id.clone(), key: vault.key.key };
+        let tampered = Vault::load(path.clone(), key, &[]).unwrap();
+        assert!(matches!(tampered.reveal(None, info.id, &[CredentialKind::AiApiKey]), Err(VaultError::Decrypt)));
+        std::fs::remove_file(path).ok();
+    }
+}
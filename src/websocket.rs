@@ -1,19 +1,313 @@
+use warp::Filter;
 use warp::ws::{WebSocket, Message};
 use futures_util::{StreamExt, SinkExt};
 use tokio::sync::mpsc;
 use uuid::Uuid;
-use tracing::{info, error};
+use tracing::{info, error, warn};
+use metrics::{counter, decrement_gauge, increment_gauge};
 
-use crate::{Sessions, models::*, ssh::SSHSession};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{AppState, broadcast::{self, BroadcastGroups}, models::*, rbac, share::ShareClaims};
+use crate::approval::Held;
+use crate::audit::{self, AuditEvent};
+use crate::auth::Identity;
+use crate::config::{PolicyAction, UserRole};
+use crate::destination::DestinationError;
+use crate::policy::Verdict;
+use crate::ratelimit::LimitError;
+use crate::risk::{self, RiskLevel};
+use crate::session::{AccessError, SharedSession};
+use crate::ssh::SSHAuth;
+use crate::terminal::{self, SessionEvent, TerminalBackend, TerminalTarget, DEFAULT_COLS, DEFAULT_ROWS};
+use crate::vault::{CredentialKind, VaultError};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct WsQuery {
+    /// 分享链接中的令牌，持有者只能访问链接对应的会话
+    share: Option<String>,
+}
+
+/// 未登录但持有有效分享链接的访客，以只读账户的身份接入
+///
+/// 放在登录校验之后作为备选，只在链接仍然有效时放行；访客不能打开或附加其它会话，
+/// 审计日志中记为 `share:<链接ID>`。
+pub fn share_guest(state: AppState) -> impl Filter<Extract = (Option<Identity>,), Error = warp::Rejection> + Clone {
+    warp::query::<WsQuery>().and_then(move |query: WsQuery| {
+        let state = state.clone();
+        async move {
+            let claims = query
+                .share
+                .as_deref()
+                .and_then(|token| state.share.verify(token).ok())
+                .filter(|claims| {
+                    let session = state.sessions.get(&claims.session_id).map(|s| s.clone());
+                    session.is_some_and(|s| s.link_role(claims.link_id).is_some())
+                })
+                .ok_or_else(warp::reject::not_found)?;
+            Ok::<_, warp::Rejection>(Some(Identity {
+                user: format!("share:{}", claims.link_id),
+                role: UserRole::Viewer,
+                groups: Vec::new(),
+            }))
+        }
+    })
+}
 
 pub async fn handle_websocket(
+    identity: Option<Identity>,
     ws: warp::ws::Ws,
-    sessions: Sessions,
+    query: WsQuery,
+    remote: Option<SocketAddr>,
+    state: AppState,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    Ok(ws.on_upgrade(move |socket| client_connection(socket, sessions)))
+    Ok(ws.on_upgrade(move |socket| client_connection(socket, identity, query, remote, state)))
+}
+
+/// 单个WebSocket连接的状态
+struct Client {
+    id: Uuid,
+    tx: mpsc::Sender<String>,
+    remote: Option<SocketAddr>,
+    /// 登录的账户，未启用登录时为空
+    identity: Option<Identity>,
+    /// 当前连接参与的会话
+    joined: HashSet<Uuid>,
+    /// 通过分享链接接入时不能打开或附加其它会话
+    share: Option<ShareClaims>,
+    broadcast: BroadcastGroups,
+    /// 各会话尚未回车的输入，用于审计和策略判定整行命令
+    input: HashMap<Uuid, String>,
+    /// 各会话等待确认的命令
+    pending: HashMap<Uuid, PendingCommand>,
+}
+
+/// 命中 confirm 规则、暂不发送的命令
+struct PendingCommand {
+    confirm_id: Uuid,
+    line: String,
+    /// 确认后写入终端的数据
+    release: String,
+    /// 拒绝后写入终端的数据
+    discard: String,
 }
 
-async fn client_connection(ws: WebSocket, sessions: Sessions) {
+/// 经命令策略筛选后的输入
+#[derive(Default)]
+struct Screened {
+    /// 可以写入终端的数据
+    data: String,
+    /// 需要回复给客户端的拦截或确认通知
+    notices: Vec<WebSocketResponse>,
+    /// 需要审批的命令
+    held: Option<Held>,
+}
+
+impl Client {
+    async fn send(&self, response: &WebSocketResponse) {
+        let _ = self.tx.send(serde_json::to_string(response).unwrap()).await;
+    }
+
+    async fn error(&self, message: String) {
+        self.send(&WebSocketResponse::Error { message }).await;
+    }
+
+    fn user(&self) -> Option<&str> {
+        self.identity.as_ref().map(|i| i.user.as_str())
+    }
+
+    async fn denied(&self, session_id: Option<Uuid>, code: AccessError) {
+        self.send(&WebSocketResponse::AccessDenied { session_id, code, message: code.to_string() }).await;
+    }
+
+    async fn limited(&self, error: LimitError) {
+        self.send(&WebSocketResponse::LimitExceeded {
+            code: error.code(),
+            message: error.to_string(),
+            retry_after: error.retry_after(),
+        }).await;
+    }
+
+    /// 打开会话前的限流，SSH 会话还要检查会话数上限
+    async fn check_limits(&self, state: &AppState, ssh: bool) -> bool {
+        let mut result = state.limits.check_connect(self.user(), self.remote.map(|addr| addr.ip()));
+        if ssh {
+            result = result.and_then(|_| state.limits.check_ssh_sessions(&state.sessions, self.user()));
+        }
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                audit::record(self.user(), None, AuditEvent::ConnectDenied { reason: e.to_string() });
+                self.limited(e).await;
+                false
+            }
+        }
+    }
+
+    /// 按命令策略筛选输入并审计提交的整行命令，自动执行的AI命令单独记录
+    ///
+    /// 命中 deny 的行不发送回车并用 Ctrl-U 清掉已输入的内容；命中 confirm 的行
+    /// 暂扣回车，等待 confirm_command；需要审批的行同样被清掉，交给 `Approvals`
+    /// 保管，批准后由服务端重新写入。广播输入不支持确认和审批 (interactive 为
+    /// false)，按拦截处理。被拦截或暂扣之后，同一次输入中剩余的内容也不再发送。
+    fn screen_input(
+        &mut self,
+        state: &AppState,
+        session: &SharedSession,
+        data: &str,
+        auto_execute: bool,
+        interactive: bool,
+    ) -> Screened {
+        // 任何新的输入都会使之前等待确认的命令作废
+        self.pending.remove(&session.id);
+        let identity = self.identity.as_ref();
+        let user = identity.map(|i| i.user.as_str());
+        let host = session.host.as_deref();
+        let mut screened = Screened::default();
+        // 未启用登录时无法区分申请人和审批人
+        let can_hold = interactive && identity.is_some();
+
+        if auto_execute {
+            let command = data.trim_end_matches(['\r', '\n']).to_string();
+            let verdict = state.approvals.escalate(state.policy.evaluate_script(identity, host, &command), &command);
+            if verdict.action == PolicyAction::Allow {
+                audit::record(user, host, AuditEvent::AutoExecute { session_id: session.id, command });
+                screened.data.push_str(data);
+                return screened;
+            }
+            audit::record(user, host, AuditEvent::PolicyViolation {
+                session_id: Some(session.id),
+                command: command.clone(),
+                action: verdict.action,
+                rule: verdict.rule,
+            });
+            match verdict.action {
+                PolicyAction::Confirm if interactive => {
+                    let confirm_id = Uuid::new_v4();
+                    self.pending.insert(session.id, PendingCommand {
+                        confirm_id,
+                        line: command.clone(),
+                        release: data.to_string(),
+                        discard: String::new(),
+                    });
+                    screened.notices.push(WebSocketResponse::CommandConfirm {
+                        session_id: session.id,
+                        confirm_id,
+                        command,
+                        message: verdict.message,
+                    });
+                }
+                PolicyAction::Approve if can_hold => {
+                    screened.held = Some(Held { command, release: data.to_string(), message: verdict.message });
+                }
+                _ => screened.notices.push(blocked(session.id, command, verdict)),
+            }
+            return screened;
+        }
+
+        let buffer = self.input.entry(session.id).or_default();
+        // 每个片段至多以一个回车或换行结尾，含回车/换行的片段只有在整行经过策略判定后才写入
+        for chunk in data.split_inclusive(['\r', '\n']) {
+            let Some(line) = audit::submitted_lines(buffer, chunk).pop() else {
+                if chunk.ends_with(['\r', '\n']) {
+                    // 拼不出整行时不发送回车
+                    screened.data.push_str(&chunk[..chunk.len() - 1]);
+                    break;
+                }
+                screened.data.push_str(chunk);
+                continue;
+            };
+            let verdict = state.approvals.escalate(state.policy.evaluate(identity, host, &line), &line);
+            if verdict.action == PolicyAction::Allow {
+                if !line.trim().is_empty() {
+                    // 密码提示处输入的内容不记录，其余输入同样遮盖其中的密钥
+                    let line = if session.awaiting_secret() {
+                        "[REDACTED:secret_prompt]".to_string()
+                    } else {
+                        state.redactor.redact(&line, &mut Default::default())
+                    };
+                    audit::record(user, host, AuditEvent::Input { session_id: session.id, line });
+                }
+                screened.data.push_str(chunk);
+                continue;
+            }
+
+            audit::record(user, host, AuditEvent::PolicyViolation {
+                session_id: Some(session.id),
+                command: line.clone(),
+                action: verdict.action,
+                rule: verdict.rule,
+            });
+            // 去掉回车，之前的输入照常写入
+            screened.data.push_str(&chunk[..chunk.len() - 1]);
+            match verdict.action {
+                PolicyAction::Confirm if interactive => {
+                    let confirm_id = Uuid::new_v4();
+                    buffer.clone_from(&line);
+                    self.pending.insert(session.id, PendingCommand {
+                        confirm_id,
+                        line: line.clone(),
+                        release: "\r".to_string(),
+                        discard: "\u{15}".to_string(),
+                    });
+                    screened.notices.push(WebSocketResponse::CommandConfirm {
+                        session_id: session.id,
+                        confirm_id,
+                        command: line,
+                        message: verdict.message,
+                    });
+                }
+                PolicyAction::Approve if can_hold => {
+                    screened.data.push('\u{15}');
+                    let release = format!("{}\r", line);
+                    screened.held = Some(Held { command: line, release, message: verdict.message });
+                }
+                _ => {
+                    screened.data.push('\u{15}');
+                    screened.notices.push(blocked(session.id, line, verdict));
+                }
+            }
+            break;
+        }
+        screened
+    }
+
+    /// 写入筛选后的输入、发送通知并提交需要审批的命令
+    async fn write_screened(&self, state: &AppState, session: &SharedSession, screened: Screened) {
+        if !screened.data.is_empty() {
+            if let Err(e) = session.backend.write(&screened.data).await {
+                self.error(format!("Write failed: {}", e)).await;
+            }
+        }
+        for notice in &screened.notices {
+            self.send(notice).await;
+        }
+        if let (Some(held), Some(identity)) = (screened.held, &self.identity) {
+            let pending = state.approvals.submit(&state.config.auth, identity, self.id, self.tx.clone(), session, held);
+            self.send(&pending).await;
+        }
+    }
+}
+
+fn blocked(session_id: Uuid, command: String, verdict: Verdict) -> WebSocketResponse {
+    let message = match verdict.action {
+        PolicyAction::Confirm => format!("{} (广播输入不支持确认，未发送)", verdict.message),
+        PolicyAction::Approve => format!("{} (广播输入或未启用登录时无法提交审批，未发送)", verdict.message),
+        _ => verdict.message,
+    };
+    WebSocketResponse::CommandBlocked { session_id, command, message }
+}
+
+async fn client_connection(
+    ws: WebSocket,
+    identity: Option<Identity>,
+    query: WsQuery,
+    remote: Option<SocketAddr>,
+    state: AppState,
+) {
     let (mut ws_tx, mut ws_rx) = ws.split();
     let (tx, mut rx) = mpsc::channel(100);
 
@@ -25,12 +319,37 @@ async fn client_connection(ws: WebSocket, sessions: Sessions) {
         }
     });
 
+    let mut client = Client {
+        id: Uuid::new_v4(),
+        tx,
+        remote,
+        identity,
+        joined: HashSet::new(),
+        share: None,
+        broadcast: BroadcastGroups::default(),
+        input: HashMap::new(),
+        pending: HashMap::new(),
+    };
+    client.send(&WebSocketResponse::Hello { client_id: client.id }).await;
+    if let Some(token) = query.share {
+        if let Err(e) = join_shared(&token, &state, &mut client).await {
+            client.error(format!("Share link rejected: {}", e)).await;
+            return;
+        }
+    }
+    // 登记在所有提前返回之后，保证断开时 remove_approver 一定会执行
+    if let Some(identity) = &client.identity {
+        state.approvals.register_approver(&state.config.auth, client.id, identity, client.tx.clone());
+    }
+    counter!("websocket_connections_total", 1);
+    increment_gauge!("websocket_connections_active", 1.0);
+
     while let Some(result) = ws_rx.next().await {
         match result {
             Ok(msg) => {
                 if let Ok(text) = msg.to_str() {
                     if let Ok(ws_msg) = serde_json::from_str::<WebSocketMessage>(text) {
-                        handle_message(ws_msg, &sessions, &tx).await;
+                        handle_message(ws_msg, &state, &mut client).await;
                     }
                 }
             }
@@ -41,58 +360,498 @@ async fn client_connection(ws: WebSocket, sessions: Sessions) {
         }
     }
 
-    info!("WebSocket connection closed");
+    // 自己创建的会话按配置保留等待恢复或直接关闭，其余会话只退出
+    let grace = Duration::from_secs(state.config.session.resume_grace);
+    let (mut closed, mut detached) = (0, 0);
+    for session_id in &client.joined {
+        let session = state.sessions.get(session_id).map(|s| s.clone());
+        let Some(session) = session else {
+            continue;
+        };
+        if session.role_of(client.id) != Some(Role::Owner) {
+            session.leave(client.id).await;
+        } else if grace.is_zero() {
+            session.close(&state.sessions, "client_closed").await;
+            closed += 1;
+        } else {
+            session.detach_owner(grace, &state.sessions).await;
+            detached += 1;
+        }
+    }
+    state.approvals.remove_approver(client.id);
+    decrement_gauge!("websocket_connections_active", 1.0);
+
+    info!("WebSocket connection closed ({} sessions closed, {} detached)", closed, detached);
 }
 
 async fn handle_message(
     msg: WebSocketMessage,
-    sessions: &Sessions,
-    tx: &mpsc::Sender<String>,
+    state: &AppState,
+    client: &mut Client,
 ) {
+    if client.share.is_some() && matches!(
+        msg,
+        WebSocketMessage::Connect { .. }
+            | WebSocketMessage::OpenChannel { .. }
+            | WebSocketMessage::Attach { .. }
+            | WebSocketMessage::Resume { .. }
+            | WebSocketMessage::BroadcastJoin { .. }
+    ) {
+        client.denied(None, AccessError::ShareRestricted).await;
+        return;
+    }
+
     match msg {
-        WebSocketMessage::Connect { host, port, username, password } => {
-            match SSHSession::new(&host, port, &username, &password).await {
-                Ok((ssh_session, mut rx)) => {
-                    let session_id = Uuid::new_v4();
-                    
-                    let tx_clone = tx.clone();
-                    tokio::spawn(async move {
-                        while let Some(data) = rx.recv().await {
-                            let response = WebSocketResponse::Data { data };
-                            if tx_clone.send(serde_json::to_string(&response).unwrap()).await.is_err() {
-                                break;
-                            }
-                        }
-                    });
-                    
-                    sessions.insert(session_id, ssh_session);
-                    
-                    let response = WebSocketResponse::Connected { session_id };
-                    let _ = tx.send(serde_json::to_string(&response).unwrap()).await;
+        WebSocketMessage::Connect {
+            protocol,
+            mut host,
+            mut port,
+            mut username,
+            password,
+            credential_id,
+            auto_reconnect,
+            cols,
+            rows,
+        } => {
+            if !client.check_limits(state, protocol == Protocol::Ssh).await {
+                return;
+            }
+            let auth = match credential_id {
+                Some(id) => match vault_credential(state, client, protocol, id, &mut host, &mut port, &mut username) {
+                    Ok(auth) => auth,
+                    Err(e) => {
+                        client.error(format!("Connection failed: {}", e)).await;
+                        return;
+                    }
+                },
+                None => SSHAuth::Password(password),
+            };
+            let port = if port == 0 { protocol.default_port() } else { port };
+            let target_host = (protocol != Protocol::Local).then(|| host.clone());
+            if let Err(code) = rbac::check_connect(&state.config.auth, client.identity.as_ref(), target_host.as_deref()) {
+                audit::record(client.user(), target_host.as_deref(), AuditEvent::ConnectDenied { reason: code.to_string() });
+                client.denied(None, code).await;
+                return;
+            }
+            // 解析后检查目标地址，之后只连接检查过的地址
+            let addrs = match &target_host {
+                Some(host) => match state.destinations.resolve(host, port).await {
+                    Ok(addrs) => addrs,
+                    Err(e @ DestinationError::Denied { .. }) => {
+                        warn!("Connection to {}:{} denied by destinations: {}", host, port, e);
+                        audit::record(client.user(), Some(host), AuditEvent::ConnectDenied { reason: e.to_string() });
+                        client.send(&WebSocketResponse::AccessDenied {
+                            session_id: None,
+                            code: AccessError::DestinationNotAllowed,
+                            message: e.to_string(),
+                        }).await;
+                        return;
+                    }
+                    Err(e) => {
+                        client.error(format!("Connection failed: {}", e)).await;
+                        return;
+                    }
+                },
+                None => Vec::new(),
+            };
+            let target = match protocol {
+                Protocol::Ssh => TerminalTarget::Ssh { host, port, addrs, username, auth, auto_reconnect },
+                Protocol::Telnet => TerminalTarget::Telnet { host, port, addrs },
+                Protocol::Local => TerminalTarget::Local,
+            };
+            let cols = cols.unwrap_or(DEFAULT_COLS);
+            let rows = rows.unwrap_or(DEFAULT_ROWS);
+            match terminal::open(target, cols, rows, &state.config, &state.connections).await {
+                Ok((backend, rx)) => register_session(backend, rx, target_host, state, client).await,
+                Err(e) => client.error(format!("Connection failed: {}", e)).await,
+            }
+        }
+        WebSocketMessage::OpenChannel { connection_id } => {
+            // 只能复用自己创建的会话所在的连接
+            let Some(host) = owned_connection_host(state, client, connection_id) else {
+                client.denied(None, AccessError::UnknownSession).await;
+                return;
+            };
+            if !client.check_limits(state, true).await {
+                return;
+            }
+            if let Err(code) = rbac::check_connect(&state.config.auth, client.identity.as_ref(), host.as_deref()) {
+                audit::record(client.user(), host.as_deref(), AuditEvent::ConnectDenied { reason: code.to_string() });
+                client.denied(None, code).await;
+                return;
+            }
+            let connection = state.connections.get(&connection_id).map(|c| c.clone());
+            let result = match connection {
+                Some(connection) => connection.open_channel().await
+                    .map(|(session, rx)| (Arc::new(session) as Arc<dyn TerminalBackend>, rx))
+                    .map_err(|e| e.to_string()),
+                None => Err(format!("Unknown connection: {}", connection_id)),
+            };
+            match result {
+                Ok((backend, rx)) => register_session(backend, rx, host, state, client).await,
+                Err(e) => client.error(format!("Open channel failed: {}", e)).await,
+            }
+        }
+        WebSocketMessage::Data { session_id, data, auto_execute } => {
+            let Some(session) = authorized(state, client, session_id, Role::Writer).await else {
+                return;
+            };
+            if let Some(group_id) = client.broadcast.group_of(session_id) {
+                broadcast_write(state, client, group_id, &data, auto_execute).await;
+                return;
+            }
+            if auto_execute && !rbac::can_auto_execute(&state.config.auth, client.identity.as_ref(), session.host.as_deref()) {
+                client.denied(Some(session_id), AccessError::AutoExecuteNotAllowed).await;
+                return;
+            }
+            if auto_execute && risk::assess(&data).level > RiskLevel::Low {
+                client.denied(Some(session_id), AccessError::AutoExecuteRisky).await;
+                return;
+            }
+            let screened = client.screen_input(state, &session, &data, auto_execute, true);
+            client.write_screened(state, &session, screened).await;
+        }
+        WebSocketMessage::ConfirmCommand { session_id, confirm_id, approve } => {
+            let Some(session) = authorized(state, client, session_id, Role::Writer).await else {
+                return;
+            };
+            let pending = match client.pending.remove(&session_id) {
+                Some(pending) if pending.confirm_id == confirm_id => pending,
+                other => {
+                    if let Some(pending) = other {
+                        client.pending.insert(session_id, pending);
+                    }
+                    client.error(format!("Unknown or expired confirmation: {}", confirm_id)).await;
+                    return;
                 }
-                Err(e) => {
-                    let response = WebSocketResponse::Error { 
-                        message: format!("Connection failed: {}", e) 
-                    };
-                    let _ = tx.send(serde_json::to_string(&response).unwrap()).await;
+            };
+            client.input.remove(&session_id);
+            audit::record(client.user(), session.host.as_deref(), AuditEvent::CommandConfirmed {
+                session_id,
+                command: pending.line,
+                approved: approve,
+            });
+            let data = if approve { pending.release } else { pending.discard };
+            client.write_screened(state, &session, Screened { data, ..Default::default() }).await;
+        }
+        WebSocketMessage::ApproveCommand { approval_id, approve } => {
+            let Some(identity) = client.identity.clone() else {
+                client.error("Approval requires login".to_string()).await;
+                return;
+            };
+            let result = state.approvals
+                .decide(&state.config.auth, &state.sessions, &identity, approval_id, approve)
+                .await;
+            if let Err(e) = result {
+                client.error(format!("Approval failed: {}", e)).await;
+            }
+        }
+        WebSocketMessage::Resize { session_id, cols, rows } => {
+            let Some(session) = authorized(state, client, session_id, Role::Writer).await else {
+                return;
+            };
+            if let Err(e) = session.backend.resize(cols, rows).await {
+                client.error(format!("Resize failed: {}", e)).await;
+            }
+        }
+        WebSocketMessage::Disconnect { session_id } => {
+            let Some(session) = authorized(state, client, session_id, Role::Viewer).await else {
+                return;
+            };
+            if session.role_of(client.id) == Some(Role::Owner) {
+                session.close(&state.sessions, "disconnect").await;
+            } else {
+                session.leave(client.id).await;
+                client.send(&WebSocketResponse::Disconnected { session_id }).await;
+            }
+            client.joined.remove(&session_id);
+            client.input.remove(&session_id);
+            client.pending.remove(&session_id);
+            client.broadcast.remove_session(session_id);
+        }
+        WebSocketMessage::Attach { session_id } => {
+            let session = state.sessions.get(&session_id).map(|s| s.clone());
+            let result = session
+                .ok_or(AccessError::UnknownSession)
+                .and_then(|s| s.attach_role(client.id, client.user()).map(|role| (s, role)));
+            let (session, role) = match result {
+                Ok(attached) => attached,
+                Err(code) => {
+                    client.denied(Some(session_id), code).await;
+                    return;
+                }
+            };
+            let participants = session.join(client.id, client.tx.clone(), client.user(), role, None).await;
+            client.joined.insert(session_id);
+            client.send(&WebSocketResponse::Attached { session_id, role, participants }).await;
+        }
+        WebSocketMessage::Resume { session_id, resume_token } => {
+            let session = state.sessions.get(&session_id).map(|s| s.clone());
+            let Some(session) = session else {
+                client.denied(Some(session_id), AccessError::UnknownSession).await;
+                return;
+            };
+            match session.resume(client.id, client.tx.clone(), client.user(), resume_token.as_deref()).await {
+                Ok(participants) => {
+                    info!("会话 {} 已由 {} 恢复", session_id, client.id);
+                    client.joined.insert(session_id);
+                    client.send(&WebSocketResponse::Attached { session_id, role: Role::Owner, participants }).await;
                 }
+                Err(code) => client.denied(Some(session_id), code).await,
             }
         }
-        WebSocketMessage::Data { session_id, data } => {
-            if let Some(session) = sessions.get(&session_id) {
-                let mut ssh = session.lock().await;
-                if let Err(e) = ssh.write(&data) {
-                    let response = WebSocketResponse::Error { 
-                        message: format!("Write failed: {}", e) 
-                    };
-                    let _ = tx.send(serde_json::to_string(&response).unwrap()).await;
+        WebSocketMessage::Detach { session_id } => {
+            let Some(session) = authorized(state, client, session_id, Role::Viewer).await else {
+                return;
+            };
+            if session.role_of(client.id) == Some(Role::Owner) {
+                client.error("Owner cannot detach, use disconnect".to_string()).await;
+                return;
+            }
+            session.leave(client.id).await;
+            client.joined.remove(&session_id);
+            client.send(&WebSocketResponse::Disconnected { session_id }).await;
+        }
+        WebSocketMessage::GrantControl { session_id, client_id } => {
+            set_role(state, client, session_id, client_id, Role::Writer).await;
+        }
+        WebSocketMessage::RevokeControl { session_id, client_id } => {
+            set_role(state, client, session_id, client_id, Role::Viewer).await;
+        }
+        WebSocketMessage::Kick { session_id, client_id } => {
+            let Some(session) = authorized(state, client, session_id, Role::Owner).await else {
+                return;
+            };
+            if !session.kick(client_id).await {
+                client.error(format!("Cannot remove {}", client_id)).await;
+            }
+        }
+        WebSocketMessage::CreateShareLink { session_id, role, ttl } => {
+            let Some(session) = authorized(state, client, session_id, Role::Owner).await else {
+                return;
+            };
+            let role = role.unwrap_or(Role::Viewer);
+            if role == Role::Owner {
+                client.error("Share links cannot grant ownership".to_string()).await;
+                return;
+            }
+            let share = &state.config.share;
+            let ttl = Duration::from_secs(ttl.unwrap_or(share.default_ttl).clamp(1, share.max_ttl));
+            let link = session.create_link(role, ttl);
+            let token = state.share.sign(&ShareClaims {
+                link_id: link.link_id,
+                session_id,
+                role,
+                expires_at: link.expires_at.timestamp(),
+            });
+            audit::record(client.user(), session.host.as_deref(), AuditEvent::ShareLink {
+                session_id,
+                link_id: link.link_id,
+                action: format!("created ({:?}, expires {})", role, link.expires_at),
+            });
+            let url = format!("/?share={}", token);
+            client.send(&WebSocketResponse::ShareLinkCreated { session_id, link, token, url }).await;
+        }
+        WebSocketMessage::RevokeShareLink { session_id, link_id } => {
+            let Some(session) = authorized(state, client, session_id, Role::Owner).await else {
+                return;
+            };
+            if session.revoke_link(link_id).await {
+                audit::record(client.user(), session.host.as_deref(), AuditEvent::ShareLink {
+                    session_id,
+                    link_id,
+                    action: "revoked".to_string(),
+                });
+                client.send(&WebSocketResponse::ShareLinks { session_id, links: session.links() }).await;
+            } else {
+                client.error(format!("Unknown share link: {}", link_id)).await;
+            }
+        }
+        WebSocketMessage::ListShareLinks { session_id } => {
+            let Some(session) = authorized(state, client, session_id, Role::Owner).await else {
+                return;
+            };
+            client.send(&WebSocketResponse::ShareLinks { session_id, links: session.links() }).await;
+        }
+        WebSocketMessage::BroadcastJoin { group_id, session_ids } => {
+            for session_id in &session_ids {
+                if authorized(state, client, *session_id, Role::Owner).await.is_none() {
+                    return;
                 }
             }
+            let group_id = client.broadcast.join(group_id, &session_ids);
+            info!("广播组 {} 加入会话 {:?}", group_id, session_ids);
+            let session_ids = client.broadcast.members(group_id);
+            client.send(&WebSocketResponse::BroadcastGroup { group_id, session_ids }).await;
         }
-        WebSocketMessage::Disconnect { session_id } => {
-            sessions.remove(&session_id);
-            let response = WebSocketResponse::Disconnected;
-            let _ = tx.send(serde_json::to_string(&response).unwrap()).await;
+        WebSocketMessage::BroadcastLeave { group_id, session_ids } => {
+            client.broadcast.leave(group_id, &session_ids);
+            let session_ids = client.broadcast.members(group_id);
+            client.send(&WebSocketResponse::BroadcastGroup { group_id, session_ids }).await;
+        }
+    }
+}
+
+/// 把输入写入广播组内所有仍然存在的会话，单个会话失败只单独报告
+async fn broadcast_write(state: &AppState, client: &mut Client, group_id: Uuid, data: &str, auto_execute: bool) {
+    if auto_execute && risk::assess(data).level > RiskLevel::Low {
+        let message = AccessError::AutoExecuteRisky.to_string();
+        let failures = client.broadcast.members(group_id).into_iter()
+            .map(|session_id| BroadcastFailure { session_id, message: message.clone() })
+            .collect();
+        client.send(&WebSocketResponse::BroadcastFailed { group_id, failures }).await;
+        return;
+    }
+    let mut targets = Vec::new();
+    let mut denied = Vec::new();
+    let mut notices = Vec::new();
+    for session_id in client.broadcast.members(group_id) {
+        let session = state.sessions.get(&session_id).map(|s| s.clone());
+        match session {
+            Some(session) if session.role_of(client.id) == Some(Role::Owner) => {
+                if auto_execute
+                    && !rbac::can_auto_execute(&state.config.auth, client.identity.as_ref(), session.host.as_deref())
+                {
+                    let message = AccessError::AutoExecuteNotAllowed.to_string();
+                    denied.push(BroadcastFailure { session_id, message });
+                } else {
+                    let screened = client.screen_input(state, &session, data, auto_execute, false);
+                    notices.extend(screened.notices);
+                    targets.push((session, screened.data));
+                }
+            }
+            // 会话已结束，顺便清理
+            _ => client.broadcast.remove_session(session_id),
+        }
+    }
+
+    let mut failures = broadcast::fan_out(targets).await;
+    for notice in &notices {
+        client.send(notice).await;
+    }
+    failures.extend(denied);
+    if !failures.is_empty() {
+        client.send(&WebSocketResponse::BroadcastFailed { group_id, failures }).await;
+    }
+}
+
+/// 校验分享令牌并以链接授予的角色加入会话
+async fn join_shared(token: &str, state: &AppState, client: &mut Client) -> Result<(), String> {
+    let claims = state.share.verify(token).map_err(|e| e.to_string())?;
+    let session = state.sessions.get(&claims.session_id).map(|s| s.clone())
+        .ok_or_else(|| "session has ended".to_string())?;
+    let role = session.link_role(claims.link_id)
+        .ok_or_else(|| crate::share::ShareError::Revoked.to_string())?;
+    // 只读账户和未登录的访客即使持有可写链接也只能观看
+    let role = if rbac::check_write(client.identity.as_ref()).is_err() { Role::Viewer } else { role };
+
+    let participants = session.join(client.id, client.tx.clone(), client.user(), role, Some(claims.link_id)).await;
+    audit::record(client.user(), session.host.as_deref(), AuditEvent::ShareLink {
+        session_id: claims.session_id,
+        link_id: claims.link_id,
+        action: format!("joined ({:?}) from {:?}", role, client.remote),
+    });
+
+    client.joined.insert(claims.session_id);
+    client.send(&WebSocketResponse::Attached { session_id: claims.session_id, role, participants }).await;
+    client.share = Some(claims);
+    Ok(())
+}
+
+/// 查找会话并校验当前连接的权限，失败时回复 access_denied
+async fn authorized(
+    state: &AppState,
+    client: &Client,
+    session_id: Uuid,
+    required: Role,
+) -> Option<Arc<SharedSession>> {
+    let session = state.sessions.get(&session_id).map(|s| s.clone());
+    let result = match &session {
+        Some(session) => session.authorize(client.id, required).and_then(|_| match required {
+            Role::Viewer => Ok(()),
+            _ => rbac::check_write(client.identity.as_ref()),
+        }),
+        None => Err(AccessError::UnknownSession),
+    };
+    match result {
+        Ok(()) => session,
+        Err(code) => {
+            client.denied(Some(session_id), code).await;
+            None
         }
     }
 }
+
+async fn set_role(state: &AppState, client: &Client, session_id: Uuid, target: Uuid, role: Role) {
+    let Some(session) = authorized(state, client, session_id, Role::Owner).await else {
+        return;
+    };
+    if !session.set_role(target, role).await {
+        client.error(format!("Cannot change role of {}", target)).await;
+    }
+}
+
+/// 解密凭据库中的 SSH 凭据，目标主机和用户以凭据为准，消息中填写的必须与之一致
+fn vault_credential(
+    state: &AppState,
+    client: &Client,
+    protocol: Protocol,
+    id: Uuid,
+    host: &mut String,
+    port: &mut u16,
+    username: &mut String,
+) -> Result<SSHAuth, String> {
+    let vault = state.vault.as_ref().ok_or_else(|| VaultError::Disabled.to_string())?;
+    if protocol != Protocol::Ssh {
+        return Err("只有 SSH 连接可以使用保存的凭据".to_string());
+    }
+    let kinds = [CredentialKind::Password, CredentialKind::PrivateKey];
+    let (info, secret) = vault.reveal(client.identity.as_ref(), id, &kinds).map_err(|e| e.to_string())?;
+    let (saved_host, saved_user) = (info.host.unwrap_or_default(), info.username.unwrap_or_default());
+    if (!host.is_empty() && !host.eq_ignore_ascii_case(&saved_host))
+        || (*port != 0 && info.port.is_some_and(|p| p != *port))
+        || (!username.is_empty() && *username != saved_user)
+    {
+        return Err(format!("凭据 {} 只能用于 {}@{}", info.name, saved_user, saved_host));
+    }
+    *host = saved_host;
+    *username = saved_user;
+    if *port == 0 {
+        *port = info.port.unwrap_or(0);
+    }
+    Ok(match info.kind {
+        CredentialKind::PrivateKey => SSHAuth::PrivateKey { key: secret.value, passphrase: secret.passphrase },
+        _ => SSHAuth::Password(secret.value),
+    })
+}
+
+/// 当前连接作为 owner 的会话所在SSH连接的主机
+fn owned_connection_host(state: &AppState, client: &Client, connection_id: Uuid) -> Option<Option<String>> {
+    client.joined.iter().find_map(|session_id| {
+        let session = state.sessions.get(session_id)?;
+        (session.backend.connection_id() == Some(connection_id) && session.role_of(client.id) == Some(Role::Owner))
+            .then(|| session.host.clone())
+    })
+}
+
+/// 登记新打开的终端，当前连接成为 owner
+async fn register_session(
+    backend: Arc<dyn TerminalBackend>,
+    rx: mpsc::Receiver<SessionEvent>,
+    host: Option<String>,
+    state: &AppState,
+    client: &mut Client,
+) {
+    let session_id = backend.id();
+    let connection_id = backend.connection_id();
+
+    info!("Terminal session {} opened ({})", session_id, backend.kind());
+    let owner_user = client.user().map(str::to_string);
+    audit::record(client.user(), host.as_deref(), AuditEvent::Connect { session_id, kind: backend.kind().to_string() });
+    let session = SharedSession::register(backend, host, client.id, owner_user, client.tx.clone(), &state.sessions);
+    let resume_token = (state.config.session.resume_grace > 0).then(|| session.resume_token.clone());
+    // 先告知 session_id，再开始转发输出
+    client.send(&WebSocketResponse::Connected { session_id, connection_id, resume_token }).await;
+    session.forward(rx, &state.sessions);
+    client.joined.insert(session_id);
+}
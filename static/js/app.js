@@ -14,6 +14,8 @@ class SSHAITerminalApp {
         this.configManager = null;
         this.uiManager = null;
         this.isInitialized = false;
+        // 通过分享链接打开页面时携带的令牌
+        this.shareToken = new URLSearchParams(window.location.search).get('share');
     }
 
     async init() {
@@ -21,6 +23,12 @@ class SSHAITerminalApp {
             // 显示加载屏幕
             this.showLoadingScreen();
 
+            // 启用登录时未登录会返回401，持有分享链接的访客不需要登录
+            if (!(await this.checkSession()) && !this.shareToken) {
+                window.location.href = '/login.html';
+                return;
+            }
+
             // 初始化工具类
             Utils.init();
 
@@ -62,12 +70,35 @@ class SSHAITerminalApp {
             // 显示主应用
             this.showApp();
 
+            if (this.shareToken) {
+                await this.joinSharedSession();
+            }
+
         } catch (error) {
             console.error('应用初始化失败:', error);
             this.showError('应用初始化失败，请刷新页面重试');
         }
     }
 
+    async joinSharedSession() {
+        try {
+            await this.sshConnection.joinShared(this.shareToken);
+            this.showSuccess('已加入共享会话');
+        } catch (error) {
+            this.showError('加入共享会话失败: ' + error.message);
+        }
+    }
+
+    async checkSession() {
+        try {
+            const response = await fetch('/api/auth/session', { credentials: 'same-origin' });
+            return response.status !== 401;
+        } catch (error) {
+            console.error('检查登录状态失败:', error);
+            return true;
+        }
+    }
+
     bindEvents() {
         // SSH表单提交
         const sshForm = document.getElementById('ssh-form');
@@ -80,6 +111,15 @@ class SSHAITerminalApp {
         const saveConfigBtn = document.getElementById('save-config-btn');
         saveConfigBtn.addEventListener('click', () => this.handleSaveConfig());
 
+        // 生成分享链接
+        const shareCreateBtn = document.getElementById('share-create-btn');
+        shareCreateBtn.addEventListener('click', () => {
+            const role = document.getElementById('share-role').value;
+            const ttl = parseInt(document.getElementById('share-ttl').value);
+            this.sshConnection.createShareLink(role, ttl);
+        });
+        this.sshConnection.onShareUpdate = (created) => this.renderSharePanel(created);
+
         // AI表单提交
         const aiForm = document.getElementById('ai-form');
         aiForm.addEventListener('submit', (e) => {
@@ -134,7 +174,8 @@ class SSHAITerminalApp {
     async handleSSHConnect() {
         const formData = Utils.getFormData('ssh-form');
         
-        if (!formData.host || !formData.username || !formData.password) {
+        // 加载了保存的配置时可以不填密码，由服务端从凭据库取出
+        if (!formData.host || !formData.username || (!formData.password && !formData.credentialId)) {
             this.showError('请填写所有必填字段');
             return;
         }
@@ -175,8 +216,8 @@ class SSHAITerminalApp {
         const formData = Utils.getFormData('ai-form');
 
         try {
-            await this.configManager.saveAIConfig(formData);
-            this.aiChat.updateConfig(formData);
+            const saved = await this.configManager.saveAIConfig(formData);
+            this.aiChat.updateConfig({ apiKey: '', ...saved });
             this.showSuccess('AI设置保存成功');
         } catch (error) {
             this.showError('保存失败: ' + error.message);
@@ -255,7 +296,7 @@ class SSHAITerminalApp {
             // 绑定加载按钮
             const loadBtn = configItem.querySelector('.btn:first-child');
             loadBtn.addEventListener('click', async () => {
-                Utils.setFormData('ssh-form', config);
+                Utils.setFormData('ssh-form', { credentialId: '', ...config });
                 this.showSuccess('配置已加载');
             });
 
@@ -277,6 +318,74 @@ class SSHAITerminalApp {
         });
     }
 
+    // 会话 owner 的分享面板：生成的链接、有效链接及其观看人数、参与者
+    renderSharePanel(created) {
+        const panel = document.getElementById('share-panel');
+        const ssh = this.sshConnection;
+        const isOwner = ssh.isConnected && ssh.role === 'owner';
+        panel.style.display = isOwner ? '' : 'none';
+        if (!isOwner) {
+            return;
+        }
+
+        const urlInput = document.getElementById('share-url');
+        if (created) {
+            urlInput.value = created.url;
+            urlInput.style.display = '';
+            urlInput.select();
+            if (navigator.clipboard) {
+                navigator.clipboard.writeText(created.url)
+                    .then(() => this.showSuccess('分享链接已复制'))
+                    .catch(() => this.showSuccess('分享链接已生成'));
+            } else {
+                this.showSuccess('分享链接已生成');
+            }
+        }
+
+        const roles = { owner: '所有者', writer: '可输入', viewer: '只读' };
+        const linkList = document.getElementById('share-links');
+        linkList.innerHTML = '';
+        ssh.shareLinks.forEach(link => {
+            const item = document.createElement('div');
+            item.className = 'config-item';
+            const expires = new Date(link.expires_at).toLocaleTimeString();
+            item.innerHTML = `
+                <span class="config-name">${roles[link.role] || link.role} · ${link.viewers} 人 · ${Utils.escapeHtml(expires)} 到期</span>
+                <div class="config-actions">
+                    <button class="btn btn-secondary">撤销</button>
+                </div>
+            `;
+            item.querySelector('.btn').addEventListener('click', () => {
+                if (confirm('撤销后通过该链接加入的参与者都会被移出，确定吗？')) {
+                    ssh.revokeShareLink(link.link_id);
+                }
+            });
+            linkList.appendChild(item);
+        });
+
+        const participantList = document.getElementById('share-participants');
+        participantList.innerHTML = '';
+        ssh.participants.forEach((role, clientId) => {
+            const item = document.createElement('div');
+            item.className = 'config-item';
+            const canWrite = role === 'writer';
+            item.innerHTML = `
+                <span class="config-name">${Utils.escapeHtml(clientId.slice(0, 8))} · ${roles[role] || role}</span>
+                <div class="config-actions">
+                    <button class="btn btn-secondary">${canWrite ? '收回输入' : '允许输入'}</button>
+                    <button class="btn btn-secondary">移出</button>
+                </div>
+            `;
+            item.querySelector('.btn:first-child').addEventListener('click', () => {
+                ssh.setParticipantControl(clientId, !canWrite);
+            });
+            item.querySelector('.btn:last-child').addEventListener('click', () => {
+                ssh.kickParticipant(clientId);
+            });
+            participantList.appendChild(item);
+        });
+    }
+
     showLoadingScreen() {
         const loadingScreen = document.getElementById('loading-screen');
         loadingScreen.style.display = 'flex';
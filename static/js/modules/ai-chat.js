@@ -11,6 +11,7 @@ export class AIChat {
             endpoint: 'https://api.openai.com/v1/chat/completions'
         };
         this.autoExecute = false;
+        this.autoExecuteAllowed = false;
         this.terminalHistory = [];
         this.maxHistoryLength = 100;
         this.commandQueue = [];
@@ -91,7 +92,7 @@ export class AIChat {
     }
 
     async processMessage(message) {
-        if (!this.config.apiKey) {
+        if (!this.config.apiKey && !this.config.credentialId) {
             this.addMessage('请先在AI设置中配置API Key', 'ai');
             return;
         }
@@ -139,6 +140,7 @@ ${context}`;
                 ai_config: {
                     provider: this.config.provider || 'openai',
                     apiKey: this.config.apiKey,
+                    credentialId: this.config.credentialId,
                 model: this.config.model || 'gpt-3.5-turbo',
                     temperature: parseFloat(this.config.temperature) || 0.7,
                     maxTokens: parseInt(this.config.maxTokens) || 2048,
@@ -153,6 +155,14 @@ ${context}`;
         }
 
         const data = await response.json();
+        // 服务器按账户权限决定是否允许自动执行
+        this.autoExecuteAllowed = data.auto_execute === true;
+        // 建议的命令命中服务端命令策略时的判定
+        this.policyVerdict = data.policy || null;
+        // 服务端对建议命令的风险评估
+        this.riskAssessment = data.risk || null;
+        // 发送给AI之前被服务端遮盖的内容
+        this.redactions = data.redactions || [];
         return data.response;
     }
 
@@ -165,20 +175,46 @@ ${context}`;
     }
 
     handleAIResponse(responseText) {
+        if (this.redactions && this.redactions.length > 0) {
+            const masked = this.redactions.map(r => `${r.kind} × ${r.count}`).join(', ');
+            this.addSystemMessage(`已在发送给AI之前遮盖敏感内容: ${masked}`);
+        }
+
         try {
             const response = JSON.parse(responseText);
             
-            if (response.type === 'command' && response.command) {
+            if (response.type === 'command' && response.command && this.policyVerdict?.action === 'deny') {
+                this.addMessage(
+                    `建议执行命令: \`${response.command}\`\n${response.description || ''}`,
+                    'ai'
+                );
+                this.addSystemMessage(`命令已被服务端策略禁止: ${this.policyVerdict.message}`);
+            } else if (response.type === 'command' && response.command) {
                 // 处理命令响应
                 this.addMessage(
                     `建议执行命令: \`${response.command}\`\n${response.description || ''}`, 
                     'ai'
                 );
+                const risk = this.riskAssessment;
+                const risky = risk && risk.level !== 'low';
+                if (risky) {
+                    const reasons = risk.reasons.map(r => `- [${r.level}] ${r.reason}`).join('\n');
+                    this.addSystemMessage(`风险等级: ${risk.level}\n${reasons}`);
+                }
                 
-                if (this.autoExecute) {
-                    this.executeCommand(response.command);
+                if (this.autoExecute && this.autoExecuteAllowed && !risky) {
+                    this.executeCommand(response.command, true);
                     this.addSystemMessage(`正在自动执行命令: ${response.command}`);
                 } else {
+                    if (this.policyVerdict?.action === 'confirm') {
+                        this.addSystemMessage(`${this.policyVerdict.message}，执行时需要再次确认`);
+                    } else if (this.policyVerdict?.action === 'approve') {
+                        this.addSystemMessage(`${this.policyVerdict.message}，执行时需要另一位账户审批`);
+                    } else if (this.autoExecute && risky) {
+                        this.addSystemMessage('风险等级高于 low 的命令不会自动执行，请确认后手动执行');
+                    } else if (this.autoExecute) {
+                        this.addSystemMessage('当前账户不允许在该主机上自动执行命令');
+                    }
                     // 添加执行按钮
                     this.addCommandMessage(response.command);
                 }
@@ -195,7 +231,7 @@ ${context}`;
         }
     }
 
-    executeCommand(command) {
+    executeCommand(command, autoExecute = false) {
         if (!this.sshConnection.isConnected) {
             this.addSystemMessage('错误: SSH未连接');
             return;
@@ -209,7 +245,7 @@ ${context}`;
         });
 
         // 执行命令
-        const success = this.sshConnection.sendCommand(command);
+        const success = this.sshConnection.sendCommand(command, autoExecute);
         if (success) {
             this.addToHistory(`$ ${command}\n`);
         }
@@ -2,6 +2,9 @@
 export class ConfigManager {
     constructor() {
         this.apiEndpoint = '/api_configs.json';
+        this.vaultEndpoint = '/api/credentials';
+        // 服务端启用凭据库时密码和 API Key 不再保存在浏览器中
+        this.vaultEnabled = false;
         this.configs = {
             ssh_configs: [],
             ai_config: null
@@ -59,9 +62,89 @@ export class ConfigManager {
         }
     }
 
+    // 凭据库管理，未启用时返回 null
+    async listCredentials() {
+        const response = await fetch(this.vaultEndpoint, { credentials: 'same-origin' });
+        if (response.status === 404) {
+            return null;
+        }
+        if (!response.ok) {
+            throw new Error(`HTTP error! status: ${response.status}`);
+        }
+        return response.json();
+    }
+
+    async createCredential(credential) {
+        const response = await fetch(this.vaultEndpoint, {
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json'
+            },
+            credentials: 'same-origin',
+            body: JSON.stringify(credential)
+        });
+        const result = await response.json().catch(() => ({}));
+        if (!response.ok) {
+            throw new Error(result.error || `HTTP error! status: ${response.status}`);
+        }
+        return result;
+    }
+
+    async deleteCredential(id) {
+        const response = await fetch(`${this.vaultEndpoint}/${encodeURIComponent(id)}`, {
+            method: 'DELETE',
+            credentials: 'same-origin'
+        });
+        if (!response.ok && response.status !== 404) {
+            throw new Error(`HTTP error! status: ${response.status}`);
+        }
+    }
+
+    // 把旧版本保存在localStorage中的密码移入凭据库
+    async migrateLocalConfigs() {
+        const saved = JSON.parse(localStorage.getItem('sshConfigs') || '[]');
+        for (const config of saved.filter(c => c.password)) {
+            await this.createCredential({
+                name: config.name,
+                kind: 'password',
+                host: config.host,
+                port: parseInt(config.port) || 22,
+                username: config.username,
+                secret: config.password
+            });
+        }
+        localStorage.removeItem('sshConfigs');
+    }
+
     // SSH配置管理
     async getSSHConfigs() {
-        // 优先从localStorage读取
+        // 启用凭据库时从服务端读取，列表中不含密码
+        try {
+            const credentials = await this.listCredentials();
+            this.vaultEnabled = credentials !== null;
+            if (this.vaultEnabled) {
+                if (localStorage.getItem('sshConfigs')) {
+                    await this.migrateLocalConfigs();
+                    return this.getSSHConfigs();
+                }
+                const configs = credentials
+                    .filter(c => c.kind !== 'ai_api_key')
+                    .map(c => ({
+                        name: c.name,
+                        host: c.host,
+                        port: c.port || 22,
+                        username: c.username,
+                        password: '',
+                        credentialId: c.id
+                    }));
+                this.configs.ssh_configs = configs;
+                return configs;
+            }
+        } catch (error) {
+            console.warn('从凭据库读取SSH配置失败:', error);
+        }
+
+        // 未启用凭据库时从localStorage读取
         try {
             const savedConfigs = localStorage.getItem('sshConfigs');
             if (savedConfigs) {
@@ -86,6 +169,25 @@ export class ConfigManager {
                 throw new Error('配置信息不完整');
             }
 
+            if (this.vaultEnabled) {
+                if (!config.password) {
+                    throw new Error('请填写密码');
+                }
+                await this.createCredential({
+                    name: config.name,
+                    kind: 'password',
+                    host: config.host,
+                    port: parseInt(config.port) || 22,
+                    username: config.username,
+                    secret: config.password
+                });
+                console.log('SSH配置已加密保存到凭据库:', config.name);
+                return true;
+            }
+
+            // 本地只保存主机信息，不保存密码
+            const { password, credentialId, ...stored } = config;
+
             // 获取现有配置
             await this.loadConfigs();
             if (!this.configs.ssh_configs) {
@@ -96,20 +198,20 @@ export class ConfigManager {
             const existingIndex = this.configs.ssh_configs.findIndex(c => c.name === config.name);
             if (existingIndex >= 0) {
                 // 更新现有配置
-                this.configs.ssh_configs[existingIndex] = config;
+                this.configs.ssh_configs[existingIndex] = stored;
             } else {
                 // 添加新配置
-                this.configs.ssh_configs.push(config);
+                this.configs.ssh_configs.push(stored);
             }
 
             // 保存到localStorage
             localStorage.setItem('sshConfigs', JSON.stringify(this.configs.ssh_configs));
             
-            console.log('SSH配置已保存到localStorage:', config);
+            console.log('SSH配置已保存到localStorage:', stored);
             return true;
         } catch (error) {
             console.error('保存SSH配置失败:', error);
-            throw new Error('保存SSH配置到本地存储失败');
+            throw error;
         }
     }
 
@@ -122,6 +224,13 @@ export class ConfigManager {
                 throw new Error('无效的配置索引');
             }
 
+            const credentialId = this.configs.ssh_configs[index].credentialId;
+            if (credentialId) {
+                await this.deleteCredential(credentialId);
+                console.log('凭据已删除:', credentialId);
+                return true;
+            }
+
             // 从数组中删除配置
             this.configs.ssh_configs.splice(index, 1);
             
@@ -143,7 +252,12 @@ export class ConfigManager {
             const savedConfig = localStorage.getItem('aiConfig');
             if (savedConfig) {
                 const config = JSON.parse(savedConfig);
-                console.log('从localStorage加载AI配置:', config);
+                console.log('从localStorage加载AI配置:', config.provider, config.model);
+                this.configs.ai_config = config;
+                // 旧版本保存在本地的 API Key 移入凭据库
+                if (this.vaultEnabled && config.apiKey) {
+                    return this.saveAIConfig(config);
+                }
                 return config;
             }
         } catch (error) {
@@ -164,17 +278,42 @@ export class ConfigManager {
 
     async saveAIConfig(config) {
         try {
-            // 直接保存到localStorage（客户端存储）
-            localStorage.setItem('aiConfig', JSON.stringify(config));
+            const stored = { ...config };
+            if (this.vaultEnabled) {
+                // API Key 加密保存到凭据库，本地只记住凭据 id
+                const previous = this.configs.ai_config && this.configs.ai_config.credentialId;
+                const previousEndpoint = this.configs.ai_config && this.configs.ai_config.endpoint;
+                if (config.apiKey) {
+                    // 服务端只会把 API Key 发往保存时的接口地址
+                    const credential = await this.createCredential({
+                        name: `${config.provider || 'ai'} API Key`,
+                        kind: 'ai_api_key',
+                        endpoint: config.endpoint,
+                        secret: config.apiKey
+                    });
+                    stored.credentialId = credential.id;
+                    if (previous) {
+                        await this.deleteCredential(previous).catch(error => console.warn('删除旧的API Key失败:', error));
+                    }
+                } else if (previous) {
+                    if (config.endpoint !== previousEndpoint) {
+                        throw new Error('更换接口地址后需要重新填写 API Key');
+                    }
+                    stored.credentialId = previous;
+                }
+                delete stored.apiKey;
+            }
+
+            localStorage.setItem('aiConfig', JSON.stringify(stored));
 
             // 更新本地缓存
-            this.configs.ai_config = config;
+            this.configs.ai_config = stored;
             
-            console.log('AI配置已保存到localStorage:', config);
-            return true;
+            console.log('AI配置已保存:', stored.provider, stored.model);
+            return stored;
         } catch (error) {
             console.error('保存AI配置失败:', error);
-            throw new Error('保存AI配置到本地存储失败');
+            throw new Error(error.message || '保存AI配置到本地存储失败');
         }
     }
 
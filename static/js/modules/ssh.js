@@ -13,6 +13,14 @@ export class SSHConnection {
         this.terminalDataHandler = null;
         this.resizeHandler = null;
         this.connectionTimeout = null;
+        // 本连接的 client_id 和在当前会话中的角色
+        this.clientId = null;
+        this.role = null;
+        // 会话的其他参与者 (client_id -> role) 和 owner 创建的分享链接
+        this.participants = new Map();
+        this.shareLinks = [];
+        // 参与者或分享链接变化时通知界面
+        this.onShareUpdate = null;
     }
 
     async init() {
@@ -20,6 +28,57 @@ export class SSHConnection {
         console.log('SSH连接模块初始化');
     }
 
+    // 打开 /ws 前取得一次性令牌，未启用登录时服务端不返回令牌
+    // 通过分享链接接入时附带 share 令牌，未登录的访客没有一次性令牌
+    async webSocketUrl(share = null) {
+        const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
+        const params = new URLSearchParams();
+        if (share) {
+            params.set('share', share);
+        }
+        const response = await fetch('/api/auth/ws-token', { method: 'POST', credentials: 'same-origin' });
+        if (response.ok) {
+            const { token } = await response.json();
+            if (token) {
+                params.set('csrf', token);
+            }
+        } else if (!share) {
+            throw new Error(`获取连接令牌失败 (${response.status})`);
+        }
+        const query = params.toString();
+        return `${protocol}//${window.location.host}/ws${query ? `?${query}` : ''}`;
+    }
+
+    // 通过分享链接加入他人的会话，服务端按链接授予 viewer 或 writer 角色
+    async joinShared(token) {
+        if (this.isConnected) {
+            throw new Error('已经连接到服务器');
+        }
+        this.updateConnectionStatus('connecting');
+        this.terminal.writeln('\r\n*** 正在通过分享链接加入会话... ***\r\n');
+
+        const wsUrl = await this.webSocketUrl(token);
+        return new Promise((resolve, reject) => {
+            this.ws = new WebSocket(wsUrl);
+            this.ws.onmessage = (event) => {
+                try {
+                    this.handleMessage(JSON.parse(event.data), resolve, reject);
+                } catch (error) {
+                    console.error('处理消息失败:', error);
+                }
+            };
+            this.ws.onerror = () => {
+                this.terminal.writeln('\r\n*** 分享链接无效、已过期或已被撤销 ***\r\n');
+                this.updateConnectionStatus('error');
+                reject(new Error('分享链接无效、已过期或已被撤销'));
+            };
+            this.ws.onclose = (event) => {
+                console.log('WebSocket连接已关闭, 代码:', event.code);
+                this.handleClose(event);
+            };
+        });
+    }
+
     async connect(config) {
         if (this.isConnected) {
             throw new Error('已经连接到服务器');
@@ -32,12 +91,17 @@ export class SSHConnection {
         this.terminal.writeln('\r\n*** 正在连接到 SSH 服务器... ***');
         this.terminal.writeln(`*** 目标: ${config.username}@${config.host}:${config.port} ***\r\n`);
 
+        let wsUrl;
+        try {
+            wsUrl = await this.webSocketUrl();
+        } catch (error) {
+            this.updateConnectionStatus('disconnected');
+            this.terminal.writeln(`\r\n*** 连接初始化失败: ${error.message} ***\r\n`);
+            throw error;
+        }
+
         return new Promise((resolve, reject) => {
             try {
-                // 构建WebSocket URL
-                const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
-                const wsUrl = `${protocol}//${window.location.host}/ws`;
-                
                 this.ws = new WebSocket(wsUrl);
                 
                 // 设置连接超时
@@ -59,7 +123,8 @@ export class SSHConnection {
                         host: config.host,
                         port: parseInt(config.port) || 22,
                         username: config.username,
-                        password: config.password
+                        // 没有填写密码时使用凭据库中保存的凭据
+                        ...(config.password ? { password: config.password } : { credential_id: config.credentialId })
                     }));
                     
                     this.terminal.writeln('*** 正在进行SSH认证... ***');
@@ -109,6 +174,62 @@ export class SSHConnection {
 
     handleMessage(data, resolve, reject) {
         switch (data.type) {
+            case 'hello':
+                this.clientId = data.client_id;
+                break;
+
+            case 'attached':
+                // 通过分享链接加入，只读参与者不转发键盘输入
+                this.sessionId = data.session_id;
+                this.isConnected = true;
+                this.role = data.role;
+                this.participants = new Map(
+                    data.participants.filter(p => p.client_id !== this.clientId).map(p => [p.client_id, p.role])
+                );
+                this.updateConnectionStatus('connected');
+                this.setupTerminalHandlers();
+                this.startPingInterval();
+                this.terminal.writeln(`*** 已加入共享会话 (${this.role === 'viewer' ? '只读' : '可输入'}) ***\r\n`);
+                this.notifyShareUpdate();
+                if (resolve) {
+                    resolve();
+                }
+                break;
+
+            case 'participant_joined':
+                this.participants.set(data.client_id, data.role);
+                this.notifyShareUpdate();
+                break;
+
+            case 'participant_left':
+                this.participants.delete(data.client_id);
+                this.notifyShareUpdate();
+                break;
+
+            case 'role_changed':
+                if (data.client_id === this.clientId) {
+                    this.role = data.role;
+                    this.terminal.writeln(`\r\n*** 你的角色已变为 ${data.role} ***\r\n`);
+                } else {
+                    this.participants.set(data.client_id, data.role);
+                }
+                this.notifyShareUpdate();
+                break;
+
+            case 'share_link_created':
+                this.shareLinks = this.shareLinks.filter(l => l.link_id !== data.link.link_id).concat([data.link]);
+                this.notifyShareUpdate({ url: new URL(data.url, window.location.origin).href, link: data.link });
+                break;
+
+            case 'share_links':
+                this.shareLinks = data.links;
+                this.notifyShareUpdate();
+                break;
+
+            case 'access_denied':
+                this.terminal.writeln(`\r\n*** ${data.message} ***\r\n`);
+                break;
+
             case 'connected':
                 if (this.connectionTimeout) {
                     clearTimeout(this.connectionTimeout);
@@ -116,6 +237,9 @@ export class SSHConnection {
                 }
                 this.sessionId = data.session_id;
                 this.isConnected = true;
+                this.role = 'owner';
+                this.participants = new Map();
+                this.shareLinks = [];
                 this.reconnectAttempts = 0;
                 this.updateConnectionStatus('connected');
                 this.setupTerminalHandlers();
@@ -123,6 +247,7 @@ export class SSHConnection {
                 
                 this.terminal.writeln('\r\n*** SSH连接成功建立! ***');
                 this.terminal.writeln('*** 终端已准备就绪 ***\r\n');
+                this.notifyShareUpdate();
                 
                 if (resolve) {
                     resolve();
@@ -167,12 +292,74 @@ export class SSHConnection {
 
             case 'disconnected':
                 this.handleDisconnect();
+                this.notifyShareUpdate();
                 break;
 
             case 'pong':
                 // 心跳响应
                 break;
 
+            case 'limit_exceeded': {
+                if (this.connectionTimeout) {
+                    clearTimeout(this.connectionTimeout);
+                    this.connectionTimeout = null;
+                }
+                const retry = data.retry_after ? ` (${data.retry_after} 秒后可重试)` : '';
+                this.terminal.writeln(`\r\n*** ${data.message}${retry} ***\r\n`);
+                if (!this.isConnected) {
+                    this.updateConnectionStatus('disconnected');
+                    if (reject) {
+                        reject(new Error(data.message));
+                    }
+                }
+                break;
+            }
+
+            case 'command_blocked':
+                this.terminal.writeln(`\r\n*** 命令被拦截: ${data.command} ***`);
+                this.terminal.writeln(`*** ${data.message} ***\r\n`);
+                break;
+
+            case 'command_confirm': {
+                // 服务端暂扣了回车，确认后才会执行
+                const approve = window.confirm(`${data.message}\n\n${data.command}\n\n确定执行吗？`);
+                if (this.ws && this.ws.readyState === WebSocket.OPEN) {
+                    this.ws.send(JSON.stringify({
+                        type: 'confirm_command',
+                        session_id: data.session_id,
+                        confirm_id: data.confirm_id,
+                        approve: approve
+                    }));
+                }
+                break;
+            }
+
+            case 'approval_pending':
+                this.terminal.writeln(`\r\n*** 命令等待审批: ${data.command} ***`);
+                this.terminal.writeln(`*** ${data.message} (已通知 ${data.approvers} 位审批人，${new Date(data.expires_at).toLocaleTimeString()} 前有效) ***\r\n`);
+                break;
+
+            case 'approval_request': {
+                // 其他账户的命令需要当前账户审批
+                const where = data.host || '本地终端';
+                const approve = window.confirm(`${data.requester} 请求在 ${where} 上执行:\n\n${data.command}\n\n${data.message}\n\n批准执行吗？`);
+                if (this.ws && this.ws.readyState === WebSocket.OPEN) {
+                    this.ws.send(JSON.stringify({
+                        type: 'approve_command',
+                        approval_id: data.approval_id,
+                        approve: approve
+                    }));
+                }
+                break;
+            }
+
+            case 'approval_resolved': {
+                const outcomes = { approved: '已批准', rejected: '已拒绝', expired: '已超时', cancelled: '已取消' };
+                const by = data.approver ? ` (${data.approver})` : '';
+                this.terminal.writeln(`\r\n*** 审批${outcomes[data.outcome] || data.outcome}${by}: ${data.command} ***\r\n`);
+                break;
+            }
+
             default:
                 console.warn('未知消息类型:', data.type);
         }
@@ -181,6 +368,9 @@ export class SSHConnection {
     setupTerminalHandlers() {
         // 处理终端输入
         this.terminalDataHandler = this.terminal.onData((data) => {
+            if (this.role === 'viewer') {
+                return;
+            }
             if (this.ws && this.ws.readyState === WebSocket.OPEN && this.sessionId) {
                 this.ws.send(JSON.stringify({
                     type: 'data',
@@ -203,6 +393,39 @@ export class SSHConnection {
         });
     }
 
+    notifyShareUpdate(created = null) {
+        if (this.onShareUpdate) {
+            this.onShareUpdate(created);
+        }
+    }
+
+    send(message) {
+        if (this.ws && this.ws.readyState === WebSocket.OPEN && this.sessionId) {
+            this.ws.send(JSON.stringify({ session_id: this.sessionId, ...message }));
+        }
+    }
+
+    // 以下操作只有会话的 owner 可以执行
+    createShareLink(role, ttl) {
+        this.send({ type: 'create_share_link', role, ttl });
+    }
+
+    listShareLinks() {
+        this.send({ type: 'list_share_links' });
+    }
+
+    revokeShareLink(linkId) {
+        this.send({ type: 'revoke_share_link', link_id: linkId });
+    }
+
+    setParticipantControl(clientId, grant) {
+        this.send({ type: grant ? 'grant_control' : 'revoke_control', client_id: clientId });
+    }
+
+    kickParticipant(clientId) {
+        this.send({ type: 'kick', client_id: clientId });
+    }
+
     startPingInterval() {
         // 定期发送心跳包保持连接
         this.pingInterval = setInterval(() => {
@@ -252,6 +475,10 @@ export class SSHConnection {
 
         this.ws = null;
         this.sessionId = null;
+        this.role = null;
+        this.participants = new Map();
+        this.shareLinks = [];
+        this.notifyShareUpdate();
         this.updateConnectionStatus('disconnected');
         this.terminal.writeln('\r\n*** 连接已断开 ***\r\n');
     }
@@ -286,6 +513,9 @@ export class SSHConnection {
     handleDisconnect() {
         this.isConnected = false;
         this.sessionId = null;
+        this.role = null;
+        this.participants = new Map();
+        this.shareLinks = [];
         this.updateConnectionStatus('disconnected');
         this.terminal.writeln('\r\n*** SSH连接已断开 ***\r\n');
     }
@@ -356,12 +586,13 @@ export class SSHConnection {
         }
     }
 
-    sendCommand(command) {
+    sendCommand(command, autoExecute = false) {
         if (this.ws && this.ws.readyState === WebSocket.OPEN && this.sessionId) {
             this.ws.send(JSON.stringify({
                 type: 'data',
                 session_id: this.sessionId,
-                data: command + '\n'
+                data: command + '\n',
+                auto_execute: autoExecute
             }));
             return true;
         }
@@ -399,9 +630,13 @@ export class SSHConnection {
     }
     
     async testWebSocketConnection() {
+        let wsUrl;
+        try {
+            wsUrl = await this.webSocketUrl();
+        } catch (error) {
+            return { success: false, error: error.message };
+        }
         return new Promise((resolve) => {
-            const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
-            const wsUrl = `${protocol}//${window.location.host}/ws`;
             const testWs = new WebSocket(wsUrl);
             
             const timeout = setTimeout(() => {